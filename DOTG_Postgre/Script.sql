@@ -30,12 +30,39 @@ receiver varchar(12),
 foreign key (sender) references users(username),
 foreign key (receiver) references users(username)
 )
+create table ratings (
+username varchar(12) primary key,
+rating double precision not null default 1500,
+games_played integer not null default 0,
+foreign key (username) references users(username)
+)
+create table messages (
+id serial primary key,
+sender varchar(12) not null,
+receiver varchar(12) not null,
+content varchar(500) not null,
+sent_at bigint not null,
+is_read bool not null default false,
+foreign key (sender) references users(username),
+foreign key (receiver) references users(username)
+)
+create index messages_conversation_idx on messages (sender, receiver, id)
+create table BlockedUsers (
+blocker varchar(12),
+blocked varchar(12),
+primary key (blocker, blocked),
+foreign key (blocker) references users(username),
+foreign key (blocked) references users(username)
+)
 delete from users
 delete from friends
 
 select * from users
 select * from FriendRequests
 select * from friends
+select * from ratings
+select * from messages
+select * from BlockedUsers
 update users set status = false where username = 'haha'
 update users set status = false
 delete from FriendRequests where sender = 'haha' and receiver = 'keke' 
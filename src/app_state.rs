@@ -1,9 +1,14 @@
 use redis::aio::MultiplexedConnection;
 use sqlx::PgPool;
-use tokio::sync::RwLock;
+use tokio::sync::{Mutex, RwLock};
 
 use std::{collections::HashMap, process::Child, sync::Arc};
 
+use crate::{
+    game_server_launcher::GameServerLauncher, game_server_logs::GameServerLogs,
+    game_server_ports::GameServerPortPool,
+};
+
 pub type ClientSender = tokio::sync::mpsc::UnboundedSender<String>;
 
 //Map to store a mpsc Sender of the coresponding user
@@ -11,12 +16,19 @@ pub type ClientsMap = Arc<RwLock<HashMap<String, ClientSender>>>;
 
 pub type GameServerExeMap = Arc<RwLock<HashMap<String, Child>>>;
 
+pub type SharedGameServerLauncher = Arc<dyn GameServerLauncher>;
+
+pub type SharedGameServerPortPool = Arc<Mutex<GameServerPortPool>>;
+
 //AppState that contains Connection Pool and Clients Map for Web Socket
 #[derive(Clone)]
 pub struct AppState {
     pub connection_pool: PgPool,
     pub clients_map: ClientsMap,
     pub game_server_exe_map: GameServerExeMap,
+    pub game_server_launcher: SharedGameServerLauncher,
+    pub game_server_logs: GameServerLogs,
+    pub game_server_ports: SharedGameServerPortPool,
     pub redis_conn: MultiplexedConnection,
 }
 
@@ -1,4 +1,4 @@
-use crate::global_vars::SECRET_KEY;
+use crate::global_vars::{HOST_AGENT_SECRET, SECRET_KEY};
 use axum::{
     RequestPartsExt,
     extract::FromRequestParts,
@@ -10,8 +10,9 @@ use axum_extra::{
     headers::authorization::{Authorization, Bearer},
 };
 use dotenvy::dotenv;
-use jsonwebtoken::{DecodingKey, Validation, decode};
-use serde::{Deserialize, Serialize};
+use jsonwebtoken::{DecodingKey, EncodingKey, Header, Validation, decode, encode};
+use rand::{Rng, distr::Alphanumeric};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Claims {
@@ -19,6 +20,21 @@ pub struct Claims {
     pub exp: usize,
 }
 
+//Claims of a signed lobby invite link, kept apart from Claims so it can't be used as a login token
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LobbyInviteClaims {
+    pub lobby_code: String,
+    pub exp: usize,
+}
+
+//Claims of a message between the backend and a host agent, signed with HOST_AGENT_SECRET
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HostAgentClaims<T> {
+    pub host_id: String,
+    pub message: T,
+    pub exp: usize,
+}
+
 pub struct AuthUser {
     pub username: String,
 }
@@ -29,7 +45,7 @@ where
 {
     type Rejection = AuthError;
 
-    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
         let TypedHeader(Authorization(bearer)) = parts
             .extract::<TypedHeader<Authorization<Bearer>>>()
             .await
@@ -71,3 +87,42 @@ pub fn get_jwt_secret() -> &'static [u8] {
     });
     return secret_key.as_bytes();
 }
+
+//Random uppercase alphanumeric code, used for lobby join codes
+pub fn generate_random_code(length: usize) -> String {
+    return rand::rng()
+        .sample_iter(&Alphanumeric)
+        .take(length)
+        .map(|character| char::from(character).to_ascii_uppercase())
+        .collect();
+}
+
+//Messages expire quickly, they are only meant to cross the pub/sub channel
+pub fn sign_host_agent_message<T: Serialize>(host_id: &str, message: T) -> Option<String> {
+    let expiration = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .ok()?
+        .as_secs() as usize
+        + 60;
+    let claims = HostAgentClaims {
+        host_id: host_id.to_string(),
+        message,
+        exp: expiration,
+    };
+    return encode(
+        &Header::default(),
+        &claims,
+        &EncodingKey::from_secret(HOST_AGENT_SECRET.as_ref()?.as_bytes()),
+    )
+    .ok();
+}
+
+pub fn verify_host_agent_message<T: DeserializeOwned>(token: &str) -> Option<HostAgentClaims<T>> {
+    return decode::<HostAgentClaims<T>>(
+        token,
+        &DecodingKey::from_secret(HOST_AGENT_SECRET.as_ref()?.as_bytes()),
+        &Validation::default(),
+    )
+    .ok()
+    .map(|token_data| token_data.claims);
+}
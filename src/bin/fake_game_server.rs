@@ -0,0 +1,230 @@
+//Stand-in for the Unreal dedicated server, started by the mock launcher.
+//It takes the same arguments, binds the match port, prints heartbeats and
+//reports the end of the match to the backend like the real server does.
+use std::{env, time::Duration};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpStream, UdpSocket},
+    time::{Instant, interval, sleep},
+};
+
+const HEARTBEAT_INTERVAL_SECS: u64 = 5;
+
+struct FakeServerArgs {
+    level: String,
+    port: u16,
+    game_mode: String,
+    server_id: String,
+    ready_url: Option<String>,
+    heartbeat_url: Option<String>,
+    ready_token: String,
+    startup_secs: u64,
+    match_secs: u64,
+    //Stops sending heartbeats after this many seconds, to try the backend watchdog
+    hang_after_secs: Option<u64>,
+}
+
+fn parse_args() -> Option<FakeServerArgs> {
+    let mut args = env::args().skip(1);
+    //First argument is the travel url: {level}?port={port}?game_mode={game_mode}
+    let travel_url = args.next()?;
+    let mut url_parts = travel_url.split('?');
+    let level = url_parts.next()?.to_string();
+    let mut port = None;
+    let mut game_mode = String::new();
+    for option in url_parts {
+        if let Some(value) = option.strip_prefix("port=") {
+            port = value.parse::<u16>().ok();
+        } else if let Some(value) = option.strip_prefix("game_mode=") {
+            game_mode = value.to_string();
+        }
+    }
+
+    let mut server_id = String::new();
+    let mut ready_url = None;
+    let mut heartbeat_url = None;
+    let mut ready_token = String::new();
+    let mut startup_secs = 2;
+    let mut match_secs = 60;
+    let mut hang_after_secs = None;
+    for arg in args {
+        if let Some(value) = arg.strip_prefix("-server_id=") {
+            server_id = value.to_string();
+        } else if let Some(value) = arg.strip_prefix("-ready_url=") {
+            ready_url = Some(value.to_string());
+        } else if let Some(value) = arg.strip_prefix("-heartbeat_url=") {
+            heartbeat_url = Some(value.to_string());
+        } else if let Some(value) = arg.strip_prefix("-ready_token=") {
+            ready_token = value.to_string();
+        } else if let Some(value) = arg.strip_prefix("-startup_secs=") {
+            startup_secs = value.parse().unwrap_or(startup_secs);
+        } else if let Some(value) = arg.strip_prefix("-match_secs=") {
+            match_secs = value.parse().unwrap_or(match_secs);
+        } else if let Some(value) = arg.strip_prefix("-hang_after_secs=") {
+            hang_after_secs = value.parse().ok();
+        }
+    }
+
+    return Some(FakeServerArgs {
+        level,
+        port: port?,
+        game_mode,
+        server_id,
+        ready_url,
+        heartbeat_url,
+        ready_token,
+        startup_secs,
+        match_secs,
+        hang_after_secs,
+    });
+}
+
+fn api_url() -> String {
+    return env::var("PUBLIC_API_URL").unwrap_or_else(|_| "http://127.0.0.1:3000".to_string());
+}
+
+//Plain HTTP/1.1 POST so the fake server doesn't need an http client dependency, returns the json body if any
+async fn post_to_backend(url: &str) -> std::io::Result<serde_json::Value> {
+    let url = url.trim_start_matches("http://");
+    let (host, path) = match url.find('/') {
+        Some(path_start) => url.split_at(path_start),
+        None => (url, "/"),
+    };
+    let mut stream = TcpStream::connect(host).await?;
+    let request = format!(
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        path, host
+    );
+    stream.write_all(request.as_bytes()).await?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await?;
+    println!(
+        "Backend answered: {}",
+        response.lines().next().unwrap_or("")
+    );
+    let body = response
+        .split_once("\r\n\r\n")
+        .map(|(_, body)| body)
+        .unwrap_or("");
+    return Ok(serde_json::from_str(body).unwrap_or(serde_json::Value::Null));
+}
+
+//Returns true when the backend keeps this server idle in its warm pool
+async fn report_ready(server_args: &FakeServerArgs) -> bool {
+    println!("ready");
+    let Some(ready_url) = &server_args.ready_url else {
+        return false;
+    };
+    let ready_url = format!("{}&token={}", ready_url, server_args.ready_token);
+    match post_to_backend(&ready_url).await {
+        Ok(response) => return response["status"] == "idle",
+        Err(err) => {
+            eprintln!("Failed to report ready: {}", err);
+            return false;
+        }
+    }
+}
+
+//Returns the match assignment of a warm server, if the backend sent one
+async fn send_heartbeat(
+    server_args: &FakeServerArgs,
+    process_start: Instant,
+    phase: &str,
+) -> Option<serde_json::Value> {
+    if server_args
+        .hang_after_secs
+        .is_some_and(|hang_after_secs| process_start.elapsed().as_secs() >= hang_after_secs)
+    {
+        return None;
+    }
+    println!("heartbeat {} {}", server_args.server_id, phase);
+    let heartbeat_url = format!(
+        "{}&token={}&player_count=0&phase={}",
+        server_args.heartbeat_url.as_ref()?,
+        server_args.ready_token,
+        phase
+    );
+    match post_to_backend(&heartbeat_url).await {
+        Ok(response) => {
+            return Some(response["assignment"].clone()).filter(|assignment| !assignment.is_null());
+        }
+        Err(err) => {
+            eprintln!("Failed to send heartbeat: {}", err);
+            return None;
+        }
+    }
+}
+
+//Returns true when the backend puts this server back in its warm pool
+async fn report_match_end(server_args: &FakeServerArgs) -> bool {
+    let drop_url = format!(
+        "{}/game_server/drop?server_id={}&token={}",
+        api_url().trim_end_matches('/'),
+        server_args.server_id,
+        server_args.ready_token
+    );
+    match post_to_backend(&drop_url).await {
+        Ok(response) => return response["reuse"] == true,
+        Err(err) => {
+            eprintln!("Failed to report match end: {}", err);
+            return false;
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let Some(server_args) = parse_args() else {
+        eprintln!(
+            "Usage: fake_game_server {{level}}?port={{port}}?game_mode={{game_mode}} -server_id={{id}} [-startup_secs=N] [-match_secs=N]"
+        );
+        std::process::exit(2);
+    };
+
+    println!(
+        "Fake server {:?} loading {} ({}) on port {}",
+        server_args.server_id, server_args.level, server_args.game_mode, server_args.port
+    );
+    let process_start = Instant::now();
+    sleep(Duration::from_secs(server_args.startup_secs)).await;
+
+    let Ok(_socket) = UdpSocket::bind(("0.0.0.0", server_args.port)).await else {
+        eprintln!("Port {} already in use", server_args.port);
+        std::process::exit(1);
+    };
+    let mut is_idle = report_ready(&server_args).await;
+
+    let mut heartbeat = interval(Duration::from_secs(HEARTBEAT_INTERVAL_SECS));
+    loop {
+        //A warm server waits for a match, then loads its map and reports ready again
+        while is_idle {
+            heartbeat.tick().await;
+            if let Some(assignment) = send_heartbeat(&server_args, process_start, "waiting").await {
+                println!(
+                    "Assigned {} on {}, loading {}",
+                    assignment["game_mode"], assignment["map"], assignment["level"]
+                );
+                sleep(Duration::from_secs(server_args.startup_secs)).await;
+                is_idle = report_ready(&server_args).await;
+            }
+        }
+
+        let match_end = sleep(Duration::from_secs(server_args.match_secs));
+        tokio::pin!(match_end);
+        loop {
+            tokio::select! {
+                _ = &mut match_end => break,
+                _ = heartbeat.tick() => {
+                    send_heartbeat(&server_args, process_start, "in_progress").await;
+                }
+            }
+        }
+
+        println!("Match finished");
+        if !report_match_end(&server_args).await {
+            break;
+        }
+        is_idle = true;
+    }
+}
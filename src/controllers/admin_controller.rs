@@ -0,0 +1,128 @@
+use std::collections::HashMap;
+
+use axum::{
+    Json,
+    extract::{Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+};
+use redis::AsyncCommands;
+use serde_json::json;
+
+use crate::{app_state::AppState, auth::AuthUser, global_vars::ADMIN_USERS};
+
+const DEFAULT_LOG_LINES: usize = 100;
+
+fn is_admin(auth_user: &AuthUser) -> bool {
+    return ADMIN_USERS.contains(&auth_user.username);
+}
+
+//Server ids end up in log file names
+fn is_valid_server_id(server_id: &String) -> bool {
+    return !server_id.is_empty()
+        && server_id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '@');
+}
+
+//Last lines of a game server's output: ?server_id=&lines=
+pub async fn get_game_server_logs(
+    State(app_state_): State<AppState>,
+    auth_user: AuthUser,
+    Query(query_payload): Query<HashMap<String, String>>,
+) -> impl IntoResponse {
+    if !is_admin(&auth_user) {
+        return (StatusCode::FORBIDDEN, "Admin only !").into_response();
+    }
+    let Some(server_id) = query_payload.get("server_id") else {
+        return (StatusCode::BAD_REQUEST, "Missing server id !").into_response();
+    };
+    if !is_valid_server_id(server_id) {
+        return (StatusCode::BAD_REQUEST, "Invalid server id format !").into_response();
+    }
+    let line_num = query_payload
+        .get("lines")
+        .and_then(|lines| lines.parse::<usize>().ok())
+        .unwrap_or(DEFAULT_LOG_LINES);
+    match app_state_.game_server_logs.tail(server_id, line_num).await {
+        Some(lines) => {
+            return Json(json!({
+                "server_id": server_id,
+                "lines": lines
+            }))
+            .into_response();
+        }
+        None => return (StatusCode::NOT_FOUND, "No logs for this server !").into_response(),
+    }
+}
+
+//Streams new lines of a running game server, local or on a host agent, to the admin's WebSocket as game_server_log/lines.
+//Following stops with unfollow or when the admin's WebSocket closes
+pub async fn follow_game_server_logs(
+    State(app_state_): State<AppState>,
+    auth_user: AuthUser,
+    Query(query_payload): Query<HashMap<String, String>>,
+) -> impl IntoResponse {
+    if !is_admin(&auth_user) {
+        return (StatusCode::FORBIDDEN, "Admin only !").into_response();
+    }
+    let Some(server_id) = query_payload.get("server_id") else {
+        return (StatusCode::BAD_REQUEST, "Missing server id !").into_response();
+    };
+    if !is_valid_server_id(server_id) {
+        return (StatusCode::BAD_REQUEST, "Invalid server id format !").into_response();
+    }
+    let mut redis_conn = app_state_.redis_conn.clone();
+    match AsyncCommands::exists::<_, bool>(&mut redis_conn, format!("match:{}:token", server_id))
+        .await
+    {
+        Ok(true) => {}
+        Ok(false) => {
+            return (StatusCode::NOT_FOUND, "Game server isn't running !").into_response();
+        }
+        Err(_) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Error proccessing the request !",
+            )
+                .into_response();
+        }
+    }
+    if !app_state_
+        .game_server_logs
+        .follow(server_id, &auth_user.username)
+        .await
+    {
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Error proccessing the request !",
+        )
+            .into_response();
+    }
+    return (StatusCode::OK, "Following game server logs !").into_response();
+}
+
+pub async fn unfollow_game_server_logs(
+    State(app_state_): State<AppState>,
+    auth_user: AuthUser,
+    Query(query_payload): Query<HashMap<String, String>>,
+) -> impl IntoResponse {
+    if !is_admin(&auth_user) {
+        return (StatusCode::FORBIDDEN, "Admin only !").into_response();
+    }
+    let Some(server_id) = query_payload.get("server_id") else {
+        return (StatusCode::BAD_REQUEST, "Missing server id !").into_response();
+    };
+    if !app_state_
+        .game_server_logs
+        .unfollow(server_id, &auth_user.username)
+        .await
+    {
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Error proccessing the request !",
+        )
+            .into_response();
+    }
+    return (StatusCode::OK, "Stopped following game server logs !").into_response();
+}
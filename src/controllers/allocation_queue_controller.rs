@@ -0,0 +1,363 @@
+use axum::{extract::State, http::StatusCode, response::IntoResponse};
+use redis::{AsyncCommands, ExistenceCheck, SetExpiry, SetOptions, aio::MultiplexedConnection};
+use serde_json::json;
+use std::{collections::HashSet, sync::LazyLock, time::Duration};
+
+use crate::{
+    app_state::AppState,
+    auth::{AuthUser, generate_random_code},
+    controllers::{game_server_controller, lobby_controller},
+    global_vars::{GAME_SERVER_QUEUE_TIMEOUT_SECS, USERNAME_REGEX},
+    models::{
+        game_server::{AllocationRequest, GameServer},
+        lobby::LobbyStatus,
+    },
+};
+
+//allocation_queue - [AllocationRequest json], oldest first
+pub const ALLOCATION_QUEUE_KEY: &str = "allocation_queue";
+const ALLOCATION_QUEUE_INTERVAL_SECS: u64 = 2;
+//allocation_queue:lock - lock id of the caller allocating from or in front of the queue, one at a time across instances
+const ALLOCATION_QUEUE_LOCK_KEY: &str = "allocation_queue:lock";
+const ALLOCATION_QUEUE_LOCK_SECS: u64 = 30;
+
+//Deletes KEYS[1] only if it still holds ARGV[1]
+static ALLOCATION_QUEUE_UNLOCK_SCRIPT: LazyLock<redis::Script> = LazyLock::new(|| {
+    redis::Script::new(
+        r"
+        if redis.call('GET', KEYS[1]) == ARGV[1] then
+            return redis.call('DEL', KEYS[1])
+        end
+        return 0
+        ",
+    )
+});
+
+pub enum AllocationOutcome {
+    Allocated(GameServer),
+    //Position in the allocation queue, starting at 1
+    Queued(usize),
+}
+
+//Allocates a server right away, or queues the match when capacity is full or other matches are already waiting
+pub async fn allocate_or_enqueue(
+    app_state_: &AppState,
+    allocation_request: &AllocationRequest,
+) -> Result<AllocationOutcome, (StatusCode, &'static str)> {
+    let mut redis_conn = app_state_.redis_conn.clone();
+    //While the queue is being worked on the match waits its turn behind it
+    if let Some(lock_id) = lock_allocation_queue(redis_conn.clone()).await {
+        let queue_length = AsyncCommands::llen::<_, usize>(&mut redis_conn, ALLOCATION_QUEUE_KEY)
+            .await
+            .unwrap_or(0);
+        let allocation_result = if queue_length == 0 {
+            Some(
+                game_server_controller::allocate_game_server(
+                    app_state_,
+                    &allocation_request.server_id,
+                    &allocation_request.lobby_ids,
+                    &allocation_request.host,
+                    &allocation_request.match_settings,
+                )
+                .await,
+            )
+        } else {
+            None
+        };
+        unlock_allocation_queue(&lock_id, redis_conn.clone()).await;
+        match allocation_result {
+            Some(Ok(server_info)) => return Ok(AllocationOutcome::Allocated(server_info)),
+            Some(Err((StatusCode::SERVICE_UNAVAILABLE, _))) | None => {}
+            Some(Err(err)) => return Err(err),
+        }
+    }
+    match enqueue_allocation(allocation_request, redis_conn).await {
+        Some(queue_position) => return Ok(AllocationOutcome::Queued(queue_position)),
+        None => {
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Error proccessing the request !",
+            ));
+        }
+    }
+}
+
+//Queues a match that couldn't get a server because capacity is full, its lobbies stay Allocating.
+//Returns its position in the queue, starting at 1
+async fn enqueue_allocation(
+    allocation_request: &AllocationRequest,
+    mut redis_conn: MultiplexedConnection,
+) -> Option<usize> {
+    let queue_position = AsyncCommands::rpush::<_, _, usize>(
+        &mut redis_conn,
+        ALLOCATION_QUEUE_KEY,
+        json!(allocation_request).to_string(),
+    )
+    .await
+    .ok()?;
+    broadcast_queue_positions(redis_conn).await;
+    return Some(queue_position);
+}
+
+async fn lock_allocation_queue(mut redis_conn: MultiplexedConnection) -> Option<String> {
+    let lock_id = generate_random_code(16);
+    let Ok(true) = AsyncCommands::set_options::<_, _, bool>(
+        &mut redis_conn,
+        ALLOCATION_QUEUE_LOCK_KEY,
+        &lock_id,
+        SetOptions::default()
+            .conditional_set(ExistenceCheck::NX)
+            .with_expiration(SetExpiry::EX(ALLOCATION_QUEUE_LOCK_SECS)),
+    )
+    .await
+    else {
+        return None;
+    };
+    return Some(lock_id);
+}
+
+async fn unlock_allocation_queue(lock_id: &String, mut redis_conn: MultiplexedConnection) {
+    let _ = ALLOCATION_QUEUE_UNLOCK_SCRIPT
+        .key(ALLOCATION_QUEUE_LOCK_KEY)
+        .arg(lock_id)
+        .invoke_async::<usize>(&mut redis_conn)
+        .await;
+}
+
+//Allocates the queued matches in order as soon as a server slot frees up
+pub async fn run_allocation_queue(app_state_: AppState) {
+    let mut interval = tokio::time::interval(Duration::from_secs(ALLOCATION_QUEUE_INTERVAL_SECS));
+    loop {
+        interval.tick().await;
+        process_allocation_queue(&app_state_).await;
+    }
+}
+
+//Only the instance holding the queue lock works on the queue, so a queued match is allocated once
+async fn process_allocation_queue(app_state_: &AppState) {
+    let mut redis_conn = app_state_.redis_conn.clone();
+    let Some(lock_id) = lock_allocation_queue(redis_conn.clone()).await else {
+        return;
+    };
+    let mut queue_changed = false;
+    loop {
+        let Ok(Some(queue_entry)) =
+            AsyncCommands::lindex::<_, Option<String>>(&mut redis_conn, ALLOCATION_QUEUE_KEY, 0)
+                .await
+        else {
+            break;
+        };
+        let Ok(allocation_request) = serde_json::from_str::<AllocationRequest>(&queue_entry) else {
+            remove_queue_entry(&queue_entry, redis_conn.clone()).await;
+            continue;
+        };
+        //Lobbies disbanded or sent back to Ready while waiting break the match
+        let mut is_waiting = true;
+        for lobby_id in allocation_request.lobby_ids.iter() {
+            if lobby_controller::get_lobby_status(lobby_id, redis_conn.clone()).await
+                != Some(LobbyStatus::Allocating)
+            {
+                is_waiting = false;
+                break;
+            }
+        }
+        if !is_waiting {
+            remove_queue_entry(&queue_entry, redis_conn.clone()).await;
+            abandon_allocation(
+                &allocation_request,
+                "A lobby left the match !",
+                redis_conn.clone(),
+            )
+            .await;
+            queue_changed = true;
+            continue;
+        }
+        //The head is the oldest entry, the ones behind it can't have waited longer
+        if lobby_controller::get_timestamp_secs() - allocation_request.queued_at
+            >= *GAME_SERVER_QUEUE_TIMEOUT_SECS
+        {
+            remove_queue_entry(&queue_entry, redis_conn.clone()).await;
+            abandon_allocation(
+                &allocation_request,
+                "No game server became available in time !",
+                redis_conn.clone(),
+            )
+            .await;
+            queue_changed = true;
+            continue;
+        }
+        match game_server_controller::allocate_game_server(
+            app_state_,
+            &allocation_request.server_id,
+            &allocation_request.lobby_ids,
+            &allocation_request.host,
+            &allocation_request.match_settings,
+        )
+        .await
+        {
+            Ok(_) => {
+                remove_queue_entry(&queue_entry, redis_conn.clone()).await;
+                queue_changed = true;
+            }
+            //Still full, the head keeps its turn
+            Err((StatusCode::SERVICE_UNAVAILABLE, _)) => break,
+            Err((_, message)) => {
+                remove_queue_entry(&queue_entry, redis_conn.clone()).await;
+                abandon_allocation(&allocation_request, message, redis_conn.clone()).await;
+                queue_changed = true;
+            }
+        }
+    }
+    unlock_allocation_queue(&lock_id, redis_conn.clone()).await;
+    if queue_changed {
+        broadcast_queue_positions(redis_conn).await;
+    }
+}
+
+//Lets the leader of a queued lobby give up waiting, every lobby of that match goes back to Ready
+pub async fn cancel_queued_allocation(
+    State(app_state_): State<AppState>,
+    claims: AuthUser,
+) -> impl IntoResponse {
+    let username = &claims.username;
+
+    if !USERNAME_REGEX.is_match(username) {
+        return (StatusCode::BAD_REQUEST, "Invalid username format !").into_response();
+    }
+
+    let mut redis_conn = app_state_.redis_conn.clone();
+    let Ok(lobby_id) =
+        AsyncCommands::get::<_, String>(&mut redis_conn, format!("user:{}:lobby", username)).await
+    else {
+        return (StatusCode::NOT_FOUND, "Not in a lobby !").into_response();
+    };
+    if let Ok(Some(lobby_leader)) = AsyncCommands::hget::<_, _, Option<String>>(
+        &mut redis_conn,
+        format!("lobby:{}", &lobby_id),
+        "leader",
+    )
+    .await
+    {
+        if &lobby_leader != username {
+            return (
+                StatusCode::UNAUTHORIZED,
+                "No permission to perform the request !",
+            )
+                .into_response();
+        }
+    }
+    //Taken under the lock so the entry can't be allocated at the same time
+    let Some(lock_id) = lock_allocation_queue(redis_conn.clone()).await else {
+        return (
+            StatusCode::CONFLICT,
+            "The queue is busy, please try again !",
+        )
+            .into_response();
+    };
+    let queue_entries =
+        AsyncCommands::lrange::<_, Vec<String>>(&mut redis_conn, ALLOCATION_QUEUE_KEY, 0, -1)
+            .await
+            .unwrap_or_default();
+    let queued_allocation = queue_entries.iter().find_map(|queue_entry| {
+        let allocation_request = serde_json::from_str::<AllocationRequest>(queue_entry).ok()?;
+        if !allocation_request.lobby_ids.contains(&lobby_id) {
+            return None;
+        }
+        return Some((queue_entry, allocation_request));
+    });
+    let Some((queue_entry, allocation_request)) = queued_allocation else {
+        unlock_allocation_queue(&lock_id, redis_conn.clone()).await;
+        return (
+            StatusCode::BAD_REQUEST,
+            "Lobby is not waiting for a game server !",
+        )
+            .into_response();
+    };
+    remove_queue_entry(queue_entry, redis_conn.clone()).await;
+    unlock_allocation_queue(&lock_id, redis_conn.clone()).await;
+    abandon_allocation(
+        &allocation_request,
+        &format!("{} cancelled the match", username),
+        redis_conn.clone(),
+    )
+    .await;
+    broadcast_queue_positions(redis_conn).await;
+    return (StatusCode::CREATED, "Left the game server queue !").into_response();
+}
+
+async fn remove_queue_entry(queue_entry: &String, mut redis_conn: MultiplexedConnection) {
+    let _ = AsyncCommands::lrem::<_, _, ()>(&mut redis_conn, ALLOCATION_QUEUE_KEY, 1, queue_entry)
+        .await;
+}
+
+//Gives the lobbies of a queued match back and tells their members why
+async fn abandon_allocation(
+    allocation_request: &AllocationRequest,
+    reason: &str,
+    mut redis_conn: MultiplexedConnection,
+) {
+    let data_to_lobby = json!({
+        "resource": "game_server",
+        "action": "start_failed",
+        "payload": {
+            "server_id": allocation_request.server_id,
+            "reason": reason
+        }
+    });
+    for lobby_id in allocation_request.lobby_ids.iter() {
+        let _ = lobby_controller::transition_lobby_status(
+            lobby_id,
+            LobbyStatus::Ready,
+            redis_conn.clone(),
+        )
+        .await;
+        if let Ok(member_set) = AsyncCommands::smembers::<_, HashSet<String>>(
+            &mut redis_conn,
+            format!("lobby:{}:members", lobby_id),
+        )
+        .await
+        {
+            lobby_controller::broadcast_to_lobby(&member_set, &data_to_lobby, redis_conn.clone())
+                .await;
+        }
+    }
+}
+
+//Sends every queued lobby its current position
+async fn broadcast_queue_positions(mut redis_conn: MultiplexedConnection) {
+    let Ok(queue_entries) =
+        AsyncCommands::lrange::<_, Vec<String>>(&mut redis_conn, ALLOCATION_QUEUE_KEY, 0, -1).await
+    else {
+        return;
+    };
+    let queue_length = queue_entries.len();
+    for (queue_index, queue_entry) in queue_entries.iter().enumerate() {
+        let Ok(allocation_request) = serde_json::from_str::<AllocationRequest>(queue_entry) else {
+            continue;
+        };
+        let data_to_lobby = json!({
+            "resource": "game_server",
+            "action": "queue_position",
+            "payload": {
+                "server_id": allocation_request.server_id,
+                "position": queue_index + 1,
+                "queue_length": queue_length
+            }
+        });
+        for lobby_id in allocation_request.lobby_ids.iter() {
+            if let Ok(member_set) = AsyncCommands::smembers::<_, HashSet<String>>(
+                &mut redis_conn,
+                format!("lobby:{}:members", lobby_id),
+            )
+            .await
+            {
+                lobby_controller::broadcast_to_lobby(
+                    &member_set,
+                    &data_to_lobby,
+                    redis_conn.clone(),
+                )
+                .await;
+            }
+        }
+    }
+}
@@ -347,7 +347,24 @@ pub async fn get_friendlist(
     if result_friendlist.is_empty() {
         return (StatusCode::NOT_FOUND, "Friendlist Empty !").into_response();
     } else {
-        let final_friendlist: Vec<serde_json::Value> = result_friendlist.iter().map(|row| json!({"username": row.get::<String, _>("username"), "status": row.get::<bool, _>("status")})).collect();
+        //sender -> number of unread direct messages from that friend
+        let mut unread_counts: HashMap<String, i64> = HashMap::new();
+        if let Ok(unread_rows) = sqlx::query(
+            "Select sender, count(*) as unread from messages where receiver = $1 and is_read = false group by sender",
+        )
+        .bind(username)
+        .fetch_all(&app_state_.connection_pool)
+        .await
+        {
+            for row in unread_rows.iter() {
+                unread_counts.insert(row.get::<String, _>("sender"), row.get::<i64, _>("unread"));
+            }
+        }
+        let final_friendlist: Vec<serde_json::Value> = result_friendlist.iter().map(|row| {
+            let friend_username = row.get::<String, _>("username");
+            let unread = unread_counts.get(&friend_username).cloned().unwrap_or(0);
+            json!({"username": friend_username, "status": row.get::<bool, _>("status"), "unread": unread})
+        }).collect();
         return (StatusCode::OK, Json(final_friendlist)).into_response();
     }
 }
@@ -414,3 +431,98 @@ pub async fn remove_friend(
     )
         .into_response();
 }
+
+pub async fn block_user(
+    State(app_state_): State<AppState>,
+    claims: AuthUser,
+    query_params: Query<HashMap<String, String>>,
+) -> impl IntoResponse {
+    if query_params.is_empty() {
+        return (StatusCode::BAD_REQUEST, "Params empty !").into_response();
+    }
+
+    let username = &claims.username;
+
+    if !USERNAME_REGEX.is_match(username) {
+        return (StatusCode::BAD_REQUEST, "Invalid username format !").into_response();
+    }
+
+    let Some(blocked_user) = query_params.get("blocked") else {
+        return (StatusCode::BAD_REQUEST, "Missing user to block !").into_response();
+    };
+    if !USERNAME_REGEX.is_match(blocked_user) {
+        return (StatusCode::BAD_REQUEST, "Invalid username format !").into_response();
+    }
+    if username == blocked_user {
+        return (StatusCode::BAD_REQUEST, "Can't block self !").into_response();
+    }
+
+    match sqlx::query("Select username from users where username = $1")
+        .bind(blocked_user)
+        .fetch_optional(&app_state_.connection_pool)
+        .await
+    {
+        Ok(Some(_)) => {}
+        Ok(None) => return (StatusCode::NOT_FOUND, "User doesn't exist !").into_response(),
+        Err(_) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Error proccessing the request !",
+            )
+                .into_response();
+        }
+    }
+
+    if let Ok(_) = sqlx::query(
+        "Insert into BlockedUsers (blocker, blocked) values ($1, $2) on conflict do nothing",
+    )
+    .bind(username)
+    .bind(blocked_user)
+    .execute(&app_state_.connection_pool)
+    .await
+    {
+        return (StatusCode::CREATED, blocked_user.clone()).into_response();
+    }
+    return (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "Error finishing the request, please try again !",
+    )
+        .into_response();
+}
+
+pub async fn unblock_user(
+    State(app_state_): State<AppState>,
+    claims: AuthUser,
+    query_params: Query<HashMap<String, String>>,
+) -> impl IntoResponse {
+    if query_params.is_empty() {
+        return (StatusCode::BAD_REQUEST, "Params empty !").into_response();
+    }
+
+    let username = &claims.username;
+
+    if !USERNAME_REGEX.is_match(username) {
+        return (StatusCode::BAD_REQUEST, "Invalid username format !").into_response();
+    }
+
+    let Some(blocked_user) = query_params.get("blocked") else {
+        return (StatusCode::BAD_REQUEST, "Missing user to unblock !").into_response();
+    };
+    if !USERNAME_REGEX.is_match(blocked_user) {
+        return (StatusCode::BAD_REQUEST, "Invalid username format !").into_response();
+    }
+
+    if let Ok(_) = sqlx::query("Delete from BlockedUsers where blocker = $1 and blocked = $2")
+        .bind(username)
+        .bind(blocked_user)
+        .execute(&app_state_.connection_pool)
+        .await
+    {
+        return (StatusCode::CREATED, blocked_user.clone()).into_response();
+    }
+    return (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "Error finishing the request, please try again !",
+    )
+        .into_response();
+}
@@ -1,6 +1,5 @@
 use std::{
     collections::{HashMap, HashSet},
-    process::{Command, Stdio},
     thread,
     time::Duration,
 };
@@ -11,16 +10,41 @@ use axum::{
     http::StatusCode,
     response::IntoResponse,
 };
-use redis::{AsyncCommands, FromRedisValue, aio::MultiplexedConnection, pipe};
+use redis::{
+    AsyncCommands, ExistenceCheck, FromRedisValue, SetOptions, aio::MultiplexedConnection, pipe,
+};
 use serde_json::json;
 
 use crate::{
     app_state::AppState,
-    auth::AuthUser,
-    global_vars::USERNAME_REGEX,
-    models::{game_server::GameServer, lobby::LobbyInfo},
+    auth::{AuthUser, generate_random_code, sign_host_agent_message, verify_host_agent_message},
+    controllers::{
+        allocation_queue_controller::{self, AllocationOutcome},
+        loadout_controller, lobby_controller, warm_pool_controller,
+    },
+    game_server_launcher::{GameServerStatus, LaunchRequest},
+    game_server_ports,
+    global_vars::{
+        GAME_CATALOG, GAME_SERVER_HEARTBEAT_TIMEOUT_SECS, GAME_SERVER_LOCAL_CAPACITY,
+        GAME_SERVER_STARTUP_TIMEOUT_SECS, HOST_AGENT_SECRET, PUBLIC_API_URL, USERNAME_REGEX,
+    },
+    host_agent::{
+        GAME_SERVER_HOSTS_KEY, HOST_AGENT_COMMAND_CHANNEL_PREFIX, HostAgentCommand, HostAgentEvent,
+    },
+    models::{
+        catalog::MatchSettings,
+        game_server::{AllocationRequest, GameServer},
+        lobby::{LobbyInfo, LobbyStatus},
+    },
 };
 
+const GAME_SERVER_SUPERVISE_INTERVAL_SECS: u64 = 2;
+const GAME_SERVER_EXIT_RECORD_TTL_SECS: i64 = 3600;
+//Servers running on host agents, each with a match:{server_id}:host key
+const REMOTE_GAME_SERVERS_KEY: &str = "remote_game_servers";
+//Phases a server reports in its heartbeats, a server is "waiting" until it sends its first one
+const MATCH_PHASES: [&str; 4] = ["waiting", "warmup", "in_progress", "post_match"];
+
 fn create_game_server_info_hash_fields(game_server_info: &GameServer) -> Vec<(&str, String)> {
     return vec![
         ("address", game_server_info.address.clone()),
@@ -61,6 +85,35 @@ pub async fn create_game_server(
                 }
             }
         }
+        let match_settings =
+            lobby_controller::get_lobby_match_settings(&current_lobby_id, redis_conn.clone()).await;
+        //The leader starting the match counts as ready
+        let Some((member_set, mut ready_set)) =
+            lobby_controller::get_lobby_ready_state(&current_lobby_id, redis_conn.clone()).await
+        else {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Error proccessing the request !",
+            )
+                .into_response();
+        };
+        ready_set.insert(auth_user.username.clone());
+        if !lobby_controller::has_ready_quorum(member_set.len(), ready_set.len()) {
+            return (StatusCode::BAD_REQUEST, "Not enough members are ready !").into_response();
+        }
+        if let Some(game_mode) = GAME_CATALOG.find_game_mode(&match_settings.game_mode) {
+            if member_set.len() < game_mode.min_players {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    "Not enough players for game mode !",
+                )
+                    .into_response();
+            }
+            if member_set.len() > game_mode.max_players {
+                return (StatusCode::BAD_REQUEST, "Too many players for game mode !")
+                    .into_response();
+            }
+        }
         if let Ok(game_server_info_opt) =
             AsyncCommands::get::<_, Option<String>>(&mut redis_conn, &game_server_info_key).await
         {
@@ -74,91 +127,57 @@ pub async fn create_game_server(
                 }
             }
         }
-        if let Ok(listener) = tokio::net::TcpListener::bind("0.0.0.0:0").await {
-            if let Ok(address) = listener.local_addr() {
-                let port = address.port();
-                if let Ok(exec) = Command::new(r"D:\GameBuilds\WindowsServer\BeatHimUpServer.exe")
-                    .arg(format!("Level_MainLevel?port={}", port))
-                    .arg("-nopause")
-                    .arg("-log")
-                    .arg(format!("-server_id={}", current_lobby_id))
-                    .stdin(Stdio::piped())
-                    .spawn()
-                {
-                    //let mut pipe = redis::pipe();
-                    let response_address = format!("127.0.0.1:{}", port);
-                    let server_info = GameServer::new(&response_address, &auth_user.username);
-                    if let Ok(_) = AsyncCommands::set::<_, _, ()>(
-                        &mut redis_conn,
-                        &game_server_info_key,
-                        json!(server_info).to_string(),
-                    )
-                    .await
-                    {
-                        if let Ok(_) = AsyncCommands::hset::<_, _, _, ()>(
-                            &mut redis_conn,
-                            &key_list,
-                            "status",
-                            "In_Match",
-                        )
-                        .await
-                        {
-                            if let Ok(member_set) = AsyncCommands::smembers::<_, HashSet<String>>(
-                                &mut redis_conn,
-                                format!("{}:members", &key_list),
-                            )
-                            .await
-                            {
-                                drop(listener);
-                                for member in member_set.iter() {
-                                    let _ = AsyncCommands::del::<_, ()>(
-                                        &mut redis_conn,
-                                        format!("character_info:{}", member),
-                                    )
-                                    .await;
-                                    if member == &auth_user.username {
-                                        continue;
-                                    }
-                                    let data_to_lobby = json!({
-                                        "resource": "game_server",
-                                        "action": "create",
-                                        "payload": {
-                                            "game_server": json!(server_info)
-                                        }
-                                    });
-                                    let pub_sub_data_json = json!({
-                                        "username": member,
-                                        "data": data_to_lobby
-                                    });
-                                    let _ = AsyncCommands::publish::<_, _, ()>(
-                                        &mut redis_conn,
-                                        "web_socket_events",
-                                        pub_sub_data_json.to_string(),
-                                    )
-                                    .await;
-                                }
-                            }
-                            // //Insert a new game server proccess to map
-                            // {
-                            //     let mut game_server_exe_map_write =
-                            //         app_state_.game_server_exe_map.write().await;
-                            //     game_server_exe_map_write.insert(current_lobby_id, exec);
-                            // }
-                            return (StatusCode::CREATED, Json(server_info)).into_response();
-                        }
-                    }
-                } else {
-                    return (
-                        StatusCode::INTERNAL_SERVER_ERROR,
-                        "Failed to start new Server process !",
-                    )
-                        .into_response();
-                }
-            } else {
-                return (StatusCode::SERVICE_UNAVAILABLE, "No port available !").into_response();
+        if let Err((status_code, message)) = lobby_controller::transition_lobby_status(
+            &current_lobby_id,
+            LobbyStatus::Allocating,
+            redis_conn.clone(),
+        )
+        .await
+        {
+            return (status_code, message).into_response();
+        }
+        let allocation_request = AllocationRequest {
+            server_id: current_lobby_id.clone(),
+            lobby_ids: vec![current_lobby_id.clone()],
+            host: auth_user.username.clone(),
+            match_settings,
+            queued_at: lobby_controller::get_timestamp_secs(),
+        };
+        match allocation_queue_controller::allocate_or_enqueue(&app_state_, &allocation_request)
+            .await
+        {
+            //The leader gets the address with the other members once the server is ready
+            Ok(AllocationOutcome::Allocated(server_info)) => {
+                return (
+                    StatusCode::ACCEPTED,
+                    Json(json!({
+                        "server_id": server_info.server_id,
+                        "status": LobbyStatus::Allocating
+                    })),
+                )
+                    .into_response();
+            }
+            //Allocated by the queue once a server slot frees up
+            Ok(AllocationOutcome::Queued(queue_position)) => {
+                return (
+                    StatusCode::ACCEPTED,
+                    Json(json!({
+                        "server_id": allocation_request.server_id,
+                        "status": LobbyStatus::Allocating,
+                        "queue_position": queue_position
+                    })),
+                )
+                    .into_response();
+            }
+            Err((status_code, message)) => {
+                let _ = lobby_controller::transition_lobby_status(
+                    &current_lobby_id,
+                    LobbyStatus::Ready,
+                    redis_conn.clone(),
+                )
+                .await;
+                return (status_code, message).into_response();
             }
-        } else {
-            return (StatusCode::SERVICE_UNAVAILABLE, "No address available !").into_response();
         }
     }
     return (
@@ -168,36 +187,810 @@ pub async fn create_game_server(
         .into_response();
 }
 
-pub async fn drop_game_server(
+//Spawns a game server process for the lobbies of a match. The lobbies stay Allocating and their members
+//are only notified once the server reports ready through /game_server/ready, see mark_game_server_ready
+pub async fn allocate_game_server(
+    app_state_: &AppState,
+    server_id: &String,
+    lobby_ids: &Vec<String>,
+    host: &String,
+    match_settings: &MatchSettings,
+) -> Result<GameServer, (StatusCode, &'static str)> {
+    let mut redis_conn = app_state_.redis_conn.clone();
+    let Some(map) = GAME_CATALOG.find_map(&match_settings.map) else {
+        return Err((StatusCode::BAD_REQUEST, "Unknown map !"));
+    };
+    if let Some(warm_server_info) = warm_pool_controller::claim_warm_server(app_state_).await {
+        return assign_warm_server(
+            app_state_,
+            &warm_server_info,
+            lobby_ids,
+            host,
+            match_settings,
+            &map.level,
+        )
+        .await;
+    }
+    let game_server_host = pick_game_server_host(redis_conn.clone()).await?;
+    //The port and address of a server on a host agent are only known once the agent launched it
+    let mut port = 0;
+    let mut response_address = String::new();
+    if game_server_host.is_none() {
+        let local_server_num = app_state_.game_server_exe_map.read().await.len();
+        if local_server_num >= *GAME_SERVER_LOCAL_CAPACITY {
+            return Err((
+                StatusCode::SERVICE_UNAVAILABLE,
+                "No game server capacity available, please try again later !",
+            ));
+        }
+        let Some(local_port) = app_state_.game_server_ports.lock().await.lease(server_id) else {
+            return Err((
+                StatusCode::SERVICE_UNAVAILABLE,
+                "No game server port available, please try again later !",
+            ));
+        };
+        port = local_port;
+        response_address = game_server_ports::advertised_address(port);
+    }
+    let server_info = GameServer::new(server_id, &response_address, host);
+    let ready_token = generate_random_code(32);
+    //match:lobby_haha:roster - [{username: "", lobby_id: "", spectator: false, loadout: {}}], read by the server at startup
+    //match:lobby_haha:startup - {game_server: {}, game_mode: "", map: ""}, until the server reports ready
+    //match:lobby_haha:token - secret the server authenticates with
+    //match:lobby_haha:lobbies - [lobby_haha, lobby_keke]
+    //match:lobby_haha:host - id of the host agent running the server, if not this backend
+    let roster = loadout_controller::build_match_roster(lobby_ids, redis_conn.clone()).await;
+    let mut pipe = redis::pipe();
+    pipe.atomic()
+        .set(
+            format!("match:{}:roster", server_id),
+            json!(roster).to_string(),
+        )
+        .set(
+            format!("match:{}:startup", server_id),
+            json!({
+                "game_server": server_info,
+                "game_mode": match_settings.game_mode,
+                "map": match_settings.map
+            })
+            .to_string(),
+        )
+        .set(format!("match:{}:token", server_id), &ready_token);
+    for lobby_id in lobby_ids.iter() {
+        pipe.sadd(format!("match:{}:lobbies", server_id), lobby_id);
+    }
+    if let Some(host_id) = &game_server_host {
+        pipe.set(format!("match:{}:host", server_id), host_id)
+            .sadd(REMOTE_GAME_SERVERS_KEY, server_id);
+    }
+    if let Err(_) = pipe.query_async::<()>(&mut redis_conn).await {
+        app_state_.game_server_ports.lock().await.release(server_id);
+        return Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Error proccessing the request !",
+        ));
+    }
+    let launch_request = build_launch_request(
+        server_id,
+        port,
+        &map.level,
+        &match_settings.game_mode,
+        ready_token,
+    );
+    if let Some(host_id) = &game_server_host {
+        //The agent answers with a launched or launch_failed event, see handle_host_agent_event
+        let launch_command = HostAgentCommand::Launch { launch_request };
+        if !dispatch_host_command(host_id, launch_command, redis_conn.clone()).await {
+            discard_match_keys(server_id, redis_conn.clone()).await;
+            return Err((
+                StatusCode::SERVICE_UNAVAILABLE,
+                "Game server host unreachable, please try again later !",
+            ));
+        }
+        let _ = AsyncCommands::hincr::<_, _, _, ()>(
+            &mut redis_conn,
+            format!("host:{}", host_id),
+            "running",
+            1,
+        )
+        .await;
+    } else {
+        let Ok(mut exec) = app_state_.game_server_launcher.spawn(&launch_request) else {
+            app_state_.game_server_ports.lock().await.release(server_id);
+            discard_match_keys(server_id, redis_conn.clone()).await;
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to start new Server process !",
+            ));
+        };
+        app_state_
+            .game_server_logs
+            .capture_output(server_id, &mut exec);
+        //Tracked until it exits, see run_game_server_supervisor
+        {
+            let mut game_server_exe_map_write = app_state_.game_server_exe_map.write().await;
+            game_server_exe_map_write.insert(server_id.clone(), exec);
+        }
+    }
+    let startup_app_state = app_state_.clone();
+    let startup_server_id = server_id.clone();
+    tokio::spawn(async move {
+        watch_game_server_startup(startup_app_state, startup_server_id).await;
+    });
+    return Ok(server_info);
+}
+
+//The roster carries every player's loadout, the server fetches it with its token
+fn build_roster_url(server_id: &String, ready_token: &String) -> String {
+    return format!(
+        "{}/game_server/roster?server_id={}&token={}",
+        PUBLIC_API_URL.as_str(),
+        server_id,
+        ready_token
+    );
+}
+
+pub fn build_launch_request(
+    server_id: &String,
+    port: u16,
+    level: &String,
+    game_mode: &String,
+    ready_token: String,
+) -> LaunchRequest {
+    return LaunchRequest {
+        server_id: server_id.clone(),
+        port,
+        level: level.clone(),
+        game_mode: game_mode.clone(),
+        roster_url: build_roster_url(server_id, &ready_token),
+        ready_url: format!(
+            "{}/game_server/ready?server_id={}",
+            PUBLIC_API_URL.as_str(),
+            server_id
+        ),
+        heartbeat_url: format!(
+            "{}/game_server/heartbeat?server_id={}",
+            PUBLIC_API_URL.as_str(),
+            server_id
+        ),
+        ready_token,
+    };
+}
+
+//Hands a match to an idle warm server, keeping its id. It gets the match in its next heartbeat response,
+//loads the map and reports ready again, the lobbies stay Allocating until then like for a cold start
+async fn assign_warm_server(
+    app_state_: &AppState,
+    warm_server_info: &GameServer,
+    lobby_ids: &Vec<String>,
+    host: &String,
+    match_settings: &MatchSettings,
+    level: &String,
+) -> Result<GameServer, (StatusCode, &'static str)> {
+    let mut redis_conn = app_state_.redis_conn.clone();
+    let server_id = &warm_server_info.server_id;
+    let server_info = GameServer::new(server_id, &warm_server_info.address, host);
+    let Ok(Some(ready_token)) = AsyncCommands::get::<_, Option<String>>(
+        &mut redis_conn,
+        format!("match:{}:token", server_id),
+    )
+    .await
+    else {
+        stop_game_server_process(app_state_, server_id).await;
+        discard_match_keys(server_id, redis_conn.clone()).await;
+        return Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Error proccessing the request !",
+        ));
+    };
+    //match:warm_haha:assignment - {level: "", game_mode: "", map: "", roster_url: ""}, taken by the next heartbeat
+    let roster = loadout_controller::build_match_roster(lobby_ids, redis_conn.clone()).await;
+    let mut pipe = redis::pipe();
+    pipe.atomic()
+        .set(
+            format!("match:{}:roster", server_id),
+            json!(roster).to_string(),
+        )
+        .set(
+            format!("match:{}:startup", server_id),
+            json!({
+                "game_server": server_info,
+                "game_mode": match_settings.game_mode,
+                "map": match_settings.map
+            })
+            .to_string(),
+        )
+        .set(
+            format!("match:{}:assignment", server_id),
+            json!({
+                "level": level,
+                "game_mode": match_settings.game_mode,
+                "map": match_settings.map,
+                "roster_url": build_roster_url(server_id, &ready_token)
+            })
+            .to_string(),
+        );
+    for lobby_id in lobby_ids.iter() {
+        pipe.sadd(format!("match:{}:lobbies", server_id), lobby_id);
+    }
+    if let Err(_) = pipe.query_async::<()>(&mut redis_conn).await {
+        //Can't put it back in a known state, the pool replaces it
+        stop_game_server_process(app_state_, server_id).await;
+        discard_match_keys(server_id, redis_conn.clone()).await;
+        return Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Error proccessing the request !",
+        ));
+    }
+    let startup_app_state = app_state_.clone();
+    let startup_server_id = server_id.clone();
+    tokio::spawn(async move {
+        watch_game_server_startup(startup_app_state, startup_server_id).await;
+    });
+    return Ok(server_info);
+}
+
+//Keys written by allocate_game_server for a server that never got launched
+async fn discard_match_keys(server_id: &String, mut redis_conn: MultiplexedConnection) {
+    let mut pipe = redis::pipe();
+    pipe.del(format!("match:{}:roster", server_id))
+        .del(format!("match:{}:startup", server_id))
+        .del(format!("match:{}:token", server_id))
+        .del(format!("match:{}:lobbies", server_id))
+        .del(format!("match:{}:host", server_id))
+        .del(format!("match:{}:assignment", server_id))
+        .srem(REMOTE_GAME_SERVERS_KEY, server_id);
+    let _ = pipe.query_async::<()>(&mut redis_conn).await;
+}
+
+//Least loaded live host agent with a free slot, None when no agent is registered and this backend runs the servers itself
+async fn pick_game_server_host(
+    mut redis_conn: MultiplexedConnection,
+) -> Result<Option<String>, (StatusCode, &'static str)> {
+    //Agents can't be told to launch anything without the shared secret
+    if HOST_AGENT_SECRET.is_none() {
+        return Ok(None);
+    }
+    let Ok(host_ids) =
+        AsyncCommands::smembers::<_, HashSet<String>>(&mut redis_conn, GAME_SERVER_HOSTS_KEY).await
+    else {
+        return Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Error proccessing the request !",
+        ));
+    };
+    let mut has_live_host = false;
+    let mut least_loaded_host: Option<(String, f64)> = None;
+    for host_id in host_ids.iter() {
+        let Ok(host_info) = AsyncCommands::hgetall::<_, HashMap<String, String>>(
+            &mut redis_conn,
+            format!("host:{}", host_id),
+        )
+        .await
+        else {
+            continue;
+        };
+        //The agent stopped reporting and its registration expired
+        if host_info.is_empty() {
+            let _ =
+                AsyncCommands::srem::<_, _, ()>(&mut redis_conn, GAME_SERVER_HOSTS_KEY, host_id)
+                    .await;
+            continue;
+        }
+        has_live_host = true;
+        let capacity = host_info
+            .get("capacity")
+            .and_then(|capacity| capacity.parse::<usize>().ok())
+            .unwrap_or(0);
+        let running = host_info
+            .get("running")
+            .and_then(|running| running.parse::<usize>().ok())
+            .unwrap_or(0);
+        if running >= capacity {
+            continue;
+        }
+        let load = running as f64 / capacity as f64;
+        if least_loaded_host
+            .as_ref()
+            .is_none_or(|(_, least_load)| load < *least_load)
+        {
+            least_loaded_host = Some((host_id.clone(), load));
+        }
+    }
+    if has_live_host && least_loaded_host.is_none() {
+        return Err((
+            StatusCode::SERVICE_UNAVAILABLE,
+            "All game server hosts are full, please try again later !",
+        ));
+    }
+    return Ok(least_loaded_host.map(|(host_id, _)| host_id));
+}
+
+//Publishes a signed command on the host's channel, false when the agent isn't listening
+async fn dispatch_host_command(
+    host_id: &String,
+    command: HostAgentCommand,
+    mut redis_conn: MultiplexedConnection,
+) -> bool {
+    let Some(token) = sign_host_agent_message(host_id, command) else {
+        return false;
+    };
+    match AsyncCommands::publish::<_, _, usize>(
+        &mut redis_conn,
+        format!("{}:{}", HOST_AGENT_COMMAND_CHANNEL_PREFIX, host_id),
+        token,
+    )
+    .await
+    {
+        Ok(receiver_num) => return receiver_num > 0,
+        Err(_) => return false,
+    }
+}
+
+//Events of the servers running on host agents, received on the host_agent_events channel
+pub async fn handle_host_agent_event(app_state_: &AppState, token: &String) {
+    let Some(claims) = verify_host_agent_message::<HostAgentEvent>(token) else {
+        println!("Rejected host agent event with an invalid signature");
+        return;
+    };
+    let mut redis_conn = app_state_.redis_conn.clone();
+    //Agents only report on the servers they were told to run
+    let server_id = match &claims.message {
+        HostAgentEvent::Launched { server_id, .. }
+        | HostAgentEvent::LaunchFailed { server_id }
+        | HostAgentEvent::Exited { server_id, .. } => server_id,
+    };
+    let Ok(Some(server_host_id)) = AsyncCommands::get::<_, Option<String>>(
+        &mut redis_conn,
+        format!("match:{}:host", server_id),
+    )
+    .await
+    else {
+        return;
+    };
+    if server_host_id != claims.host_id {
+        println!(
+            "Rejected host agent event from {:?} about game server {:?} of host {:?}",
+            claims.host_id, server_id, server_host_id
+        );
+        return;
+    }
+    match claims.message {
+        HostAgentEvent::Launched { server_id, address } => {
+            let startup_key = format!("match:{}:startup", server_id);
+            let Ok(Some(startup_str)) =
+                AsyncCommands::get::<_, Option<String>>(&mut redis_conn, &startup_key).await
+            else {
+                return;
+            };
+            let Ok(mut startup_info) = serde_json::from_str::<serde_json::Value>(&startup_str)
+            else {
+                return;
+            };
+            startup_info["game_server"]["address"] = json!(address);
+            //Only while the server is still starting, the ready call or the startup timeout may have taken it
+            let _ = AsyncCommands::set_options::<_, _, ()>(
+                &mut redis_conn,
+                &startup_key,
+                startup_info.to_string(),
+                SetOptions::default().conditional_set(ExistenceCheck::XX),
+            )
+            .await;
+        }
+        HostAgentEvent::LaunchFailed { server_id } => {
+            println!(
+                "Host agent {:?} couldn't start game server {:?}",
+                claims.host_id, server_id
+            );
+            forget_remote_game_server(&server_id, redis_conn.clone()).await;
+            let data_to_lobby = json!({
+                "resource": "game_server",
+                "action": "start_failed",
+                "payload": {
+                    "server_id": server_id,
+                    "reason": "Game server host couldn't start the match !"
+                }
+            });
+            fail_match(&server_id, &data_to_lobby, redis_conn).await;
+        }
+        HostAgentEvent::Exited {
+            server_id,
+            exit_code,
+        } => {
+            forget_remote_game_server(&server_id, redis_conn.clone()).await;
+            handle_game_server_exit(&server_id, exit_code, redis_conn).await;
+        }
+    }
+}
+
+async fn forget_remote_game_server(server_id: &String, mut redis_conn: MultiplexedConnection) {
+    let mut pipe = redis::pipe();
+    pipe.del(format!("match:{}:host", server_id))
+        .srem(REMOTE_GAME_SERVERS_KEY, server_id);
+    let _ = pipe.query_async::<()>(&mut redis_conn).await;
+}
+
+//host:port, with brackets around an IPv6 host
+fn is_valid_server_address(address: &str) -> bool {
+    let Some((host, port)) = address.rsplit_once(':') else {
+        return false;
+    };
+    return !host.is_empty()
+        && port.parse::<u16>().is_ok()
+        && host
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || ".-:[]".contains(c));
+}
+
+//Called by a launched server once its map is loaded and it accepts connections.
+//Servers started by a host agent also pass &address=, see host_agent
+pub async fn mark_game_server_ready(
     State(app_state_): State<AppState>,
     Query(query_payload): Query<HashMap<String, String>>,
 ) -> impl IntoResponse {
-    if query_payload.is_empty() {
-        return (StatusCode::BAD_REQUEST, "Query empty !").into_response();
-    }
+    let (Some(server_id), Some(token)) =
+        (query_payload.get("server_id"), query_payload.get("token"))
+    else {
+        return (StatusCode::BAD_REQUEST, "Missing server id or token !").into_response();
+    };
     let mut redis_conn = app_state_.redis_conn.clone();
-    if let Some(current_lobby_id) = query_payload.get("server_id") {
-        let key_list = format!("lobby:{}", current_lobby_id);
-        let game_server_info_key = format!("game_server:{}", current_lobby_id);
-        let mut pipe = redis::pipe();
-        pipe.atomic()
-            .del(game_server_info_key)
-            .hset(&key_list, "status", "Ready")
-            .smembers(format!("{}:members", &key_list));
-        if let Ok((_, _, member_set)) = pipe
-            .query_async::<((), (), HashSet<String>)>(&mut redis_conn)
-            .await
+    if let Err((status_code, message)) =
+        check_game_server_token(server_id, token, redis_conn.clone()).await
+    {
+        return (status_code, message).into_response();
+    }
+    //Servers on host agents report their address, the ready call can arrive before the agent's Launched event
+    let reported_address = query_payload.get("address");
+    if let Some(address) = reported_address
+        && !is_valid_server_address(address)
+    {
+        return (StatusCode::BAD_REQUEST, "Invalid server address !").into_response();
+    }
+    if reported_address.is_none() {
+        let Ok(startup_opt) = AsyncCommands::get::<_, Option<String>>(
+            &mut redis_conn,
+            format!("match:{}:startup", server_id),
+        )
+        .await
+        else {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Error proccessing the request !",
+            )
+                .into_response();
+        };
+        let has_address = startup_opt
+            .and_then(|startup_str| serde_json::from_str::<serde_json::Value>(&startup_str).ok())
+            .is_some_and(|startup_info| startup_info["game_server"]["address"] != json!(""));
+        if !has_address {
+            return (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "Server address isn't known yet, please try again !",
+            )
+                .into_response();
+        }
+    }
+    //Taking the startup info settles the race with the startup timeout
+    let Ok(startup_opt) = AsyncCommands::get_del::<_, Option<String>>(
+        &mut redis_conn,
+        format!("match:{}:startup", server_id),
+    )
+    .await
+    else {
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Error proccessing the request !",
+        )
+            .into_response();
+    };
+    let Some(mut startup_info) = startup_opt
+        .and_then(|startup_str| serde_json::from_str::<serde_json::Value>(&startup_str).ok())
+    else {
+        return (StatusCode::CONFLICT, "Server is not starting !").into_response();
+    };
+    //Only fills in an unknown address, the address of a local server is set by this backend
+    if let Some(address) = reported_address
+        && startup_info["game_server"]["address"] == json!("")
+    {
+        startup_info["game_server"]["address"] = json!(address);
+    }
+    //A warm server has no match yet, it waits for one in the pool
+    if startup_info["warm"] == json!(true) {
+        if !warm_pool_controller::mark_warm_server_idle(
+            server_id,
+            &startup_info["game_server"],
+            startup_info["owner"].as_str().unwrap_or_default(),
+            redis_conn.clone(),
+        )
+        .await
+        {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Error proccessing the request !",
+            )
+                .into_response();
+        }
+        return (StatusCode::OK, Json(json!({ "status": "idle" }))).into_response();
+    }
+    let Ok(lobby_ids) = AsyncCommands::smembers::<_, HashSet<String>>(
+        &mut redis_conn,
+        format!("match:{}:lobbies", server_id),
+    )
+    .await
+    else {
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Error proccessing the request !",
+        )
+            .into_response();
+    };
+    let server_info = &startup_info["game_server"];
+    //game_server:lobby_haha - {server_id: "lobby_haha", address: "", host: ""}
+    let mut pipe = redis::pipe();
+    pipe.atomic();
+    //The watchdog starts counting from the ready call
+    pipe.hset_multiple(
+        format!("match:{}:heartbeat", server_id),
+        &[
+            ("player_count", "0".to_string()),
+            ("phase", MATCH_PHASES[0].to_string()),
+            (
+                "last_seen",
+                lobby_controller::get_timestamp_secs().to_string(),
+            ),
+        ],
+    )
+    .expire(
+        format!("match:{}:heartbeat", server_id),
+        *GAME_SERVER_HEARTBEAT_TIMEOUT_SECS,
+    );
+    for lobby_id in lobby_ids.iter() {
+        let key_list = format!("lobby:{}", lobby_id);
+        pipe.set(format!("game_server:{}", lobby_id), server_info.to_string())
+            //Members have to ready up again for the next match
+            .del(format!("{}:ready", &key_list))
+            .del(format!("{}:ready_check", &key_list));
+    }
+    if let Err(_) = pipe.query_async::<()>(&mut redis_conn).await {
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Error proccessing the request !",
+        )
+            .into_response();
+    }
+    for lobby_id in lobby_ids.iter() {
+        let _ = lobby_controller::transition_lobby_status(
+            lobby_id,
+            LobbyStatus::InMatch,
+            redis_conn.clone(),
+        )
+        .await;
+    }
+    for lobby_id in lobby_ids.iter() {
+        if let Ok(member_set) = AsyncCommands::smembers::<_, HashSet<String>>(
+            &mut redis_conn,
+            format!("lobby:{}:members", lobby_id),
+        )
+        .await
         {
-            for member in member_set {
-                let _ = AsyncCommands::del::<_, ()>(
+            let spectator_set =
+                lobby_controller::get_lobby_spectators(lobby_id, redis_conn.clone()).await;
+            for member in member_set.iter() {
+                //Spectators join the server without a character
+                let is_spectator = spectator_set.contains(member);
+                if !is_spectator {
+                    let _ = AsyncCommands::del::<_, ()>(
+                        &mut redis_conn,
+                        format!("character_info:{}", member),
+                    )
+                    .await;
+                }
+                let data_to_lobby = json!({
+                    "resource": "game_server",
+                    "action": "create",
+                    "payload": {
+                        "game_server": server_info,
+                        "spectator": is_spectator,
+                        "game_mode": startup_info["game_mode"],
+                        "map": startup_info["map"]
+                    }
+                });
+                let pub_sub_data_json = json!({
+                    "username": member,
+                    "data": data_to_lobby
+                });
+                let _ = AsyncCommands::publish::<_, _, ()>(
                     &mut redis_conn,
-                    format!("character_info:{}", member),
+                    "web_socket_events",
+                    pub_sub_data_json.to_string(),
                 )
                 .await;
             }
-            return (StatusCode::CREATED, "Dropped server !").into_response();
         }
     }
+    return (StatusCode::OK, Json(json!({ "status": "in_match" }))).into_response();
+}
+
+//Kills a server that didn't report ready in time and gives its lobbies back
+pub async fn watch_game_server_startup(app_state_: AppState, server_id: String) {
+    tokio::time::sleep(Duration::from_secs(*GAME_SERVER_STARTUP_TIMEOUT_SECS)).await;
+    let mut redis_conn = app_state_.redis_conn.clone();
+    let Ok(Some(_)) = AsyncCommands::get_del::<_, Option<String>>(
+        &mut redis_conn,
+        format!("match:{}:startup", server_id),
+    )
+    .await
+    else {
+        return;
+    };
+    println!("Game server {:?} didn't start in time", server_id);
+    stop_game_server_process(&app_state_, &server_id).await;
+    let data_to_lobby = json!({
+        "resource": "game_server",
+        "action": "start_failed",
+        "payload": {
+            "server_id": server_id,
+            "reason": "Game server didn't start in time !"
+        }
+    });
+    fail_match(&server_id, &data_to_lobby, redis_conn).await;
+}
+
+//Reported periodically by a running server, a server whose heartbeat expires is considered hung
+pub async fn game_server_heartbeat(
+    State(app_state_): State<AppState>,
+    Query(query_payload): Query<HashMap<String, String>>,
+) -> impl IntoResponse {
+    let (Some(server_id), Some(token)) =
+        (query_payload.get("server_id"), query_payload.get("token"))
+    else {
+        return (StatusCode::BAD_REQUEST, "Missing server id or token !").into_response();
+    };
+    let Some(Ok(player_count)) = query_payload
+        .get("player_count")
+        .map(|player_count_str| player_count_str.parse::<usize>())
+    else {
+        return (StatusCode::BAD_REQUEST, "Invalid player count !").into_response();
+    };
+    let Some(phase) = query_payload
+        .get("phase")
+        .filter(|phase| MATCH_PHASES.contains(&phase.as_str()))
+    else {
+        return (StatusCode::BAD_REQUEST, "Invalid match phase !").into_response();
+    };
+    let mut redis_conn = app_state_.redis_conn.clone();
+    if let Err((status_code, message)) =
+        check_game_server_token(server_id, token, redis_conn.clone()).await
+    {
+        return (status_code, message).into_response();
+    }
+    //match:lobby_haha:heartbeat - {player_count: "", phase: "", last_seen: ""}
+    let heartbeat_key = format!("match:{}:heartbeat", server_id);
+    let mut pipe = redis::pipe();
+    pipe.atomic()
+        .hset_multiple(
+            &heartbeat_key,
+            &[
+                ("player_count", player_count.to_string()),
+                ("phase", phase.clone()),
+                (
+                    "last_seen",
+                    lobby_controller::get_timestamp_secs().to_string(),
+                ),
+            ],
+        )
+        .expire(&heartbeat_key, *GAME_SERVER_HEARTBEAT_TIMEOUT_SECS);
+    if let Err(_) = pipe.query_async::<()>(&mut redis_conn).await {
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Error proccessing the request !",
+        )
+            .into_response();
+    }
+    //A warm server gets its match through the heartbeat response
+    let assignment = AsyncCommands::get_del::<_, Option<String>>(
+        &mut redis_conn,
+        format!("match:{}:assignment", server_id),
+    )
+    .await
+    .ok()
+    .flatten()
+    .and_then(|assignment_str| serde_json::from_str::<serde_json::Value>(&assignment_str).ok());
+    return (StatusCode::OK, Json(json!({ "assignment": assignment }))).into_response();
+}
+
+//Checks the secret a launched server got through its arguments
+pub async fn check_game_server_token(
+    server_id: &String,
+    token: &String,
+    mut redis_conn: MultiplexedConnection,
+) -> Result<(), (StatusCode, &'static str)> {
+    match AsyncCommands::get::<_, Option<String>>(
+        &mut redis_conn,
+        format!("match:{}:token", server_id),
+    )
+    .await
+    {
+        Ok(Some(server_token)) => {
+            if &server_token != token {
+                return Err((StatusCode::UNAUTHORIZED, "Invalid server token !"));
+            }
+            return Ok(());
+        }
+        Ok(None) => return Err((StatusCode::NOT_FOUND, "Match not found !")),
+        Err(_) => {
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Error proccessing the request !",
+            ));
+        }
+    }
+}
+
+//Match roster with the players' loadouts, fetched by the game server when it starts
+pub async fn get_match_roster(
+    State(app_state_): State<AppState>,
+    Query(query_payload): Query<HashMap<String, String>>,
+) -> impl IntoResponse {
+    let (Some(server_id), Some(token)) =
+        (query_payload.get("server_id"), query_payload.get("token"))
+    else {
+        return (StatusCode::BAD_REQUEST, "Missing server id or token !").into_response();
+    };
+    let mut redis_conn = app_state_.redis_conn.clone();
+    if let Err(err) = check_game_server_token(server_id, token, redis_conn.clone()).await {
+        return err.into_response();
+    }
+    match AsyncCommands::get::<_, Option<String>>(
+        &mut redis_conn,
+        format!("match:{}:roster", server_id),
+    )
+    .await
+    {
+        Ok(Some(roster_str)) => {
+            if let Ok(roster) = serde_json::from_str::<serde_json::Value>(&roster_str) {
+                return (StatusCode::OK, Json(roster)).into_response();
+            }
+        }
+        Ok(None) => return (StatusCode::NOT_FOUND, "Match not found !").into_response(),
+        Err(_) => {}
+    }
+    return (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "Error proccessing the request !",
+    )
+        .into_response();
+}
+
+pub async fn drop_game_server(
+    State(app_state_): State<AppState>,
+    Query(query_payload): Query<HashMap<String, String>>,
+) -> impl IntoResponse {
+    let (Some(server_id), Some(ready_token)) =
+        (query_payload.get("server_id"), query_payload.get("token"))
+    else {
+        return (StatusCode::BAD_REQUEST, "Missing server id or token !").into_response();
+    };
+    let mut redis_conn = app_state_.redis_conn.clone();
+    if let Err(err) = check_game_server_token(server_id, ready_token, redis_conn.clone()).await {
+        return err.into_response();
+    }
+    if release_match_lobbies(server_id, redis_conn.clone()).await {
+        //A reused server keeps authenticating with the same token
+        if warm_pool_controller::recycle_warm_server(&app_state_, server_id, ready_token).await {
+            return (StatusCode::CREATED, Json(json!({ "reuse": true }))).into_response();
+        }
+        let _ =
+            AsyncCommands::del::<_, ()>(&mut redis_conn, format!("match:{}:warm", server_id)).await;
+        //The instance that owns the process stops it if it's still running
+        let _ = AsyncCommands::publish::<_, _, ()>(
+            &mut redis_conn,
+            "drop_game_server_event",
+            server_id,
+        )
+        .await;
+        return (StatusCode::CREATED, Json(json!({ "reuse": false }))).into_response();
+    }
 
     return (
         StatusCode::INTERNAL_SERVER_ERROR,
@@ -205,3 +998,265 @@ pub async fn drop_game_server(
     )
         .into_response();
 }
+
+//Detaches every lobby of a match from its game server and puts them back to Ready
+pub async fn release_match_lobbies(
+    server_id: &String,
+    mut redis_conn: MultiplexedConnection,
+) -> bool {
+    let match_lobbies_key = format!("match:{}:lobbies", server_id);
+    let Ok(mut lobby_ids) =
+        AsyncCommands::smembers::<_, HashSet<String>>(&mut redis_conn, &match_lobbies_key).await
+    else {
+        return false;
+    };
+    //Servers started by a lobby leader use the lobby id as server id
+    if lobby_ids.is_empty() {
+        lobby_ids.insert(server_id.clone());
+    }
+    for lobby_id in lobby_ids.iter() {
+        let key_list = format!("lobby:{}", lobby_id);
+        let game_server_info_key = format!("game_server:{}", lobby_id);
+        let _ = lobby_controller::transition_lobby_status(
+            lobby_id,
+            LobbyStatus::Ready,
+            redis_conn.clone(),
+        )
+        .await;
+        let mut pipe = redis::pipe();
+        pipe.atomic().del(game_server_info_key).sdiff(&[
+            format!("{}:members", &key_list),
+            format!("{}:spectators", &key_list),
+        ]);
+        let Ok((_, member_set)) = pipe
+            .query_async::<((), HashSet<String>)>(&mut redis_conn)
+            .await
+        else {
+            return false;
+        };
+        for member in member_set {
+            let _ =
+                AsyncCommands::del::<_, ()>(&mut redis_conn, format!("character_info:{}", member))
+                    .await;
+        }
+    }
+    let mut pipe = redis::pipe();
+    pipe.del(&match_lobbies_key)
+        .del(format!("match:{}:roster", server_id))
+        .del(format!("match:{}:startup", server_id))
+        .del(format!("match:{}:token", server_id))
+        .del(format!("match:{}:heartbeat", server_id))
+        .del(format!("match:{}:assignment", server_id));
+    let _ = pipe.query_async::<()>(&mut redis_conn).await;
+    return true;
+}
+
+//Stops a tracked game server process once its match got dropped, servers on host agents get a stop command
+pub async fn stop_game_server_process(app_state_: &AppState, server_id: &String) {
+    let game_server_process = {
+        let mut game_server_exe_map_write = app_state_.game_server_exe_map.write().await;
+        game_server_exe_map_write.remove(server_id)
+    };
+    if game_server_process.is_none() {
+        let mut redis_conn = app_state_.redis_conn.clone();
+        if let Ok(Some(host_id)) = AsyncCommands::get::<_, Option<String>>(
+            &mut redis_conn,
+            format!("match:{}:host", server_id),
+        )
+        .await
+        {
+            let stop_command = HostAgentCommand::Stop {
+                server_id: server_id.clone(),
+            };
+            dispatch_host_command(&host_id, stop_command, redis_conn).await;
+            return;
+        }
+    }
+    if let Some(mut game_server_process) = game_server_process {
+        if let GameServerStatus::Running = app_state_
+            .game_server_launcher
+            .status(&mut game_server_process)
+        {
+            let _ = app_state_
+                .game_server_launcher
+                .stop(&mut game_server_process);
+        }
+    }
+    app_state_.game_server_ports.lock().await.release(server_id);
+}
+
+//Reaps exited game server processes. A server exiting while its match is still registered crashed
+pub async fn run_game_server_supervisor(app_state_: AppState) {
+    let mut interval =
+        tokio::time::interval(Duration::from_secs(GAME_SERVER_SUPERVISE_INTERVAL_SECS));
+    loop {
+        interval.tick().await;
+        let mut exited_servers = Vec::new();
+        {
+            let mut game_server_exe_map_write = app_state_.game_server_exe_map.write().await;
+            game_server_exe_map_write.retain(|server_id, game_server_process| {
+                match app_state_.game_server_launcher.status(game_server_process) {
+                    GameServerStatus::Exited(exit_code) => {
+                        exited_servers.push((server_id.clone(), exit_code));
+                        return false;
+                    }
+                    _ => return true,
+                }
+            });
+        }
+        for (server_id, exit_code) in exited_servers.iter() {
+            app_state_.game_server_ports.lock().await.release(server_id);
+            handle_game_server_exit(server_id, *exit_code, app_state_.redis_conn.clone()).await;
+        }
+        stop_hung_game_servers(&app_state_).await;
+    }
+}
+
+//Running servers past their startup whose heartbeat expired get killed and their lobbies released
+async fn stop_hung_game_servers(app_state_: &AppState) {
+    let mut server_ids: Vec<String> = {
+        let game_server_exe_map_read = app_state_.game_server_exe_map.read().await;
+        game_server_exe_map_read.keys().cloned().collect()
+    };
+    let mut redis_conn = app_state_.redis_conn.clone();
+    server_ids.extend(release_orphaned_remote_servers(redis_conn.clone()).await);
+    if server_ids.is_empty() {
+        return;
+    }
+    let mut pipe = redis::pipe();
+    for server_id in server_ids.iter() {
+        pipe.exists(format!("match:{}:lobbies", server_id))
+            .exists(format!("match:{}:startup", server_id))
+            .exists(format!("match:{}:heartbeat", server_id));
+    }
+    let Ok(server_states) = pipe.query_async::<Vec<bool>>(&mut redis_conn).await else {
+        return;
+    };
+    for (server_id, server_state) in server_ids.iter().zip(server_states.chunks(3)) {
+        let [in_match, starting, alive] = server_state else {
+            continue;
+        };
+        if !*in_match || *starting || *alive {
+            continue;
+        }
+        println!("Game server {:?} stopped sending heartbeats", server_id);
+        stop_game_server_process(app_state_, server_id).await;
+        let data_to_lobby = json!({
+            "resource": "game_server",
+            "action": "crashed",
+            "payload": {
+                "server_id": server_id,
+                "exit_code": null,
+                "reason": "Game server stopped responding !"
+            }
+        });
+        fail_match(server_id, &data_to_lobby, redis_conn.clone()).await;
+    }
+}
+
+//Fails the matches of host agents that went offline, returns the remote servers that still have a host
+async fn release_orphaned_remote_servers(mut redis_conn: MultiplexedConnection) -> Vec<String> {
+    let Ok(remote_server_ids) =
+        AsyncCommands::smembers::<_, Vec<String>>(&mut redis_conn, REMOTE_GAME_SERVERS_KEY).await
+    else {
+        return Vec::new();
+    };
+    let mut hosted_server_ids = Vec::new();
+    for server_id in remote_server_ids {
+        let host_alive = match AsyncCommands::get::<_, Option<String>>(
+            &mut redis_conn,
+            format!("match:{}:host", server_id),
+        )
+        .await
+        {
+            Ok(Some(host_id)) => {
+                AsyncCommands::exists::<_, bool>(&mut redis_conn, format!("host:{}", host_id))
+                    .await
+                    .unwrap_or(true)
+            }
+            Ok(None) => false,
+            Err(_) => true,
+        };
+        if host_alive {
+            hosted_server_ids.push(server_id);
+            continue;
+        }
+        println!("Host of game server {:?} went offline", server_id);
+        forget_remote_game_server(&server_id, redis_conn.clone()).await;
+        let data_to_lobby = json!({
+            "resource": "game_server",
+            "action": "crashed",
+            "payload": {
+                "server_id": server_id,
+                "exit_code": null,
+                "reason": "Game server host went offline !"
+            }
+        });
+        fail_match(&server_id, &data_to_lobby, redis_conn.clone()).await;
+    }
+    return hosted_server_ids;
+}
+
+async fn handle_game_server_exit(
+    server_id: &String,
+    exit_code: Option<i32>,
+    mut redis_conn: MultiplexedConnection,
+) {
+    println!(
+        "Game server {:?} exited with code {:?}",
+        server_id, exit_code
+    );
+    //match:lobby_haha:exit_code - exit code of the last process of that server, -1 when killed by a signal
+    let mut pipe = redis::pipe();
+    pipe.set_ex(
+        format!("match:{}:exit_code", server_id),
+        exit_code.unwrap_or(-1),
+        GAME_SERVER_EXIT_RECORD_TTL_SECS as u64,
+    )
+    .del(format!("match:{}:warm", server_id));
+    let _ = pipe.query_async::<()>(&mut redis_conn).await;
+    let data_to_lobby = json!({
+        "resource": "game_server",
+        "action": "crashed",
+        "payload": {
+            "server_id": server_id,
+            "exit_code": exit_code,
+            "reason": "Game server exited unexpectedly !"
+        }
+    });
+    fail_match(server_id, &data_to_lobby, redis_conn).await;
+}
+
+//Releases the lobbies of a match that ended abnormally and tells their members why.
+//A dropped match has no lobbies left, so there is nothing to do for it
+async fn fail_match(
+    server_id: &String,
+    data_to_lobby: &serde_json::Value,
+    mut redis_conn: MultiplexedConnection,
+) {
+    let Ok(lobby_ids) = AsyncCommands::smembers::<_, HashSet<String>>(
+        &mut redis_conn,
+        format!("match:{}:lobbies", server_id),
+    )
+    .await
+    else {
+        return;
+    };
+    if lobby_ids.is_empty() {
+        return;
+    }
+    if !release_match_lobbies(server_id, redis_conn.clone()).await {
+        return;
+    }
+    for lobby_id in lobby_ids.iter() {
+        if let Ok(member_set) = AsyncCommands::smembers::<_, HashSet<String>>(
+            &mut redis_conn,
+            format!("lobby:{}:members", lobby_id),
+        )
+        .await
+        {
+            lobby_controller::broadcast_to_lobby(&member_set, data_to_lobby, redis_conn.clone())
+                .await;
+        }
+    }
+}
@@ -15,8 +15,10 @@ use redis::{AsyncCommands, FromRedisValue, pipe};
 use serde_json::json;
 
 use crate::{
-    app_state::AppState, auth::AuthUser, global_vars::USERNAME_REGEX,
-    models::in_game::CharacterInfo,
+    app_state::AppState,
+    auth::AuthUser,
+    global_vars::USERNAME_REGEX,
+    models::{in_game::CharacterInfo, lobby::LobbyStatus},
 };
 
 // fn create_character_info_hash_fields(character_info: &CharacterInfo) -> Vec<(&str, String)> {
@@ -54,11 +56,20 @@ pub async fn save_character_stats(
     )
     .await
     {
+        if let Ok(true) = AsyncCommands::sismember::<_, _, bool>(
+            &mut redis_conn,
+            format!("lobby:{}:spectators", current_lobby_id),
+            &auth_user.username,
+        )
+        .await
+        {
+            return (StatusCode::BAD_REQUEST, "Spectators have no character !").into_response();
+        }
         let lobby_key_list = format!("lobby:{}", current_lobby_id);
         if let Ok(lobby_status) =
             AsyncCommands::hget::<_, _, String>(&mut redis_conn, lobby_key_list, "status").await
         {
-            if lobby_status == "In_Match" {
+            if lobby_status.parse::<LobbyStatus>() == Ok(LobbyStatus::InMatch) {
                 if let Ok(_) =
                     AsyncCommands::set::<_, _, ()>(&mut redis_conn, key_list, character_info_json)
                         .await
@@ -0,0 +1,152 @@
+use redis::{AsyncCommands, aio::MultiplexedConnection};
+use serde_json::{Value, json};
+use std::collections::{HashMap, HashSet};
+
+use axum::{Json, extract::State, http::StatusCode, response::IntoResponse};
+
+use crate::{
+    app_state::AppState,
+    auth::AuthUser,
+    controllers::lobby_controller,
+    global_vars::{GAME_CATALOG, USERNAME_REGEX},
+    models::{catalog::Loadout, lobby::LobbyStatus},
+};
+
+pub async fn get_catalog() -> impl IntoResponse {
+    return (StatusCode::OK, Json(GAME_CATALOG.clone())).into_response();
+}
+
+pub async fn set_loadout(
+    State(app_state_): State<AppState>,
+    claims: AuthUser,
+    Json(loadout): Json<Loadout>,
+) -> impl IntoResponse {
+    let username = &claims.username;
+
+    if !USERNAME_REGEX.is_match(username) {
+        return (StatusCode::BAD_REQUEST, "Invalid username format !").into_response();
+    }
+
+    if let Err(message) = GAME_CATALOG.validate_loadout(&loadout) {
+        return (StatusCode::BAD_REQUEST, message).into_response();
+    }
+
+    //lobby:lobby_haha:loadouts - {haha: loadout json}
+    let mut redis_conn = app_state_.redis_conn.clone();
+    if let Ok(lobby_id) =
+        AsyncCommands::get::<_, String>(&mut redis_conn, format!("user:{}:lobby", username)).await
+    {
+        let key_list = format!("lobby:{}", &lobby_id);
+        let mut pipe = redis::pipe();
+        pipe.hget(&key_list, "status")
+            .smembers(format!("{}:members", &key_list));
+        if let Ok((lobby_status, member_set)) = pipe
+            .query_async::<(String, HashSet<String>)>(&mut redis_conn)
+            .await
+        {
+            //Loadouts are locked once the match is on its way
+            if lobby_status.parse::<LobbyStatus>() != Ok(LobbyStatus::Ready) {
+                return (StatusCode::BAD_REQUEST, "Lobby busy !").into_response();
+            }
+            if lobby_controller::get_lobby_spectators(&lobby_id, redis_conn.clone())
+                .await
+                .contains(username)
+            {
+                return (StatusCode::BAD_REQUEST, "Spectators have no loadout !").into_response();
+            }
+            if let Ok(()) = AsyncCommands::hset::<_, _, _, ()>(
+                &mut redis_conn,
+                format!("{}:loadouts", &key_list),
+                username,
+                json!(loadout).to_string(),
+            )
+            .await
+            {
+                let data_to_lobby = json!({
+                    "resource": "lobby",
+                    "action": "loadout_update",
+                    "payload": {
+                        "username": username,
+                        "loadout": loadout
+                    }
+                });
+                lobby_controller::broadcast_to_lobby(
+                    &member_set,
+                    &data_to_lobby,
+                    redis_conn.clone(),
+                )
+                .await;
+                return (StatusCode::CREATED, Json(loadout)).into_response();
+            }
+        }
+    }
+    return (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "Error finishing the request, please try again !",
+    )
+        .into_response();
+}
+
+//Loadouts picked by the lobby members, members that haven't picked one are left out
+pub async fn get_lobby_loadouts(
+    lobby_id: &String,
+    mut redis_conn: MultiplexedConnection,
+) -> HashMap<String, Loadout> {
+    let Ok(loadouts) = AsyncCommands::hgetall::<_, HashMap<String, String>>(
+        &mut redis_conn,
+        format!("lobby:{}:loadouts", lobby_id),
+    )
+    .await
+    else {
+        return HashMap::new();
+    };
+    return loadouts
+        .into_iter()
+        .filter_map(|(member, loadout_str)| {
+            serde_json::from_str::<Loadout>(&loadout_str)
+                .ok()
+                .map(|loadout| (member, loadout))
+        })
+        .collect();
+}
+
+//Every player and spectator of the match's lobbies, with the loadout each player uses
+pub async fn build_match_roster(
+    lobby_ids: &Vec<String>,
+    mut redis_conn: MultiplexedConnection,
+) -> Vec<Value> {
+    let mut roster: Vec<Value> = Vec::new();
+    for lobby_id in lobby_ids.iter() {
+        let Ok(member_set) = AsyncCommands::smembers::<_, HashSet<String>>(
+            &mut redis_conn,
+            format!("lobby:{}:members", lobby_id),
+        )
+        .await
+        else {
+            continue;
+        };
+        let spectator_set =
+            lobby_controller::get_lobby_spectators(lobby_id, redis_conn.clone()).await;
+        let mut loadouts = get_lobby_loadouts(lobby_id, redis_conn.clone()).await;
+        for member in member_set.iter() {
+            if spectator_set.contains(member) {
+                roster.push(json!({
+                    "username": member,
+                    "lobby_id": lobby_id,
+                    "spectator": true
+                }));
+                continue;
+            }
+            let loadout = loadouts
+                .remove(member)
+                .or_else(|| GAME_CATALOG.default_loadout());
+            roster.push(json!({
+                "username": member,
+                "lobby_id": lobby_id,
+                "spectator": false,
+                "loadout": loadout
+            }));
+        }
+    }
+    return roster;
+}
@@ -0,0 +1,197 @@
+use redis::{AsyncCommands, aio::MultiplexedConnection};
+use serde_json::{Value, json};
+use std::{
+    collections::{HashMap, HashSet},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use axum::{Json, extract::State, http::StatusCode, response::IntoResponse};
+
+use crate::{
+    app_state::AppState,
+    auth::AuthUser,
+    controllers::lobby_controller,
+    global_vars::{LOBBY_CHAT_HISTORY_LEN, USERNAME_REGEX},
+};
+
+const MAX_MESSAGE_LEN: usize = 200;
+//At most CHAT_RATE_LIMIT messages per user every CHAT_RATE_WINDOW_SECS seconds
+const CHAT_RATE_LIMIT: u64 = 5;
+const CHAT_RATE_WINDOW_SECS: i64 = 10;
+
+pub async fn send_chat_message(
+    State(app_state_): State<AppState>,
+    claims: AuthUser,
+    Json(payload): Json<HashMap<String, String>>,
+) -> impl IntoResponse {
+    let username = &claims.username;
+
+    if !USERNAME_REGEX.is_match(username) {
+        return (StatusCode::BAD_REQUEST, "Invalid username format !").into_response();
+    }
+
+    let Some(message) = payload.get("message") else {
+        return (StatusCode::BAD_REQUEST, "Missing message !").into_response();
+    };
+
+    match send_lobby_chat_message(username, message, app_state_.redis_conn.clone()).await {
+        Ok(chat_message) => {
+            return (StatusCode::CREATED, Json(chat_message)).into_response();
+        }
+        Err((status_code, error_message)) => {
+            return (status_code, error_message).into_response();
+        }
+    }
+}
+
+pub async fn get_chat_history(
+    State(app_state_): State<AppState>,
+    claims: AuthUser,
+) -> impl IntoResponse {
+    let username = &claims.username;
+
+    if !USERNAME_REGEX.is_match(username) {
+        return (StatusCode::BAD_REQUEST, "Invalid username format !").into_response();
+    }
+
+    let mut redis_conn = app_state_.redis_conn.clone();
+    if let Ok(lobby_id) =
+        AsyncCommands::get::<_, String>(&mut redis_conn, format!("user:{}:lobby", username)).await
+    {
+        if let Some(chat_history) = get_lobby_chat_history(&lobby_id, redis_conn).await {
+            return (StatusCode::OK, Json(chat_history)).into_response();
+        }
+    }
+    return (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "Error finishing the request, please try again !",
+    )
+        .into_response();
+}
+
+//Validates a chat message, stores it in the lobby history and fans it out to every lobby member.
+//Used by both the REST endpoint and the web socket
+pub async fn send_lobby_chat_message(
+    username: &String,
+    message: &str,
+    mut redis_conn: MultiplexedConnection,
+) -> Result<Value, (StatusCode, &'static str)> {
+    let message = message.trim();
+    if message.is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "Message empty !"));
+    }
+    if message.chars().count() > MAX_MESSAGE_LEN {
+        return Err((StatusCode::BAD_REQUEST, "Message too long !"));
+    }
+    if message.chars().any(|character| character.is_control()) {
+        return Err((StatusCode::BAD_REQUEST, "Invalid message !"));
+    }
+
+    //chat_rate:haha - number of messages sent in the current window
+    let rate_key = format!("chat_rate:{}", username);
+    let Ok(sent_num) = AsyncCommands::incr::<_, _, u64>(&mut redis_conn, &rate_key, 1).await else {
+        return Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Error finishing the request, please try again !",
+        ));
+    };
+    if sent_num == 1 {
+        let _ =
+            AsyncCommands::expire::<_, ()>(&mut redis_conn, &rate_key, CHAT_RATE_WINDOW_SECS).await;
+    }
+    if sent_num > CHAT_RATE_LIMIT {
+        return Err((StatusCode::TOO_MANY_REQUESTS, "Sending messages too fast !"));
+    }
+
+    let Ok(lobby_id) =
+        AsyncCommands::get::<_, String>(&mut redis_conn, format!("user:{}:lobby", username)).await
+    else {
+        return Err((StatusCode::BAD_REQUEST, "Not in a lobby !"));
+    };
+    //lobby:lobby_haha:chat - [latest message, ..., oldest message]
+    let key_list = format!("lobby:{}", lobby_id);
+    let chat_message = json!({
+        "sender": username,
+        "message": message,
+        "sent_at": SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64
+    });
+    let mut pipe = redis::pipe();
+    pipe.atomic()
+        .lpush(format!("{}:chat", &key_list), chat_message.to_string())
+        .ltrim(
+            format!("{}:chat", &key_list),
+            0,
+            *LOBBY_CHAT_HISTORY_LEN as isize - 1,
+        )
+        .smembers(format!("{}:members", &key_list));
+    let Ok((_, _, member_set)) = pipe
+        .query_async::<((), (), HashSet<String>)>(&mut redis_conn)
+        .await
+    else {
+        return Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Error finishing the request, please try again !",
+        ));
+    };
+    let data_to_lobby = json!({
+        "resource": "lobby_chat",
+        "action": "receive",
+        "payload": chat_message
+    });
+    lobby_controller::broadcast_to_lobby(&member_set, &data_to_lobby, redis_conn.clone()).await;
+    return Ok(chat_message);
+}
+
+//Retained messages of a lobby, oldest first
+pub async fn get_lobby_chat_history(
+    lobby_id: &String,
+    mut redis_conn: MultiplexedConnection,
+) -> Option<Vec<Value>> {
+    let Ok(chat_history) = AsyncCommands::lrange::<_, Vec<String>>(
+        &mut redis_conn,
+        format!("lobby:{}:chat", lobby_id),
+        0,
+        -1,
+    )
+    .await
+    else {
+        return None;
+    };
+    return Some(
+        chat_history
+            .iter()
+            .rev()
+            .filter_map(|chat_message| serde_json::from_str::<Value>(chat_message).ok())
+            .collect(),
+    );
+}
+
+//Pushes the chat history of the user's lobby to the user, on joining a lobby or reconnecting
+pub async fn send_chat_history(username: &String, mut redis_conn: MultiplexedConnection) {
+    if let Ok(lobby_id) =
+        AsyncCommands::get::<_, String>(&mut redis_conn, format!("user:{}:lobby", username)).await
+    {
+        if let Some(chat_history) = get_lobby_chat_history(&lobby_id, redis_conn.clone()).await {
+            let data_to_user = json!({
+                "resource": "lobby_chat",
+                "action": "history",
+                "payload": {
+                    "messages": chat_history
+                }
+            });
+            let pub_sub_data_json = json!({
+                "username": username,
+                "data": data_to_user
+            });
+            let _ = AsyncCommands::publish::<_, _, ()>(
+                &mut redis_conn,
+                "web_socket_events",
+                pub_sub_data_json.to_string(),
+            )
+            .await;
+        }
+    }
+}
@@ -1,6 +1,11 @@
+use jsonwebtoken::{DecodingKey, EncodingKey, Header, Validation, decode, encode};
 use redis::{AsyncCommands, FromRedisValue, aio::MultiplexedConnection};
 use serde_json::json;
-use std::collections::{HashMap, HashSet};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::LazyLock,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
 use axum::{
     Json,
@@ -9,18 +14,49 @@ use axum::{
     response::IntoResponse,
 };
 
-use crate::{auth::AuthUser, models::lobby::LobbyInfo};
+use crate::{
+    auth::{AuthUser, LobbyInviteClaims, generate_random_code, get_jwt_secret},
+    controllers::{loadout_controller, lobby_chat_controller, matchmaking_controller},
+    models::{
+        catalog::MatchSettings,
+        game_server::GameServer,
+        lobby::{LobbyInfo, LobbyStatus},
+    },
+};
 
-use crate::global_vars::USERNAME_REGEX;
+use crate::global_vars::{
+    GAME_CATALOG, JOIN_CODE_REGEX, LEADER_HANDOVER_GRACE_SECS, LOBBY_BAN_SECS,
+    LOBBY_OFFLINE_GRACE_SECS, LOBBY_SPECTATOR_LIMIT, PUBLIC_API_URL, READY_CHECK_QUORUM,
+    USERNAME_REGEX, VOTE_KICK_TIMEOUT_SECS,
+};
 
 use crate::app_state::AppState;
 
+//Per-lobby keys (lobby:{id}:{suffix}) that have to follow the lobby when its id changes on leader handover
+const LOBBY_DATA_SUFFIXES: [&str; 12] = [
+    "ready",
+    "ready_check",
+    "join_requests",
+    "joined",
+    "chat",
+    "bans",
+    "vote_kick",
+    "vote_kick_votes",
+    "spectators",
+    "loadouts",
+    "match_settings",
+    "offline_since",
+];
+//Pending lobby invitations are dropped after this many seconds
+const LOBBY_INVITATION_TTL_SECS: i64 = 300;
+const LOBBY_SWEEP_INTERVAL_SECS: u64 = 30;
+
 fn create_lobby_info_hash_fields(lobby_info: &LobbyInfo) -> Vec<(&str, String)> {
     return vec![
         ("lobby_name", lobby_info.lobby_name.clone()),
         ("leader", lobby_info.leader.clone()),
         ("limit_num", lobby_info.limit_num.to_string().clone()),
-        ("status", lobby_info.status.clone()),
+        ("status", lobby_info.status.to_string()),
     ];
 }
 
@@ -41,18 +77,24 @@ pub async fn create_lobby(
         format!("{}'s lobby", username).as_str(),
         username,
         5,
-        "Ready",
+        LobbyStatus::Ready,
     );
     let mut pipe = redis::pipe();
     //lobby:lobby_haha - {name: "", leader: ""}
     //user:haha:lobby - lobby_haha
     //active_lobbies - lobby_haha
     //lobby:lobby_haha:members - haha
+    //lobby:lobby_haha:joined - [haha: joined_at]
     pipe.atomic()
         .hset_multiple(&key_list, &create_lobby_info_hash_fields(&lobby_info))
         .set(format!("user:{}:lobby", username), &lobby_id)
         .sadd("active_lobbies", &lobby_id)
-        .sadd(format!("{}:members", &key_list), username);
+        .sadd(format!("{}:members", &key_list), username)
+        .zadd(
+            format!("{}:joined", &key_list),
+            username,
+            get_join_timestamp(),
+        );
 
     if let Ok(()) = pipe.query_async(&mut redis_conn).await {
         return (StatusCode::CREATED, Json(lobby_info)).into_response();
@@ -65,6 +107,121 @@ pub async fn create_lobby(
         .into_response();
 }
 
+//Full state of the caller's lobby so clients can resynchronize after a reconnect or reload
+pub async fn get_lobby(State(app_state_): State<AppState>, claims: AuthUser) -> impl IntoResponse {
+    let username = &claims.username;
+
+    if !USERNAME_REGEX.is_match(username) {
+        return (StatusCode::BAD_REQUEST, "Invalid username format !").into_response();
+    }
+
+    let mut redis_conn = app_state_.redis_conn.clone();
+    let Ok(Some(lobby_id)) = AsyncCommands::get::<_, Option<String>>(
+        &mut redis_conn,
+        format!("user:{}:lobby", username),
+    )
+    .await
+    else {
+        return (StatusCode::NOT_FOUND, "Not in a lobby !").into_response();
+    };
+    let key_list = format!("lobby:{}", lobby_id);
+    let mut pipe = redis::pipe();
+    pipe.hgetall(&key_list)
+        .get(format!("game_server:{}", &lobby_id));
+    let Ok((lobby_info, game_server_info_str)) = pipe
+        .query_async::<(HashMap<String, String>, Option<String>)>(&mut redis_conn)
+        .await
+    else {
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Error finishing the request, please try again !",
+        )
+            .into_response();
+    };
+    if lobby_info.is_empty() {
+        return (StatusCode::NOT_FOUND, "Lobby not found !").into_response();
+    }
+    let Some((_, ready_set)) = get_lobby_ready_state(&lobby_id, redis_conn.clone()).await else {
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Error finishing the request, please try again !",
+        )
+            .into_response();
+    };
+    let spectator_set = get_lobby_spectators(&lobby_id, redis_conn.clone()).await;
+    let loadouts = loadout_controller::get_lobby_loadouts(&lobby_id, redis_conn.clone()).await;
+    let members_by_join_order = get_members_by_join_order(&lobby_id, redis_conn.clone()).await;
+    let online_users = get_online_members(&members_by_join_order, redis_conn.clone()).await;
+    let members: Vec<serde_json::Value> = members_by_join_order
+        .iter()
+        .map(|member| {
+            json!({
+                "username": member,
+                "online": online_users.contains(member),
+                "ready": ready_set.contains(member),
+                "spectator": spectator_set.contains(member),
+                "loadout": loadouts.get(member)
+            })
+        })
+        .collect();
+    let game_server = game_server_info_str.and_then(|game_server_info_str| {
+        serde_json::from_str::<GameServer>(&game_server_info_str).ok()
+    });
+    let invitations = get_pending_invitations(username, redis_conn.clone()).await;
+    let match_settings = get_lobby_match_settings(&lobby_id, redis_conn.clone()).await;
+
+    let response = json!({
+        "lobby_id": lobby_id,
+        "lobby_name": lobby_info.get("lobby_name"),
+        "leader": lobby_info.get("leader"),
+        "limit_num": lobby_info
+            .get("limit_num")
+            .and_then(|limit_num| limit_num.parse::<usize>().ok()),
+        "status": lobby_info.get("status"),
+        "members": members,
+        "game_server": game_server,
+        "match_settings": match_settings,
+        "invitations": invitations
+    });
+    return (StatusCode::OK, Json(response)).into_response();
+}
+
+//Senders of the lobby invitations the user hasn't answered yet, oldest first
+pub async fn get_pending_invitations(
+    username: &String,
+    mut redis_conn: MultiplexedConnection,
+) -> Vec<serde_json::Value> {
+    let invitations_key = format!("user:{}:lobby_invitations", username);
+    let expired_before = get_timestamp_secs() - LOBBY_INVITATION_TTL_SECS;
+    let mut pipe = redis::pipe();
+    pipe.atomic()
+        .zrembyscore(&invitations_key, "-inf", expired_before)
+        .zrange_withscores(&invitations_key, 0, -1);
+    let Ok((_, invitations)) = pipe
+        .query_async::<((), Vec<(String, i64)>)>(&mut redis_conn)
+        .await
+    else {
+        return Vec::new();
+    };
+    return invitations
+        .into_iter()
+        .map(|(sender, sent_at)| {
+            json!({
+                "sender": sender,
+                "sent_at": sent_at
+            })
+        })
+        .collect();
+}
+
+//Unix time in seconds, used for invitation and ban timestamps
+pub fn get_timestamp_secs() -> i64 {
+    return SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64;
+}
+
 pub async fn invite_to_lobby(
     State(app_state_): State<AppState>,
     claims: AuthUser,
@@ -110,6 +267,19 @@ pub async fn invite_to_lobby(
             if member_set.contains(request_receiver) {
                 return (StatusCode::BAD_REQUEST, "Already in lobby !").into_response();
             }
+            //user:haha:lobby_invitations - {sender: sent at (unix seconds)}
+            let invitations_key = format!("user:{}:lobby_invitations", request_receiver);
+            let mut pipe = redis::pipe();
+            pipe.atomic()
+                .zadd(&invitations_key, request_sender, get_timestamp_secs())
+                .expire(&invitations_key, LOBBY_INVITATION_TTL_SECS);
+            if let Err(_) = pipe.query_async::<()>(&mut redis_conn).await {
+                return (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Error finishing the request, please try again !",
+                )
+                    .into_response();
+            }
             let data_to_receiver = json!({
                 "resource": "lobby_invitation",
                 "action": "receive",
@@ -191,104 +361,72 @@ pub async fn accept_lobby_invitation(
         )
             .into_response();
     }
-    //lobby:lobby_haha - {name: "", leader: ""}
-    //user:haha:lobby - lobby_haha
-    //active_lobbies - [lobby_haha]
-    //lobby:lobby_haha:members - [haha]
     let mut redis_conn = app_state_.redis_conn.clone();
-    let mut pipe = redis::pipe();
+    let _ = AsyncCommands::zrem::<_, _, ()>(
+        &mut redis_conn,
+        format!("user:{}:lobby_invitations", request_receiver),
+        request_sender,
+    )
+    .await;
     if let Ok(target_lobby_id) =
         AsyncCommands::get::<_, String>(&mut redis_conn, format!("user:{}:lobby", &request_sender))
             .await
     {
-        let key_list = format!("lobby:{}", target_lobby_id);
-        if let Ok(lobby_info) =
-            AsyncCommands::hgetall::<_, HashMap<String, redis::Value>>(&mut redis_conn, &key_list)
-                .await
+        let as_spectator =
+            query_params.get("spectator").map(|value| value.as_str()) == Some("true");
+        match join_lobby_proccess(
+            request_receiver,
+            &target_lobby_id,
+            as_spectator,
+            redis_conn.clone(),
+        )
+        .await
         {
-            if let Some(lobby_status_redis) = lobby_info.get("status") {
-                if let Ok(lobby_status) = String::from_redis_value_ref(lobby_status_redis) {
-                    if lobby_status != "Ready" {
-                        return (
-                            StatusCode::BAD_REQUEST,
-                            Json(json!({
-                                "sender": request_sender,
-                                "message": "Lobby busy !"
-                            })),
-                        )
-                            .into_response();
+            Ok((lobby_info_response, member_set)) => {
+                for member in member_set.iter() {
+                    if member == request_receiver {
+                        continue;
                     }
-                }
-            }
-            //Get lobby members set
-            if let Ok(mut member_set) = AsyncCommands::smembers::<_, HashSet<String>>(
-                &mut redis_conn,
-                format!("{}:members", &key_list),
-            )
-            .await
-            {
-                if member_set.len() == 5 {
-                    return (
-                        StatusCode::BAD_REQUEST,
-                        Json(json!({
+                    let data_to_lobby = json!({
+                        "resource": "lobby_invitation",
+                        "action": "accept",
+                        "payload": {
                             "sender": request_sender,
-                            "message": "Lobby full !"
-                        })),
-                    )
-                        .into_response();
-                }
-                //Receiver leave current lobby first then join the new lobby
-                leave_lobby_proccess(request_receiver, redis_conn.clone()).await;
-                pipe.atomic()
-                    .set(format!("user:{}:lobby", request_receiver), &target_lobby_id)
-                    .sadd(format!("{}:members", &key_list), request_receiver);
-                if let Ok(_) = pipe.query_async::<()>(&mut redis_conn).await {
-                    for member in member_set.iter() {
-                        if member == request_receiver {
-                            continue;
+                            "receiver": request_receiver
                         }
-                        let data_to_lobby = json!({
-                            "resource": "lobby_invitation",
-                            "action": "accept",
-                            "payload": {
-                                "sender": request_sender,
-                                "receiver": request_receiver
-                            }
-                        });
-                        let pub_sub_data_json = json!({
-                            "username": member,
-                            "data": data_to_lobby
-                        });
-                        let _ = AsyncCommands::publish::<_, _, ()>(
-                            &mut redis_conn,
-                            "web_socket_events",
-                            pub_sub_data_json.to_string(),
-                        )
-                        .await;
-                    }
-                    member_set.insert(request_receiver.clone());
-                    let lobby_info_response = LobbyInfo::new(
-                        &String::from_redis_value(lobby_info.get("lobby_name").unwrap().clone())
-                            .unwrap(),
-                        &String::from_redis_value(lobby_info.get("leader").unwrap().clone())
-                            .unwrap(),
-                        usize::from_redis_value(lobby_info.get("limit_num").unwrap().clone())
-                            .unwrap(),
-                        &String::from_redis_value(lobby_info.get("status").unwrap().clone())
-                            .unwrap(),
-                    );
-                    let response = json!({
-                        "sender": request_sender,
-                        "lobby": {
-                            "lobby_name": lobby_info_response.lobby_name,
-                            "leader": lobby_info_response.leader,
-                            "limit_num": lobby_info_response.limit_num,
-                            "status": lobby_info_response.status,
-                            "members": member_set
-                        },
                     });
-                    return (StatusCode::CREATED, response.to_string()).into_response();
+                    let pub_sub_data_json = json!({
+                        "username": member,
+                        "data": data_to_lobby
+                    });
+                    let _ = AsyncCommands::publish::<_, _, ()>(
+                        &mut redis_conn,
+                        "web_socket_events",
+                        pub_sub_data_json.to_string(),
+                    )
+                    .await;
                 }
+                let response = json!({
+                    "sender": request_sender,
+                    "lobby": {
+                        "lobby_name": lobby_info_response.lobby_name,
+                        "leader": lobby_info_response.leader,
+                        "limit_num": lobby_info_response.limit_num,
+                        "status": lobby_info_response.status,
+                        "members": member_set
+                    },
+                });
+                return (StatusCode::CREATED, response.to_string()).into_response();
+            }
+            Err((status_code, message)) => {
+                return (
+                    status_code,
+                    Json(json!({
+                        "sender": request_sender,
+                        "message": message
+                    })),
+                )
+                    .into_response();
             }
         }
     }
@@ -329,6 +467,12 @@ pub async fn decline_lobby_invitation(
     }
 
     let mut redis_conn = app_state_.redis_conn.clone();
+    let _ = AsyncCommands::zrem::<_, _, ()>(
+        &mut redis_conn,
+        format!("user:{}:lobby_invitations", request_receiver),
+        &request_sender,
+    )
+    .await;
     let data_to_sender = json!({
         "resource": "lobby_invitation",
         "action": "decline",
@@ -380,8 +524,12 @@ pub async fn leave_lobby(
     //active_lobbies - [lobby_haha]
     //lobby:lobby_haha:members - [haha]
     let new_keylist = format!("lobby:{}", format!("lobby_{}", username));
-    let lobby_info_response =
-        LobbyInfo::new(&format!("{}'s lobby", username), username, 5, "Ready");
+    let lobby_info_response = LobbyInfo::new(
+        &format!("{}'s lobby", username),
+        username,
+        5,
+        LobbyStatus::Ready,
+    );
     pipe.atomic()
         .hset_multiple(
             &new_keylist,
@@ -392,7 +540,12 @@ pub async fn leave_lobby(
             format!("lobby_{}", username),
         )
         .sadd("active_lobbies", format!("lobby_{}", username))
-        .sadd(format!("{}:members", new_keylist), &username);
+        .sadd(format!("{}:members", new_keylist), &username)
+        .zadd(
+            format!("{}:joined", new_keylist),
+            &username,
+            get_join_timestamp(),
+        );
 
     if let Ok(()) = pipe.query_async(&mut redis_conn).await {
         let response = json!({
@@ -465,61 +618,21 @@ pub async fn make_leader(
                         return (StatusCode::BAD_REQUEST, "Target doesn't exist in lobby !")
                             .into_response();
                     }
-                    let mut pipe = redis::pipe();
-                    let new_lobby_id = format!("lobby_{}", request_receiver);
-                    let new_key_list = format!("lobby:{}", new_lobby_id);
-                    let lobby_info_response = LobbyInfo::new(
-                        &format!("{}'s lobby", request_receiver),
+                    if get_lobby_spectators(&lobby_id, redis_conn.clone())
+                        .await
+                        .contains(&request_receiver)
+                    {
+                        return (StatusCode::BAD_REQUEST, "Spectators can't lead the lobby !")
+                            .into_response();
+                    }
+                    if let Some(response) = make_leader_proccess(
+                        &lobby_id,
+                        request_sender,
                         &request_receiver,
-                        5,
-                        "Ready",
-                    );
-                    pipe.atomic()
-                        .del(&key_list)
-                        .hset_multiple(
-                            &new_key_list,
-                            &create_lobby_info_hash_fields(&lobby_info_response),
-                        )
-                        .srem("active_lobbies", &lobby_id)
-                        .sadd("active_lobbies", &new_lobby_id)
-                        .del(format!("{}:members", &key_list));
-                    if let Ok(_) = pipe.query_async::<()>(&mut redis_conn).await {
-                        let response = json!({
-                            "lobby_name": lobby_info_response.lobby_name,
-                            "leader": lobby_info_response.leader,
-                            "limit_num": lobby_info_response.limit_num,
-                            "status": lobby_info_response.status,
-                            "members": member_set
-                        });
-                        for member in member_set.iter() {
-                            pipe.atomic()
-                                .set(format!("user:{}:lobby", member), &new_lobby_id)
-                                .sadd(format!("{}:members", &new_key_list), &member);
-
-                            if let Ok(_) = pipe.query_async::<()>(&mut redis_conn).await {
-                                if member == request_sender {
-                                    continue;
-                                }
-                                let mut redis_conn = app_state_.redis_conn.clone();
-                                let data_to_lobby = json!({
-                                    "resource": "lobby",
-                                    "action": "make_leader",
-                                    "payload": {
-                                        "lobby": response
-                                    }
-                                });
-                                let pub_sub_data_json = json!({
-                                    "username": member,
-                                    "data": data_to_lobby
-                                });
-                                let _ = AsyncCommands::publish::<_, _, ()>(
-                                    &mut redis_conn,
-                                    "web_socket_events",
-                                    pub_sub_data_json.to_string(),
-                                )
-                                .await;
-                            }
-                        }
+                        redis_conn.clone(),
+                    )
+                    .await
+                    {
                         return (StatusCode::CREATED, Json(response)).into_response();
                     }
                 }
@@ -539,6 +652,354 @@ pub async fn make_leader(
         .into_response();
 }
 
+//Hands the lobby over to a new leader and notifies every other member. Lobby ids follow the leader, so the lobby
+//and its data move to lobby_{new_leader}. Returns the new lobby info with its members
+pub async fn make_leader_proccess(
+    lobby_id: &String,
+    old_leader: &String,
+    new_leader: &String,
+    mut redis_conn: MultiplexedConnection,
+) -> Option<serde_json::Value> {
+    let key_list = format!("lobby:{}", lobby_id);
+    let Ok(member_set) = AsyncCommands::smembers::<_, HashSet<String>>(
+        &mut redis_conn,
+        format!("{}:members", &key_list),
+    )
+    .await
+    else {
+        return None;
+    };
+    if !member_set.contains(new_leader) {
+        return None;
+    }
+    matchmaking_controller::cancel_matchmaking(
+        lobby_id,
+        "The lobby leader changed",
+        redis_conn.clone(),
+    )
+    .await;
+    //A lobby in a match stays in it
+    let lobby_status = get_lobby_status(lobby_id, redis_conn.clone())
+        .await
+        .unwrap_or(LobbyStatus::Ready);
+    let mut pipe = redis::pipe();
+    let new_lobby_id = format!("lobby_{}", new_leader);
+    let new_key_list = format!("lobby:{}", new_lobby_id);
+    let lobby_info_response = LobbyInfo::new(
+        &format!("{}'s lobby", new_leader),
+        new_leader,
+        5,
+        lobby_status,
+    );
+    pipe.atomic()
+        .del(&key_list)
+        .hset_multiple(
+            &new_key_list,
+            &create_lobby_info_hash_fields(&lobby_info_response),
+        )
+        .srem("active_lobbies", lobby_id)
+        .sadd("active_lobbies", &new_lobby_id)
+        .del(format!("{}:members", &key_list));
+    if let Err(_) = pipe.query_async::<()>(&mut redis_conn).await {
+        return None;
+    }
+    move_lobby_data(lobby_id, &new_lobby_id, redis_conn.clone()).await;
+    //Only happens when no player was left to take over
+    let _ = AsyncCommands::srem::<_, _, ()>(
+        &mut redis_conn,
+        format!("{}:spectators", &new_key_list),
+        new_leader,
+    )
+    .await;
+    let response = json!({
+        "lobby_name": lobby_info_response.lobby_name,
+        "leader": lobby_info_response.leader,
+        "limit_num": lobby_info_response.limit_num,
+        "status": lobby_info_response.status,
+        "members": member_set
+    });
+    for member in member_set.iter() {
+        let mut member_pipe = redis::pipe();
+        member_pipe
+            .atomic()
+            .set(format!("user:{}:lobby", member), &new_lobby_id)
+            .sadd(format!("{}:members", &new_key_list), &member);
+
+        if let Ok(_) = member_pipe.query_async::<()>(&mut redis_conn).await {
+            if member == old_leader {
+                continue;
+            }
+            let data_to_lobby = json!({
+                "resource": "lobby",
+                "action": "make_leader",
+                "payload": {
+                    "lobby": response
+                }
+            });
+            let pub_sub_data_json = json!({
+                "username": member,
+                "data": data_to_lobby
+            });
+            let _ = AsyncCommands::publish::<_, _, ()>(
+                &mut redis_conn,
+                "web_socket_events",
+                pub_sub_data_json.to_string(),
+            )
+            .await;
+        }
+    }
+    return Some(response);
+}
+
+//Called when the web socket of a user closes. A leader that doesn't reconnect within the grace period
+//hands the lobby over to its longest-standing online member
+pub async fn handle_user_disconnect(username: &String, mut redis_conn: MultiplexedConnection) {
+    //user:haha:disconnect - disconnect id, replaced on every disconnect and removed on reconnect
+    let disconnect_id = generate_random_code(8);
+    let mut pipe = redis::pipe();
+    pipe.atomic().del(get_presence_key(username)).set_ex(
+        format!("user:{}:disconnect", username),
+        &disconnect_id,
+        *LEADER_HANDOVER_GRACE_SECS + 60,
+    );
+    if let Err(_) = pipe.query_async::<()>(&mut redis_conn).await {
+        return;
+    }
+    let username = username.clone();
+    tokio::spawn(async move {
+        tokio::time::sleep(std::time::Duration::from_secs(*LEADER_HANDOVER_GRACE_SECS)).await;
+        hand_over_leadership(&username, &disconnect_id, redis_conn).await;
+    });
+}
+
+async fn hand_over_leadership(
+    username: &String,
+    disconnect_id: &String,
+    mut redis_conn: MultiplexedConnection,
+) {
+    //Skip if the user came back (or went through another disconnect) during the grace period
+    if let Ok(Some(current_disconnect_id)) = AsyncCommands::get::<_, Option<String>>(
+        &mut redis_conn,
+        format!("user:{}:disconnect", username),
+    )
+    .await
+    {
+        if &current_disconnect_id != disconnect_id {
+            return;
+        }
+    } else {
+        return;
+    }
+    if let Ok(true) =
+        AsyncCommands::exists::<_, bool>(&mut redis_conn, get_presence_key(username)).await
+    {
+        return;
+    }
+    let Ok(lobby_id) =
+        AsyncCommands::get::<_, String>(&mut redis_conn, format!("user:{}:lobby", username)).await
+    else {
+        return;
+    };
+    let Ok(Some(lobby_leader)) = AsyncCommands::hget::<_, _, Option<String>>(
+        &mut redis_conn,
+        format!("lobby:{}", &lobby_id),
+        "leader",
+    )
+    .await
+    else {
+        return;
+    };
+    if &lobby_leader != username {
+        return;
+    }
+    for member in get_leader_candidates(&lobby_id, redis_conn.clone())
+        .await
+        .iter()
+    {
+        if member == username {
+            continue;
+        }
+        if let Ok(true) =
+            AsyncCommands::exists::<_, bool>(&mut redis_conn, get_presence_key(member)).await
+        {
+            println!(
+                "Leader {:?} disconnected, lobby handed over to {:?}",
+                username, member
+            );
+            make_leader_proccess(&lobby_id, username, member, redis_conn.clone()).await;
+            return;
+        }
+    }
+}
+
+//user:haha:presence - set while the user has a web socket open or calls the API, expires PRESENCE_TTL_SECS after
+//the last sign of life so users of a crashed instance don't stay online
+pub const PRESENCE_TTL_SECS: u64 = 60;
+//How often an open web socket refreshes the presence of its user
+pub const PRESENCE_REFRESH_SECS: u64 = 20;
+
+fn get_presence_key(username: &String) -> String {
+    return format!("user:{}:presence", username);
+}
+
+pub async fn refresh_presence(username: &String, mut redis_conn: MultiplexedConnection) {
+    let _ = AsyncCommands::set_ex::<_, _, ()>(
+        &mut redis_conn,
+        get_presence_key(username),
+        1,
+        PRESENCE_TTL_SECS,
+    )
+    .await;
+}
+
+//The given users that are currently online
+pub async fn get_online_members(
+    members: &Vec<String>,
+    mut redis_conn: MultiplexedConnection,
+) -> HashSet<String> {
+    let mut pipe = redis::pipe();
+    for member in members.iter() {
+        pipe.exists(get_presence_key(member));
+    }
+    let Ok(presence) = pipe.query_async::<Vec<bool>>(&mut redis_conn).await else {
+        return HashSet::new();
+    };
+    return members
+        .iter()
+        .zip(presence)
+        .filter(|(_, is_online)| *is_online)
+        .map(|(member, _)| member.clone())
+        .collect();
+}
+
+//Background task expiring lobbies whose members have all been offline for longer than the grace period
+pub async fn run_lobby_sweeper(app_state_: AppState) {
+    let mut interval =
+        tokio::time::interval(std::time::Duration::from_secs(LOBBY_SWEEP_INTERVAL_SECS));
+    loop {
+        interval.tick().await;
+        sweep_offline_lobbies(app_state_.redis_conn.clone()).await;
+    }
+}
+
+async fn sweep_offline_lobbies(mut redis_conn: MultiplexedConnection) {
+    let Ok(lobby_ids) =
+        AsyncCommands::smembers::<_, HashSet<String>>(&mut redis_conn, "active_lobbies").await
+    else {
+        return;
+    };
+    let now = get_timestamp_secs();
+    for lobby_id in lobby_ids.iter() {
+        let key_list = format!("lobby:{}", lobby_id);
+        //lobby:lobby_haha:offline_since - unix seconds since when no member is online
+        let offline_since_key = format!("{}:offline_since", &key_list);
+        let Ok(member_set) = AsyncCommands::smembers::<_, HashSet<String>>(
+            &mut redis_conn,
+            format!("{}:members", &key_list),
+        )
+        .await
+        else {
+            continue;
+        };
+        let members: Vec<String> = member_set.iter().cloned().collect();
+        if !get_online_members(&members, redis_conn.clone())
+            .await
+            .is_empty()
+        {
+            let _ = AsyncCommands::del::<_, ()>(&mut redis_conn, &offline_since_key).await;
+            continue;
+        }
+        let mut pipe = redis::pipe();
+        pipe.set_nx(&offline_since_key, now).get(&offline_since_key);
+        let Ok((_, offline_since)) = pipe.query_async::<((), i64)>(&mut redis_conn).await else {
+            continue;
+        };
+        if now - offline_since < *LOBBY_OFFLINE_GRACE_SECS {
+            continue;
+        }
+        //A lobby getting or in a match is kept until its game server is dropped
+        if matches!(
+            get_lobby_status(lobby_id, redis_conn.clone()).await,
+            Some(LobbyStatus::Allocating | LobbyStatus::InMatch)
+        ) {
+            continue;
+        }
+        println!("Lobby {:?} expired, no member online", lobby_id);
+        expire_lobby(lobby_id, &member_set, redis_conn.clone()).await;
+    }
+}
+
+//Removes the lobby with all its data, members are left without a lobby until they log in again
+async fn expire_lobby(
+    lobby_id: &String,
+    member_set: &HashSet<String>,
+    mut redis_conn: MultiplexedConnection,
+) {
+    matchmaking_controller::cancel_matchmaking(lobby_id, "Lobby expired", redis_conn.clone()).await;
+    let key_list = format!("lobby:{}", lobby_id);
+    let mut pipe = redis::pipe();
+    pipe.atomic()
+        .srem("active_lobbies", lobby_id)
+        .del(&key_list)
+        .del(format!("{}:members", &key_list));
+    for suffix in LOBBY_DATA_SUFFIXES {
+        pipe.del(format!("{}:{}", &key_list, suffix));
+    }
+    let _ = pipe.query_async::<()>(&mut redis_conn).await;
+    for member in member_set.iter() {
+        //Only clear the pointer if the member hasn't moved to another lobby meanwhile
+        if let Ok(Some(member_lobby_id)) = AsyncCommands::get::<_, Option<String>>(
+            &mut redis_conn,
+            format!("user:{}:lobby", member),
+        )
+        .await
+        {
+            if &member_lobby_id == lobby_id {
+                let _ =
+                    AsyncCommands::del::<_, ()>(&mut redis_conn, format!("user:{}:lobby", member))
+                        .await;
+            }
+        }
+    }
+}
+
+//Score of a member in lobby:{id}:joined
+pub fn get_join_timestamp() -> u64 {
+    return SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64;
+}
+
+//Lobby members from the longest-standing to the latest joined
+pub async fn get_members_by_join_order(
+    lobby_id: &String,
+    mut redis_conn: MultiplexedConnection,
+) -> Vec<String> {
+    let key_list = format!("lobby:{}", lobby_id);
+    let mut pipe = redis::pipe();
+    pipe.zrange(format!("{}:joined", &key_list), 0, -1)
+        .smembers(format!("{}:members", &key_list));
+    let Ok((joined_members, member_set)) = pipe
+        .query_async::<(Vec<String>, HashSet<String>)>(&mut redis_conn)
+        .await
+    else {
+        return Vec::new();
+    };
+    let mut ordered_members: Vec<String> = joined_members
+        .into_iter()
+        .filter(|member| member_set.contains(member))
+        .collect();
+    //Members without a join time come last
+    let mut untracked_members: Vec<String> = member_set
+        .into_iter()
+        .filter(|member| !ordered_members.contains(member))
+        .collect();
+    untracked_members.sort();
+    ordered_members.append(&mut untracked_members);
+    return ordered_members;
+}
+
 pub async fn kick_member(
     State(app_state_): State<AppState>,
     claims: AuthUser,
@@ -573,7 +1034,6 @@ pub async fn kick_member(
     //active_lobbies - [lobby_haha]
     //lobby:lobby_haha:members - [haha]
     let mut redis_conn = app_state_.redis_conn.clone();
-    let mut pipe = redis::pipe();
     if let Ok(lobby_id) =
         AsyncCommands::get::<_, String>(&mut redis_conn, format!("user:{}:lobby", request_sender))
             .await
@@ -583,7 +1043,7 @@ pub async fn kick_member(
             AsyncCommands::hget::<_, _, String>(&mut redis_conn, &key_list, "leader").await
         {
             if &lobby_leader == request_sender {
-                if let Ok(mut member_set) = AsyncCommands::smembers::<_, HashSet<String>>(
+                if let Ok(member_set) = AsyncCommands::smembers::<_, HashSet<String>>(
                     &mut redis_conn,
                     format!("{}:members", &key_list),
                 )
@@ -594,78 +1054,1003 @@ pub async fn kick_member(
                             .into_response();
                     }
 
-                    let new_keylist_for_removed =
-                        format!("lobby:{}", format!("lobby_{}", request_receiver));
-                    let lobby_info_response = LobbyInfo::new(
-                        &format!("{}'s lobby", request_receiver),
+                    if kick_member_proccess(
+                        &lobby_id,
+                        request_sender,
                         &request_receiver,
-                        5,
-                        "Ready",
-                    );
-                    pipe.atomic()
-                        .srem(format!("{}:members", &key_list), &request_receiver)
-                        .hset_multiple(
-                            &new_keylist_for_removed,
-                            &create_lobby_info_hash_fields(&lobby_info_response),
-                        )
-                        .set(
-                            format!("user:{}:lobby", &request_receiver),
-                            format!("lobby_{}", request_receiver),
-                        )
-                        .sadd("active_lobbies", format!("lobby_{}", request_receiver))
-                        .sadd(
-                            format!("{}:members", &new_keylist_for_removed),
-                            &request_receiver,
-                        );
-                    if let Ok(_) = pipe.query_async::<()>(&mut redis_conn).await {
-                        member_set.remove(&request_receiver);
-                        let data_to_removed = json!({
-                            "resource": "lobby",
-                            "action": "is_kick",
-                            "payload": {
-                                "lobby": {
-                                    "lobby_name": lobby_info_response.lobby_name,
-                                    "leader": lobby_info_response.leader,
-                                    "limit_num": lobby_info_response.limit_num,
-                                    "status": lobby_info_response.status,
-                                    "members": [request_receiver]
-                                }
-                            }
-                        });
-                        let pub_sub_data_to_removed = json!({
-                            "username": request_receiver,
-                            "data": data_to_removed
-                        });
-                        let _ = AsyncCommands::publish::<_, _, ()>(
+                        &member_set,
+                        redis_conn.clone(),
+                    )
+                    .await
+                    {
+                        return (StatusCode::CREATED, request_receiver).into_response();
+                    }
+                }
+            } else {
+                return (
+                    StatusCode::UNAUTHORIZED,
+                    "No permission to perform the request !",
+                )
+                    .into_response();
+            }
+        }
+    }
+    return (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "Error finishing the request, please try again !",
+    )
+        .into_response();
+}
+
+//Starts a vote kick unless one is running: sets KEYS[1] to {vote_id, target, initiator} from ARGV[1..3] and
+//KEYS[2] to the initiator's vote, both expiring after ARGV[4] seconds. Returns 1 when started, 0 otherwise
+static VOTE_KICK_START_SCRIPT: LazyLock<redis::Script> = LazyLock::new(|| {
+    redis::Script::new(
+        r"
+        if redis.call('EXISTS', KEYS[1]) == 1 then
+            return 0
+        end
+        redis.call('HSET', KEYS[1], 'vote_id', ARGV[1], 'target', ARGV[2], 'initiator', ARGV[3])
+        redis.call('EXPIRE', KEYS[1], ARGV[4])
+        redis.call('DEL', KEYS[2])
+        redis.call('SADD', KEYS[2], ARGV[3])
+        redis.call('EXPIRE', KEYS[2], ARGV[4])
+        return 1
+        ",
+    )
+});
+
+pub async fn start_vote_kick(
+    State(app_state_): State<AppState>,
+    claims: AuthUser,
+    query_params: Query<HashMap<String, String>>,
+) -> impl IntoResponse {
+    if query_params.is_empty() {
+        return (StatusCode::BAD_REQUEST, "Params empty !").into_response();
+    }
+
+    let username = &claims.username;
+
+    if !USERNAME_REGEX.is_match(username) {
+        return (StatusCode::BAD_REQUEST, "Invalid username format !").into_response();
+    }
+
+    let Some(target) = query_params.get("target") else {
+        return (StatusCode::BAD_REQUEST, "Missing target !").into_response();
+    };
+    if !USERNAME_REGEX.is_match(target) {
+        return (StatusCode::BAD_REQUEST, "Invalid target username format !").into_response();
+    }
+    if target == username {
+        return (StatusCode::BAD_REQUEST, "Can't vote kick self !").into_response();
+    }
+
+    let mut redis_conn = app_state_.redis_conn.clone();
+    if let Ok(lobby_id) =
+        AsyncCommands::get::<_, String>(&mut redis_conn, format!("user:{}:lobby", username)).await
+    {
+        let key_list = format!("lobby:{}", &lobby_id);
+        let mut pipe = redis::pipe();
+        pipe.hget(&key_list, "leader")
+            .smembers(format!("{}:members", &key_list))
+            .sdiff(&[
+                format!("{}:members", &key_list),
+                format!("{}:spectators", &key_list),
+            ]);
+        if let Ok((lobby_leader, member_set, player_set)) = pipe
+            .query_async::<(String, HashSet<String>, HashSet<String>)>(&mut redis_conn)
+            .await
+        {
+            if !player_set.contains(username) {
+                return (StatusCode::BAD_REQUEST, "Spectators can't vote !").into_response();
+            }
+            if !member_set.contains(target) {
+                return (StatusCode::BAD_REQUEST, "Target doesn't exist in lobby !")
+                    .into_response();
+            }
+            if &lobby_leader == target {
+                return (StatusCode::BAD_REQUEST, "Can't vote kick the leader !").into_response();
+            }
+            //lobby:lobby_haha:vote_kick - {vote_id: "", target: "", initiator: ""}
+            //lobby:lobby_haha:vote_kick_votes - [haha]
+            let vote_id = generate_random_code(8);
+            let vote_kick_key = format!("{}:vote_kick", &key_list);
+            let votes_key = format!("{}:vote_kick_votes", &key_list);
+            //The vote and its expiry are set together, a vote left without one would block every later vote kick
+            match VOTE_KICK_START_SCRIPT
+                .key(&vote_kick_key)
+                .key(&votes_key)
+                .arg(&vote_id)
+                .arg(target)
+                .arg(username)
+                .arg(*VOTE_KICK_TIMEOUT_SECS)
+                .invoke_async::<usize>(&mut redis_conn)
+                .await
+            {
+                Ok(1) => {}
+                Ok(_) => {
+                    return (StatusCode::CONFLICT, "A vote kick is already in progress !")
+                        .into_response();
+                }
+                Err(_) => {
+                    return (
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        "Error finishing the request, please try again !",
+                    )
+                        .into_response();
+                }
+            }
+            let data_to_lobby = json!({
+                "resource": "lobby",
+                "action": "vote_kick_start",
+                "payload": {
+                    "target": target,
+                    "initiator": username,
+                    "votes_needed": get_vote_kick_threshold(&player_set, target),
+                    "timeout_secs": *VOTE_KICK_TIMEOUT_SECS
+                }
+            });
+            broadcast_to_lobby(&member_set, &data_to_lobby, redis_conn.clone()).await;
+            //A lobby of two is decided by the initiator's vote alone
+            let _ = tally_vote_kick(&lobby_id, redis_conn.clone()).await;
+            let timer_redis_conn = redis_conn.clone();
+            tokio::spawn(async move {
+                finish_vote_kick(lobby_id, vote_id, timer_redis_conn).await;
+            });
+            return (
+                StatusCode::CREATED,
+                Json(json!({
+                    "target": target,
+                    "timeout_secs": *VOTE_KICK_TIMEOUT_SECS
+                })),
+            )
+                .into_response();
+        }
+    }
+    return (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "Error finishing the request, please try again !",
+    )
+        .into_response();
+}
+
+pub async fn vote_kick(State(app_state_): State<AppState>, claims: AuthUser) -> impl IntoResponse {
+    let username = &claims.username;
+
+    if !USERNAME_REGEX.is_match(username) {
+        return (StatusCode::BAD_REQUEST, "Invalid username format !").into_response();
+    }
+
+    let mut redis_conn = app_state_.redis_conn.clone();
+    if let Ok(lobby_id) =
+        AsyncCommands::get::<_, String>(&mut redis_conn, format!("user:{}:lobby", username)).await
+    {
+        let key_list = format!("lobby:{}", &lobby_id);
+        let Ok(Some(target)) = AsyncCommands::hget::<_, _, Option<String>>(
+            &mut redis_conn,
+            format!("{}:vote_kick", &key_list),
+            "target",
+        )
+        .await
+        else {
+            return (StatusCode::NOT_FOUND, "No vote kick in progress !").into_response();
+        };
+        if &target == username {
+            return (StatusCode::BAD_REQUEST, "Can't vote on own kick !").into_response();
+        }
+        if get_lobby_spectators(&lobby_id, redis_conn.clone())
+            .await
+            .contains(username)
+        {
+            return (StatusCode::BAD_REQUEST, "Spectators can't vote !").into_response();
+        }
+        if let Ok(_) = AsyncCommands::sadd::<_, _, ()>(
+            &mut redis_conn,
+            format!("{}:vote_kick_votes", &key_list),
+            username,
+        )
+        .await
+        {
+            match tally_vote_kick(&lobby_id, redis_conn.clone()).await {
+                Some(is_kicked) => {
+                    return (
+                        StatusCode::CREATED,
+                        Json(json!({
+                            "target": target,
+                            "kicked": is_kicked
+                        })),
+                    )
+                        .into_response();
+                }
+                None => {}
+            }
+        }
+    }
+    return (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "Error finishing the request, please try again !",
+    )
+        .into_response();
+}
+
+//Majority of the players that can vote (every player except the target), spectators don't vote
+fn get_vote_kick_threshold(player_set: &HashSet<String>, target: &String) -> usize {
+    let voter_num = player_set.len() - usize::from(player_set.contains(target));
+    return voter_num / 2 + 1;
+}
+
+//Counts the votes of the running vote kick and kicks the target once a majority is reached.
+//Returns whether the target got kicked
+async fn tally_vote_kick(lobby_id: &String, mut redis_conn: MultiplexedConnection) -> Option<bool> {
+    let key_list = format!("lobby:{}", lobby_id);
+    let vote_kick_key = format!("{}:vote_kick", &key_list);
+    let votes_key = format!("{}:vote_kick_votes", &key_list);
+    let mut pipe = redis::pipe();
+    pipe.hgetall(&vote_kick_key)
+        .smembers(&votes_key)
+        .smembers(format!("{}:members", &key_list))
+        .sdiff(&[
+            format!("{}:members", &key_list),
+            format!("{}:spectators", &key_list),
+        ])
+        .hget(&key_list, "leader");
+    let Ok((vote_kick, votes, member_set, player_set, lobby_leader)) = pipe
+        .query_async::<(
+            HashMap<String, String>,
+            HashSet<String>,
+            HashSet<String>,
+            HashSet<String>,
+            String,
+        )>(&mut redis_conn)
+        .await
+    else {
+        return None;
+    };
+    let Some(target) = vote_kick.get("target") else {
+        return Some(false);
+    };
+    //Votes of players that left or started spectating meanwhile don't count
+    let vote_num = votes.intersection(&player_set).count();
+    let votes_needed = get_vote_kick_threshold(&player_set, target);
+    let data_to_lobby = json!({
+        "resource": "lobby",
+        "action": "vote_kick_update",
+        "payload": {
+            "target": target,
+            "votes": vote_num,
+            "votes_needed": votes_needed
+        }
+    });
+    broadcast_to_lobby(&member_set, &data_to_lobby, redis_conn.clone()).await;
+    if vote_num < votes_needed {
+        return Some(false);
+    }
+    //Only one caller gets to end the vote
+    let Ok(1) = AsyncCommands::del::<_, usize>(&mut redis_conn, &vote_kick_key).await else {
+        return Some(false);
+    };
+    let _ = AsyncCommands::del::<_, ()>(&mut redis_conn, &votes_key).await;
+    if !member_set.contains(target) {
+        return Some(false);
+    }
+    let data_to_lobby = json!({
+        "resource": "lobby",
+        "action": "vote_kick_passed",
+        "payload": {
+            "target": target,
+            "votes": vote_num
+        }
+    });
+    broadcast_to_lobby(&member_set, &data_to_lobby, redis_conn.clone()).await;
+    return Some(
+        kick_member_proccess(
+            lobby_id,
+            &lobby_leader,
+            target,
+            &member_set,
+            redis_conn.clone(),
+        )
+        .await,
+    );
+}
+
+//Waits for the vote kick to time out, then reports it as failed if it hasn't passed or been replaced meanwhile
+async fn finish_vote_kick(
+    lobby_id: String,
+    vote_id: String,
+    mut redis_conn: MultiplexedConnection,
+) {
+    tokio::time::sleep(std::time::Duration::from_secs(*VOTE_KICK_TIMEOUT_SECS)).await;
+    let key_list = format!("lobby:{}", &lobby_id);
+    let vote_kick_key = format!("{}:vote_kick", &key_list);
+    let Ok(vote_kick) =
+        AsyncCommands::hgetall::<_, HashMap<String, String>>(&mut redis_conn, &vote_kick_key).await
+    else {
+        return;
+    };
+    if vote_kick.get("vote_id") != Some(&vote_id) {
+        return;
+    }
+    let mut pipe = redis::pipe();
+    pipe.atomic()
+        .del(&vote_kick_key)
+        .del(format!("{}:vote_kick_votes", &key_list));
+    let _ = pipe.query_async::<()>(&mut redis_conn).await;
+    if let Ok(member_set) = AsyncCommands::smembers::<_, HashSet<String>>(
+        &mut redis_conn,
+        format!("{}:members", &key_list),
+    )
+    .await
+    {
+        let data_to_lobby = json!({
+            "resource": "lobby",
+            "action": "vote_kick_failed",
+            "payload": {
+                "target": vote_kick.get("target")
+            }
+        });
+        broadcast_to_lobby(&member_set, &data_to_lobby, redis_conn.clone()).await;
+    }
+}
+
+//Moves the kicked member back to their own lobby and bans them from the lobby for LOBBY_BAN_SECS.
+//member_set is the lobby member set before the kick
+pub async fn kick_member_proccess(
+    lobby_id: &String,
+    lobby_leader: &String,
+    kicked_member: &String,
+    member_set: &HashSet<String>,
+    mut redis_conn: MultiplexedConnection,
+) -> bool {
+    let key_list = format!("lobby:{}", lobby_id);
+    let new_keylist_for_removed = format!("lobby:{}", format!("lobby_{}", kicked_member));
+    let lobby_info_response = LobbyInfo::new(
+        &format!("{}'s lobby", kicked_member),
+        kicked_member,
+        5,
+        LobbyStatus::Ready,
+    );
+    //lobby:lobby_haha:bans - [hihi: banned until (unix seconds)]
+    let banned_until = get_timestamp_secs() + *LOBBY_BAN_SECS as i64;
+    let mut pipe = redis::pipe();
+    pipe.atomic()
+        .srem(format!("{}:members", &key_list), kicked_member)
+        .srem(format!("{}:ready", &key_list), kicked_member)
+        .srem(format!("{}:spectators", &key_list), kicked_member)
+        .hdel(format!("{}:loadouts", &key_list), kicked_member)
+        .zrem(format!("{}:joined", &key_list), kicked_member)
+        .zadd(format!("{}:bans", &key_list), kicked_member, banned_until)
+        .hset_multiple(
+            &new_keylist_for_removed,
+            &create_lobby_info_hash_fields(&lobby_info_response),
+        )
+        .set(
+            format!("user:{}:lobby", kicked_member),
+            format!("lobby_{}", kicked_member),
+        )
+        .sadd("active_lobbies", format!("lobby_{}", kicked_member))
+        .sadd(
+            format!("{}:members", &new_keylist_for_removed),
+            kicked_member,
+        )
+        .zadd(
+            format!("{}:joined", &new_keylist_for_removed),
+            kicked_member,
+            get_join_timestamp(),
+        );
+    if let Err(_) = pipe.query_async::<()>(&mut redis_conn).await {
+        return false;
+    }
+    //Old invitations from the lobby can't be used to come back
+    let mut pipe = redis::pipe();
+    for member in member_set.iter() {
+        pipe.zrem(format!("user:{}:lobby_invitations", kicked_member), member);
+    }
+    let _ = pipe.query_async::<()>(&mut redis_conn).await;
+
+    let data_to_removed = json!({
+        "resource": "lobby",
+        "action": "is_kick",
+        "payload": {
+            "lobby": {
+                "lobby_name": lobby_info_response.lobby_name,
+                "leader": lobby_info_response.leader,
+                "limit_num": lobby_info_response.limit_num,
+                "status": lobby_info_response.status,
+                "members": [kicked_member]
+            },
+            "banned_until": banned_until
+        }
+    });
+    let pub_sub_data_to_removed = json!({
+        "username": kicked_member,
+        "data": data_to_removed
+    });
+    let _ = AsyncCommands::publish::<_, _, ()>(
+        &mut redis_conn,
+        "web_socket_events",
+        pub_sub_data_to_removed.to_string(),
+    )
+    .await;
+    let mut remaining_members = member_set.clone();
+    remaining_members.remove(kicked_member);
+    for member in remaining_members.iter() {
+        if member == lobby_leader {
+            continue;
+        }
+        let lobby_info_for_member = LobbyInfo::new(
+            &format!("{}'s lobby", lobby_leader),
+            lobby_leader,
+            5,
+            LobbyStatus::Ready,
+        );
+        let data_to_lobby = json!({
+            "resource": "lobby",
+            "action": "kick_member",
+            "payload": {
+                "left_user": kicked_member,
+                "lobby": {
+                    "lobby_name": lobby_info_for_member.lobby_name,
+                    "leader": lobby_info_for_member.leader,
+                    "limit_num": lobby_info_for_member.limit_num,
+                    "status": lobby_info_for_member.status,
+                    "members": remaining_members
+                }
+            }
+        });
+        let pub_sub_data_json = json!({
+            "username": member,
+            "data": data_to_lobby
+        });
+        let _ = AsyncCommands::publish::<_, _, ()>(
+            &mut redis_conn,
+            "web_socket_events",
+            pub_sub_data_json.to_string(),
+        )
+        .await;
+    }
+    return true;
+}
+
+//Whether the user is still serving a ban from the lobby
+pub async fn is_banned_from_lobby(
+    username: &String,
+    lobby_id: &String,
+    mut redis_conn: MultiplexedConnection,
+) -> bool {
+    if let Ok(Some(banned_until)) = AsyncCommands::zscore::<_, _, Option<i64>>(
+        &mut redis_conn,
+        format!("lobby:{}:bans", lobby_id),
+        username,
+    )
+    .await
+    {
+        return banned_until > get_timestamp_secs();
+    }
+    return false;
+}
+
+pub async fn create_join_code(
+    State(app_state_): State<AppState>,
+    claims: AuthUser,
+    query_params: Query<HashMap<String, String>>,
+) -> impl IntoResponse {
+    let username = &claims.username;
+
+    if !USERNAME_REGEX.is_match(username) {
+        return (StatusCode::BAD_REQUEST, "Invalid username format !").into_response();
+    }
+
+    let mut expire_secs: u64 = 600;
+    if let Some(expire_secs_str) = query_params.get("expire_secs") {
+        match expire_secs_str.parse::<u64>() {
+            Ok(value) if value > 0 && value <= 24 * 3600 => expire_secs = value,
+            _ => return (StatusCode::BAD_REQUEST, "Invalid expire time !").into_response(),
+        }
+    }
+
+    let mut max_uses: u32 = 5;
+    if let Some(max_uses_str) = query_params.get("max_uses") {
+        match max_uses_str.parse::<u32>() {
+            Ok(value) if value > 0 && value <= 100 => max_uses = value,
+            _ => return (StatusCode::BAD_REQUEST, "Invalid max uses !").into_response(),
+        }
+    }
+
+    //lobby_code:ABCD1234 - {lobby_id: "lobby_haha", uses_left: 5} (expires after expire_secs)
+    let mut redis_conn = app_state_.redis_conn.clone();
+    if let Ok(lobby_id) =
+        AsyncCommands::get::<_, String>(&mut redis_conn, format!("user:{}:lobby", username)).await
+    {
+        let key_list = format!("lobby:{}", &lobby_id);
+        if let Ok(lobby_leader) =
+            AsyncCommands::hget::<_, _, String>(&mut redis_conn, &key_list, "leader").await
+        {
+            if &lobby_leader != username {
+                return (
+                    StatusCode::UNAUTHORIZED,
+                    "No permission to perform the request !",
+                )
+                    .into_response();
+            }
+            let lobby_code = generate_random_code(8);
+            let lobby_code_key = format!("lobby_code:{}", lobby_code);
+            let mut pipe = redis::pipe();
+            pipe.atomic()
+                .hset_multiple(
+                    &lobby_code_key,
+                    &[
+                        ("lobby_id", lobby_id.clone()),
+                        ("uses_left", max_uses.to_string()),
+                    ],
+                )
+                .expire(&lobby_code_key, expire_secs as i64);
+            if let Ok(()) = pipe.query_async(&mut redis_conn).await {
+                let expiration = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap()
+                    .as_secs() as usize
+                    + expire_secs as usize;
+                let invite_claims = LobbyInviteClaims {
+                    lobby_code: lobby_code.clone(),
+                    exp: expiration,
+                };
+                if let Ok(invite_token) = encode(
+                    &Header::default(),
+                    &invite_claims,
+                    &EncodingKey::from_secret(get_jwt_secret()),
+                ) {
+                    return (
+                        StatusCode::CREATED,
+                        Json(json!({
+                            "code": lobby_code,
+                            "invite_link": format!(
+                                "{}/lobby/join_link?token={}",
+                                PUBLIC_API_URL.as_str(),
+                                invite_token
+                            ),
+                            "expire_secs": expire_secs,
+                            "max_uses": max_uses
+                        })),
+                    )
+                        .into_response();
+                }
+            }
+        }
+    }
+    return (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "Error finishing the request, please try again !",
+    )
+        .into_response();
+}
+
+//Adds ARGV[1] to the uses left of join code KEYS[1] and returns them, nil when the code is gone.
+//Never recreates a code deleted by its last use or expired meanwhile
+static JOIN_CODE_USES_SCRIPT: LazyLock<redis::Script> = LazyLock::new(|| {
+    redis::Script::new(
+        r"
+        if redis.call('EXISTS', KEYS[1]) == 0 then
+            return false
+        end
+        return redis.call('HINCRBY', KEYS[1], 'uses_left', ARGV[1])
+        ",
+    )
+});
+
+fn decode_invite_token(invite_token: &str) -> Option<String> {
+    return decode::<LobbyInviteClaims>(
+        invite_token,
+        &DecodingKey::from_secret(get_jwt_secret()),
+        &Validation::default(),
+    )
+    .ok()
+    .map(|token_data| token_data.claims.lobby_code);
+}
+
+//Target of invite links, opened without a bearer token: shows the lobby behind the link and its join code.
+//The client then joins with /lobby/join_by_code?code= as the logged in user
+pub async fn open_join_link(
+    State(app_state_): State<AppState>,
+    query_params: Query<HashMap<String, String>>,
+) -> impl IntoResponse {
+    let Some(invite_token) = query_params.get("token") else {
+        return (StatusCode::BAD_REQUEST, "Missing invite token !").into_response();
+    };
+    let Some(lobby_code) = decode_invite_token(invite_token) else {
+        return (StatusCode::BAD_REQUEST, "Invalid or expired invite link !").into_response();
+    };
+    if !JOIN_CODE_REGEX.is_match(&lobby_code) {
+        return (StatusCode::BAD_REQUEST, "Invalid join code format !").into_response();
+    }
+
+    let mut redis_conn = app_state_.redis_conn.clone();
+    let Ok(lobby_id_opt) = AsyncCommands::hget::<_, _, Option<String>>(
+        &mut redis_conn,
+        format!("lobby_code:{}", lobby_code),
+        "lobby_id",
+    )
+    .await
+    else {
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Error proccessing the request !",
+        )
+            .into_response();
+    };
+    let Some(lobby_id) = lobby_id_opt else {
+        return (StatusCode::NOT_FOUND, "Join code invalid or expired !").into_response();
+    };
+    let key_list = format!("lobby:{}", &lobby_id);
+    let mut pipe = redis::pipe();
+    pipe.hget(&key_list, "lobby_name")
+        .hget(&key_list, "limit_num")
+        .scard(format!("{}:members", &key_list));
+    match pipe
+        .query_async::<(Option<String>, Option<usize>, usize)>(&mut redis_conn)
+        .await
+    {
+        Ok((Some(lobby_name), limit_num, member_num)) => {
+            return Json(json!({
+                "code": lobby_code,
+                "lobby_name": lobby_name,
+                "member_num": member_num,
+                "limit_num": limit_num
+            }))
+            .into_response();
+        }
+        Ok((None, _, _)) => {
+            return (StatusCode::NOT_FOUND, "Lobby doesn't exist !").into_response();
+        }
+        Err(_) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Error proccessing the request !",
+            )
+                .into_response();
+        }
+    }
+}
+
+pub async fn join_lobby_by_code(
+    State(app_state_): State<AppState>,
+    claims: AuthUser,
+    query_params: Query<HashMap<String, String>>,
+) -> impl IntoResponse {
+    if query_params.is_empty() {
+        return (StatusCode::BAD_REQUEST, "Params empty !").into_response();
+    }
+
+    let username = &claims.username;
+
+    if !USERNAME_REGEX.is_match(username) {
+        return (StatusCode::BAD_REQUEST, "Invalid username format !").into_response();
+    }
+
+    //Accept either the short code or the signed token of an invite link
+    let lobby_code: String;
+    if let Some(code) = query_params.get("code") {
+        lobby_code = code.to_ascii_uppercase();
+    } else if let Some(invite_token) = query_params.get("token") {
+        let Some(invite_code) = decode_invite_token(invite_token) else {
+            return (StatusCode::BAD_REQUEST, "Invalid or expired invite link !").into_response();
+        };
+        lobby_code = invite_code;
+    } else {
+        return (StatusCode::BAD_REQUEST, "Missing join code !").into_response();
+    }
+
+    if !JOIN_CODE_REGEX.is_match(&lobby_code) {
+        return (StatusCode::BAD_REQUEST, "Invalid join code format !").into_response();
+    }
+
+    let mut redis_conn = app_state_.redis_conn.clone();
+    let lobby_code_key = format!("lobby_code:{}", lobby_code);
+    if let Ok(target_lobby_id_opt) =
+        AsyncCommands::hget::<_, _, Option<String>>(&mut redis_conn, &lobby_code_key, "lobby_id")
+            .await
+    {
+        let Some(target_lobby_id) = target_lobby_id_opt else {
+            return (StatusCode::NOT_FOUND, "Join code invalid or expired !").into_response();
+        };
+        if let Ok(Some(current_lobby_id)) = AsyncCommands::get::<_, Option<String>>(
+            &mut redis_conn,
+            format!("user:{}:lobby", username),
+        )
+        .await
+        {
+            if current_lobby_id == target_lobby_id {
+                return (StatusCode::BAD_REQUEST, "Already in lobby !").into_response();
+            }
+        }
+        //Take a use up front so concurrent joins can't exceed max uses, give it back if joining fails
+        let Ok(uses_left_opt) = JOIN_CODE_USES_SCRIPT
+            .key(&lobby_code_key)
+            .arg(-1)
+            .invoke_async::<Option<i64>>(&mut redis_conn)
+            .await
+        else {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Error finishing the request, please try again !",
+            )
+                .into_response();
+        };
+        //Expired or used up since it was read
+        let Some(uses_left) = uses_left_opt else {
+            return (StatusCode::NOT_FOUND, "Join code invalid or expired !").into_response();
+        };
+        if uses_left < 0 {
+            let _ = AsyncCommands::del::<_, ()>(&mut redis_conn, &lobby_code_key).await;
+            return (StatusCode::NOT_FOUND, "Join code invalid or expired !").into_response();
+        }
+        let as_spectator =
+            query_params.get("spectator").map(|value| value.as_str()) == Some("true");
+        match join_lobby_proccess(username, &target_lobby_id, as_spectator, redis_conn.clone())
+            .await
+        {
+            Ok((lobby_info_response, member_set)) => {
+                if uses_left == 0 {
+                    let _ = AsyncCommands::del::<_, ()>(&mut redis_conn, &lobby_code_key).await;
+                }
+                for member in member_set.iter() {
+                    if member == username {
+                        continue;
+                    }
+                    let data_to_lobby = json!({
+                        "resource": "lobby",
+                        "action": "player_join",
+                        "payload": {
+                            "username": username
+                        }
+                    });
+                    let pub_sub_data_json = json!({
+                        "username": member,
+                        "data": data_to_lobby
+                    });
+                    let _ = AsyncCommands::publish::<_, _, ()>(
+                        &mut redis_conn,
+                        "web_socket_events",
+                        pub_sub_data_json.to_string(),
+                    )
+                    .await;
+                }
+                let response = json!({
+                    "code": lobby_code,
+                    "lobby": {
+                        "lobby_name": lobby_info_response.lobby_name,
+                        "leader": lobby_info_response.leader,
+                        "limit_num": lobby_info_response.limit_num,
+                        "status": lobby_info_response.status,
+                        "members": member_set
+                    },
+                });
+                return (StatusCode::CREATED, Json(response)).into_response();
+            }
+            Err((status_code, message)) => {
+                let _ = JOIN_CODE_USES_SCRIPT
+                    .key(&lobby_code_key)
+                    .arg(1)
+                    .invoke_async::<Option<i64>>(&mut redis_conn)
+                    .await;
+                return (status_code, message).into_response();
+            }
+        }
+    }
+    return (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "Error finishing the request, please try again !",
+    )
+        .into_response();
+}
+
+pub async fn send_join_request(
+    State(app_state_): State<AppState>,
+    claims: AuthUser,
+    query_params: Query<HashMap<String, String>>,
+) -> impl IntoResponse {
+    if query_params.is_empty() {
+        return (StatusCode::BAD_REQUEST, "Params empty !").into_response();
+    }
+    let request_sender = &claims.username;
+    let request_receiver: String;
+
+    if !USERNAME_REGEX.is_match(request_sender) {
+        return (StatusCode::BAD_REQUEST, "Invalid sender username format !").into_response();
+    }
+
+    if let Some(receiver_username) = query_params.get("receiver") {
+        request_receiver = receiver_username.clone();
+        if !USERNAME_REGEX.is_match(&request_receiver) {
+            return (
+                StatusCode::BAD_REQUEST,
+                "Invalid receiver username format !",
+            )
+                .into_response();
+        }
+    } else {
+        return (StatusCode::BAD_REQUEST, "Missing receiver !").into_response();
+    }
+
+    if request_sender == &request_receiver {
+        return (StatusCode::BAD_REQUEST, "Can't request to join self !").into_response();
+    }
+
+    //Only friends' lobbies can be requested
+    match sqlx::query(
+        "Select player1, player2 from friends where player1 = $1 and player2 = $2 or player1 = $2 and player2 = $1 ",
+    )
+    .bind(request_sender)
+    .bind(&request_receiver)
+    .fetch_optional(&app_state_.connection_pool)
+    .await
+    {
+        Ok(Some(_)) => {}
+        Ok(None) => {
+            return (StatusCode::BAD_REQUEST, "Not friends !").into_response();
+        }
+        Err(_) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Error finishing the request, please try again !",
+            )
+                .into_response();
+        }
+    }
+
+    //lobby:lobby_haha:join_requests - [keke] (expires after 10 minutes without new requests)
+    let mut redis_conn = app_state_.redis_conn.clone();
+    if let Ok(target_lobby_id) = AsyncCommands::get::<_, String>(
+        &mut redis_conn,
+        format!("user:{}:lobby", &request_receiver),
+    )
+    .await
+    {
+        let key_list = format!("lobby:{}", target_lobby_id);
+        if let Ok(Some(lobby_leader)) =
+            AsyncCommands::hget::<_, _, Option<String>>(&mut redis_conn, &key_list, "leader").await
+        {
+            if let Ok(true) = AsyncCommands::sismember::<_, _, bool>(
+                &mut redis_conn,
+                format!("{}:members", &key_list),
+                request_sender,
+            )
+            .await
+            {
+                return (StatusCode::BAD_REQUEST, "Already in lobby !").into_response();
+            }
+            let join_requests_key = format!("{}:join_requests", &key_list);
+            let mut pipe = redis::pipe();
+            pipe.atomic()
+                .sadd(&join_requests_key, request_sender)
+                .expire(&join_requests_key, 600);
+            if let Ok(()) = pipe.query_async(&mut redis_conn).await {
+                let data_to_leader = json!({
+                    "resource": "lobby",
+                    "action": "join_request",
+                    "payload": {
+                        "sender": request_sender,
+                        "lobby_id": target_lobby_id
+                    }
+                });
+                let pub_sub_data_json = json!({
+                    "username": lobby_leader,
+                    "data": data_to_leader
+                });
+                if let Ok(()) = AsyncCommands::publish(
+                    &mut redis_conn,
+                    "web_socket_events",
+                    pub_sub_data_json.to_string(),
+                )
+                .await
+                {
+                    return (StatusCode::CREATED, "Join request sent successfully !")
+                        .into_response();
+                }
+            }
+        }
+    }
+    return (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "Error finishing the request, please try again !",
+    )
+        .into_response();
+}
+
+pub async fn approve_join_request(
+    State(app_state_): State<AppState>,
+    claims: AuthUser,
+    query_params: Query<HashMap<String, String>>,
+) -> impl IntoResponse {
+    if query_params.is_empty() {
+        return (StatusCode::BAD_REQUEST, "Params empty !").into_response();
+    }
+    let lobby_leader = &claims.username;
+    let request_sender: String;
+
+    if !USERNAME_REGEX.is_match(lobby_leader) {
+        return (StatusCode::BAD_REQUEST, "Invalid username format !").into_response();
+    }
+
+    if let Some(sender_username) = query_params.get("sender") {
+        request_sender = sender_username.clone();
+        if !USERNAME_REGEX.is_match(&request_sender) {
+            return (StatusCode::BAD_REQUEST, "Invalid sender username format !").into_response();
+        }
+    } else {
+        return (StatusCode::BAD_REQUEST, "Missing sender !").into_response();
+    }
+
+    let mut redis_conn = app_state_.redis_conn.clone();
+    if let Ok(lobby_id) =
+        AsyncCommands::get::<_, String>(&mut redis_conn, format!("user:{}:lobby", lobby_leader))
+            .await
+    {
+        let key_list = format!("lobby:{}", &lobby_id);
+        if let Ok(current_leader) =
+            AsyncCommands::hget::<_, _, String>(&mut redis_conn, &key_list, "leader").await
+        {
+            if &current_leader != lobby_leader {
+                return (
+                    StatusCode::UNAUTHORIZED,
+                    "No permission to perform the request !",
+                )
+                    .into_response();
+            }
+            let join_requests_key = format!("{}:join_requests", &key_list);
+            if let Ok(has_request) = AsyncCommands::sismember::<_, _, bool>(
+                &mut redis_conn,
+                &join_requests_key,
+                &request_sender,
+            )
+            .await
+            {
+                if !has_request {
+                    return (
+                        StatusCode::BAD_REQUEST,
+                        "No join request from this player !",
+                    )
+                        .into_response();
+                }
+                //The request stays pending if the join fails, e.g. while the lobby is full or busy
+                match join_lobby_proccess(&request_sender, &lobby_id, false, redis_conn.clone())
+                    .await
+                {
+                    Ok((lobby_info_response, member_set)) => {
+                        let _ = AsyncCommands::srem::<_, _, ()>(
                             &mut redis_conn,
-                            "web_socket_events",
-                            pub_sub_data_to_removed.to_string(),
+                            &join_requests_key,
+                            &request_sender,
                         )
                         .await;
+                        let lobby_response = json!({
+                            "lobby_name": lobby_info_response.lobby_name,
+                            "leader": lobby_info_response.leader,
+                            "limit_num": lobby_info_response.limit_num,
+                            "status": lobby_info_response.status,
+                            "members": member_set
+                        });
                         for member in member_set.iter() {
-                            if member == request_sender || member == &request_receiver {
+                            if member == lobby_leader {
                                 continue;
                             }
-                            let lobby_info_for_member = LobbyInfo::new(
-                                &format!("{}'s lobby", request_sender),
-                                &request_sender,
-                                5,
-                                "Ready",
-                            );
-                            let data_to_lobby = json!({
-                                "resource": "lobby",
-                                "action": "kick_member",
-                                "payload": {
-                                    "left_user": request_receiver,
-                                    "lobby": {
-                                        "lobby_name": lobby_info_for_member.lobby_name,
-                                        "leader": lobby_info_for_member.leader,
-                                        "limit_num": lobby_info_for_member.limit_num,
-                                        "status": lobby_info_for_member.status,
-                                        "members": member_set
+                            let data_to_lobby = if member == &request_sender {
+                                json!({
+                                    "resource": "lobby",
+                                    "action": "join_request_approve",
+                                    "payload": {
+                                        "lobby": lobby_response
                                     }
-                                }
-                            });
+                                })
+                            } else {
+                                json!({
+                                    "resource": "lobby",
+                                    "action": "player_join",
+                                    "payload": {
+                                        "username": request_sender
+                                    }
+                                })
+                            };
                             let pub_sub_data_json = json!({
                                 "username": member,
                                 "data": data_to_lobby
@@ -677,16 +2062,510 @@ pub async fn kick_member(
                             )
                             .await;
                         }
-                        return (StatusCode::CREATED, request_receiver).into_response();
+                        return (StatusCode::CREATED, Json(lobby_response)).into_response();
+                    }
+                    Err((status_code, message)) => {
+                        return (status_code, message).into_response();
+                    }
+                }
+            }
+        }
+    }
+    return (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "Error finishing the request, please try again !",
+    )
+        .into_response();
+}
+
+pub async fn reject_join_request(
+    State(app_state_): State<AppState>,
+    claims: AuthUser,
+    query_params: Query<HashMap<String, String>>,
+) -> impl IntoResponse {
+    if query_params.is_empty() {
+        return (StatusCode::BAD_REQUEST, "Params empty !").into_response();
+    }
+    let lobby_leader = &claims.username;
+    let request_sender: String;
+
+    if !USERNAME_REGEX.is_match(lobby_leader) {
+        return (StatusCode::BAD_REQUEST, "Invalid username format !").into_response();
+    }
+
+    if let Some(sender_username) = query_params.get("sender") {
+        request_sender = sender_username.clone();
+        if !USERNAME_REGEX.is_match(&request_sender) {
+            return (StatusCode::BAD_REQUEST, "Invalid sender username format !").into_response();
+        }
+    } else {
+        return (StatusCode::BAD_REQUEST, "Missing sender !").into_response();
+    }
+
+    let mut redis_conn = app_state_.redis_conn.clone();
+    if let Ok(lobby_id) =
+        AsyncCommands::get::<_, String>(&mut redis_conn, format!("user:{}:lobby", lobby_leader))
+            .await
+    {
+        let key_list = format!("lobby:{}", &lobby_id);
+        if let Ok(current_leader) =
+            AsyncCommands::hget::<_, _, String>(&mut redis_conn, &key_list, "leader").await
+        {
+            if &current_leader != lobby_leader {
+                return (
+                    StatusCode::UNAUTHORIZED,
+                    "No permission to perform the request !",
+                )
+                    .into_response();
+            }
+            if let Ok(removed_num) = AsyncCommands::srem::<_, _, usize>(
+                &mut redis_conn,
+                format!("{}:join_requests", &key_list),
+                &request_sender,
+            )
+            .await
+            {
+                if removed_num == 0 {
+                    return (
+                        StatusCode::BAD_REQUEST,
+                        "No join request from this player !",
+                    )
+                        .into_response();
+                }
+                let data_to_sender = json!({
+                    "resource": "lobby",
+                    "action": "join_request_reject",
+                    "payload": {
+                        "leader": lobby_leader,
+                        "lobby_id": lobby_id
+                    }
+                });
+                let pub_sub_data_json = json!({
+                    "username": request_sender,
+                    "data": data_to_sender
+                });
+                if let Ok(()) = AsyncCommands::publish(
+                    &mut redis_conn,
+                    "web_socket_events",
+                    pub_sub_data_json.to_string(),
+                )
+                .await
+                {
+                    return (StatusCode::CREATED, request_sender).into_response();
+                }
+            }
+        }
+    }
+    return (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "Error finishing the request, please try again !",
+    )
+        .into_response();
+}
+
+pub async fn set_ready(
+    State(app_state_): State<AppState>,
+    claims: AuthUser,
+    query_params: Query<HashMap<String, String>>,
+) -> impl IntoResponse {
+    if query_params.is_empty() {
+        return (StatusCode::BAD_REQUEST, "Params empty !").into_response();
+    }
+    let username = &claims.username;
+
+    if !USERNAME_REGEX.is_match(username) {
+        return (StatusCode::BAD_REQUEST, "Invalid username format !").into_response();
+    }
+
+    let is_ready = match query_params.get("ready").map(|value| value.as_str()) {
+        Some("true") => true,
+        Some("false") => false,
+        _ => return (StatusCode::BAD_REQUEST, "Invalid ready value !").into_response(),
+    };
+
+    //lobby:lobby_haha:ready - [haha, keke]
+    //lobby:lobby_haha:ready_check - check id, deleted by whoever finishes the check (expires shortly after it times out)
+    let mut redis_conn = app_state_.redis_conn.clone();
+    if let Ok(lobby_id) =
+        AsyncCommands::get::<_, String>(&mut redis_conn, format!("user:{}:lobby", username)).await
+    {
+        let key_list = format!("lobby:{}", &lobby_id);
+        if let Ok(lobby_status) =
+            AsyncCommands::hget::<_, _, String>(&mut redis_conn, &key_list, "status").await
+        {
+            if lobby_status.parse::<LobbyStatus>() != Ok(LobbyStatus::Ready) {
+                return (StatusCode::BAD_REQUEST, "Lobby busy !").into_response();
+            }
+            if get_lobby_spectators(&lobby_id, redis_conn.clone())
+                .await
+                .contains(username)
+            {
+                return (StatusCode::BAD_REQUEST, "Spectators can't ready up !").into_response();
+            }
+            let ready_key = format!("{}:ready", &key_list);
+            let update_result = if is_ready {
+                AsyncCommands::sadd::<_, _, ()>(&mut redis_conn, &ready_key, username).await
+            } else {
+                AsyncCommands::srem::<_, _, ()>(&mut redis_conn, &ready_key, username).await
+            };
+            if let Ok(()) = update_result {
+                if let Some((member_set, ready_set)) =
+                    get_lobby_ready_state(&lobby_id, redis_conn.clone()).await
+                {
+                    let data_to_lobby = json!({
+                        "resource": "lobby",
+                        "action": "ready_update",
+                        "payload": {
+                            "username": username,
+                            "ready": is_ready,
+                            "ready_members": ready_set
+                        }
+                    });
+                    broadcast_to_lobby(&member_set, &data_to_lobby, redis_conn.clone()).await;
+                    //Finish a running ready check early once everybody is ready
+                    if ready_set.len() == member_set.len() {
+                        if let Ok(1) = AsyncCommands::del::<_, usize>(
+                            &mut redis_conn,
+                            format!("{}:ready_check", &key_list),
+                        )
+                        .await
+                        {
+                            let data_to_lobby = json!({
+                                "resource": "lobby",
+                                "action": "ready_check_complete",
+                                "payload": {
+                                    "ready_members": ready_set
+                                }
+                            });
+                            broadcast_to_lobby(&member_set, &data_to_lobby, redis_conn.clone())
+                                .await;
+                        }
                     }
+                    return (
+                        StatusCode::CREATED,
+                        Json(json!({
+                            "username": username,
+                            "ready": is_ready,
+                            "ready_members": ready_set
+                        })),
+                    )
+                        .into_response();
+                }
+            }
+        }
+    }
+    return (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "Error finishing the request, please try again !",
+    )
+        .into_response();
+}
+
+//Switches the caller between a player slot and a spectator slot of their current lobby
+pub async fn set_spectator(
+    State(app_state_): State<AppState>,
+    claims: AuthUser,
+    query_params: Query<HashMap<String, String>>,
+) -> impl IntoResponse {
+    if query_params.is_empty() {
+        return (StatusCode::BAD_REQUEST, "Params empty !").into_response();
+    }
+    let username = &claims.username;
+
+    if !USERNAME_REGEX.is_match(username) {
+        return (StatusCode::BAD_REQUEST, "Invalid username format !").into_response();
+    }
+
+    let is_spectator = match query_params.get("spectator").map(|value| value.as_str()) {
+        Some("true") => true,
+        Some("false") => false,
+        _ => return (StatusCode::BAD_REQUEST, "Invalid spectator value !").into_response(),
+    };
+
+    //lobby:lobby_haha:spectators - [keke]
+    let mut redis_conn = app_state_.redis_conn.clone();
+    if let Ok(lobby_id) =
+        AsyncCommands::get::<_, String>(&mut redis_conn, format!("user:{}:lobby", username)).await
+    {
+        let key_list = format!("lobby:{}", &lobby_id);
+        let spectators_key = format!("{}:spectators", &key_list);
+        let mut pipe = redis::pipe();
+        pipe.hgetall(&key_list)
+            .smembers(format!("{}:members", &key_list))
+            .smembers(&spectators_key);
+        if let Ok((lobby_info, member_set, spectator_set)) = pipe
+            .query_async::<(HashMap<String, String>, HashSet<String>, HashSet<String>)>(
+                &mut redis_conn,
+            )
+            .await
+        {
+            if lobby_info
+                .get("status")
+                .and_then(|status| status.parse::<LobbyStatus>().ok())
+                != Some(LobbyStatus::Ready)
+            {
+                return (StatusCode::BAD_REQUEST, "Lobby busy !").into_response();
+            }
+            if spectator_set.contains(username) == is_spectator {
+                return (StatusCode::BAD_REQUEST, "Role unchanged !").into_response();
+            }
+            //The leader starts the match and always counts as a ready player
+            if is_spectator && lobby_info.get("leader") == Some(username) {
+                return (StatusCode::BAD_REQUEST, "The leader can't spectate !").into_response();
+            }
+            let spectator_num = spectator_set.intersection(&member_set).count();
+            let mut pipe = redis::pipe();
+            if is_spectator {
+                if spectator_num >= *LOBBY_SPECTATOR_LIMIT {
+                    return (StatusCode::BAD_REQUEST, "Spectator slots full !").into_response();
                 }
+                pipe.atomic()
+                    .sadd(&spectators_key, username)
+                    .srem(format!("{}:ready", &key_list), username);
             } else {
+                let limit_num = lobby_info
+                    .get("limit_num")
+                    .and_then(|limit_num| limit_num.parse::<usize>().ok())
+                    .unwrap_or(0);
+                if member_set.len() - spectator_num >= limit_num {
+                    return (StatusCode::BAD_REQUEST, "Lobby full !").into_response();
+                }
+                pipe.atomic().srem(&spectators_key, username);
+            }
+            if let Ok(()) = pipe.query_async(&mut redis_conn).await {
+                let data_to_lobby = json!({
+                    "resource": "lobby",
+                    "action": "spectator_update",
+                    "payload": {
+                        "username": username,
+                        "spectator": is_spectator
+                    }
+                });
+                broadcast_to_lobby(&member_set, &data_to_lobby, redis_conn.clone()).await;
+                return (
+                    StatusCode::CREATED,
+                    Json(json!({
+                        "username": username,
+                        "spectator": is_spectator
+                    })),
+                )
+                    .into_response();
+            }
+        }
+    }
+    return (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "Error finishing the request, please try again !",
+    )
+        .into_response();
+}
+
+//Leader picks the game mode and map of the lobby's next match
+pub async fn set_match_settings(
+    State(app_state_): State<AppState>,
+    claims: AuthUser,
+    query_params: Query<HashMap<String, String>>,
+) -> impl IntoResponse {
+    if query_params.is_empty() {
+        return (StatusCode::BAD_REQUEST, "Params empty !").into_response();
+    }
+    let username = &claims.username;
+
+    if !USERNAME_REGEX.is_match(username) {
+        return (StatusCode::BAD_REQUEST, "Invalid username format !").into_response();
+    }
+
+    let (Some(game_mode), Some(map)) = (query_params.get("game_mode"), query_params.get("map"))
+    else {
+        return (StatusCode::BAD_REQUEST, "Missing game mode or map !").into_response();
+    };
+    let match_settings = MatchSettings {
+        game_mode: game_mode.clone(),
+        map: map.clone(),
+    };
+    let game_mode_entry = match GAME_CATALOG.validate_match_settings(&match_settings) {
+        Ok(game_mode_entry) => game_mode_entry,
+        Err(message) => return (StatusCode::BAD_REQUEST, message).into_response(),
+    };
+
+    //lobby:lobby_haha:match_settings - {game_mode: "", map: ""}
+    let mut redis_conn = app_state_.redis_conn.clone();
+    if let Ok(lobby_id) =
+        AsyncCommands::get::<_, String>(&mut redis_conn, format!("user:{}:lobby", username)).await
+    {
+        let key_list = format!("lobby:{}", &lobby_id);
+        if let Ok(lobby_info) =
+            AsyncCommands::hgetall::<_, HashMap<String, String>>(&mut redis_conn, &key_list).await
+        {
+            if lobby_info.get("leader") != Some(username) {
+                return (
+                    StatusCode::UNAUTHORIZED,
+                    "No permission to perform the request !",
+                )
+                    .into_response();
+            }
+            if lobby_info
+                .get("status")
+                .and_then(|status| status.parse::<LobbyStatus>().ok())
+                != Some(LobbyStatus::Ready)
+            {
+                return (StatusCode::BAD_REQUEST, "Lobby busy !").into_response();
+            }
+            if let Some((player_set, _)) =
+                get_lobby_ready_state(&lobby_id, redis_conn.clone()).await
+            {
+                if player_set.len() > game_mode_entry.max_players {
+                    return (StatusCode::BAD_REQUEST, "Too many players for game mode !")
+                        .into_response();
+                }
+            }
+            if let Ok(()) = AsyncCommands::hset_multiple::<_, _, _, ()>(
+                &mut redis_conn,
+                format!("{}:match_settings", &key_list),
+                &[("game_mode", game_mode), ("map", map)],
+            )
+            .await
+            {
+                if let Ok(member_set) = AsyncCommands::smembers::<_, HashSet<String>>(
+                    &mut redis_conn,
+                    format!("{}:members", &key_list),
+                )
+                .await
+                {
+                    let data_to_lobby = json!({
+                        "resource": "lobby",
+                        "action": "match_settings_update",
+                        "payload": {
+                            "match_settings": match_settings
+                        }
+                    });
+                    broadcast_to_lobby(&member_set, &data_to_lobby, redis_conn.clone()).await;
+                }
+                return (StatusCode::CREATED, Json(match_settings)).into_response();
+            }
+        }
+    }
+    return (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "Error finishing the request, please try again !",
+    )
+        .into_response();
+}
+
+//Match settings picked by the lobby leader, or the catalog defaults if none were picked or they're no longer valid
+pub async fn get_lobby_match_settings(
+    lobby_id: &String,
+    mut redis_conn: MultiplexedConnection,
+) -> MatchSettings {
+    if let Ok(stored_settings) = AsyncCommands::hgetall::<_, HashMap<String, String>>(
+        &mut redis_conn,
+        format!("lobby:{}:match_settings", lobby_id),
+    )
+    .await
+    {
+        if let (Some(game_mode), Some(map)) =
+            (stored_settings.get("game_mode"), stored_settings.get("map"))
+        {
+            let match_settings = MatchSettings {
+                game_mode: game_mode.clone(),
+                map: map.clone(),
+            };
+            if GAME_CATALOG
+                .validate_match_settings(&match_settings)
+                .is_ok()
+            {
+                return match_settings;
+            }
+        }
+    }
+    //The catalog is checked on startup to have defaults
+    return GAME_CATALOG.default_match_settings().unwrap();
+}
+
+pub async fn start_ready_check(
+    State(app_state_): State<AppState>,
+    claims: AuthUser,
+    query_params: Query<HashMap<String, String>>,
+) -> impl IntoResponse {
+    let username = &claims.username;
+
+    if !USERNAME_REGEX.is_match(username) {
+        return (StatusCode::BAD_REQUEST, "Invalid username format !").into_response();
+    }
+
+    let mut timeout_secs: u64 = 30;
+    if let Some(timeout_secs_str) = query_params.get("timeout_secs") {
+        match timeout_secs_str.parse::<u64>() {
+            Ok(value) if (5..=300).contains(&value) => timeout_secs = value,
+            _ => return (StatusCode::BAD_REQUEST, "Invalid timeout !").into_response(),
+        }
+    }
+
+    let mut redis_conn = app_state_.redis_conn.clone();
+    if let Ok(lobby_id) =
+        AsyncCommands::get::<_, String>(&mut redis_conn, format!("user:{}:lobby", username)).await
+    {
+        let key_list = format!("lobby:{}", &lobby_id);
+        if let Ok(lobby_info) =
+            AsyncCommands::hgetall::<_, HashMap<String, String>>(&mut redis_conn, &key_list).await
+        {
+            if lobby_info.get("leader") != Some(username) {
                 return (
                     StatusCode::UNAUTHORIZED,
                     "No permission to perform the request !",
                 )
                     .into_response();
             }
+            if lobby_info
+                .get("status")
+                .and_then(|status| status.parse::<LobbyStatus>().ok())
+                != Some(LobbyStatus::Ready)
+            {
+                return (StatusCode::BAD_REQUEST, "Lobby busy !").into_response();
+            }
+            //Every ready check starts from scratch, the leader starting it counts as ready
+            let ready_check_id = generate_random_code(8);
+            let ready_key = format!("{}:ready", &key_list);
+            let ready_check_key = format!("{}:ready_check", &key_list);
+            let mut pipe = redis::pipe();
+            pipe.atomic()
+                .del(&ready_key)
+                .sadd(&ready_key, username)
+                .set_ex(
+                    &ready_check_key,
+                    &ready_check_id,
+                    timeout_secs + READY_CHECK_GRACE_SECS,
+                );
+            if let Ok(()) = pipe.query_async(&mut redis_conn).await {
+                if let Ok(member_set) = AsyncCommands::smembers::<_, HashSet<String>>(
+                    &mut redis_conn,
+                    format!("{}:members", &key_list),
+                )
+                .await
+                {
+                    let data_to_lobby = json!({
+                        "resource": "lobby",
+                        "action": "ready_check_start",
+                        "payload": {
+                            "leader": username,
+                            "timeout_secs": timeout_secs
+                        }
+                    });
+                    broadcast_to_lobby(&member_set, &data_to_lobby, redis_conn.clone()).await;
+                }
+                let timer_redis_conn = redis_conn.clone();
+                tokio::spawn(async move {
+                    finish_ready_check(lobby_id, ready_check_id, timeout_secs, timer_redis_conn)
+                        .await;
+                });
+                return (
+                    StatusCode::CREATED,
+                    Json(json!({
+                        "timeout_secs": timeout_secs
+                    })),
+                )
+                    .into_response();
+            }
         }
     }
     return (
@@ -696,6 +2575,356 @@ pub async fn kick_member(
         .into_response();
 }
 
+//Waits for the ready check to time out, then reports its result if it hasn't been completed or replaced meanwhile
+//Keeps the ready check key past its timeout so the timer can still tell it apart from a completed check
+const READY_CHECK_GRACE_SECS: u64 = 30;
+
+//Deletes the ready check key only if it still holds the check id ARGV[1], returns the number of deleted keys
+static READY_CHECK_FINISH_SCRIPT: LazyLock<redis::Script> = LazyLock::new(|| {
+    redis::Script::new(
+        r"
+        if redis.call('GET', KEYS[1]) == ARGV[1] then
+            return redis.call('DEL', KEYS[1])
+        end
+        return 0
+        ",
+    )
+});
+
+async fn finish_ready_check(
+    lobby_id: String,
+    ready_check_id: String,
+    timeout_secs: u64,
+    mut redis_conn: MultiplexedConnection,
+) {
+    tokio::time::sleep(std::time::Duration::from_secs(timeout_secs)).await;
+    //Already completed by set_ready, or replaced by a newer check
+    let Ok(1) = READY_CHECK_FINISH_SCRIPT
+        .key(format!("lobby:{}:ready_check", &lobby_id))
+        .arg(&ready_check_id)
+        .invoke_async::<usize>(&mut redis_conn)
+        .await
+    else {
+        return;
+    };
+    if let Some((member_set, ready_set)) =
+        get_lobby_ready_state(&lobby_id, redis_conn.clone()).await
+    {
+        if member_set.is_empty() {
+            return;
+        }
+        let data_to_lobby = if has_ready_quorum(member_set.len(), ready_set.len()) {
+            json!({
+                "resource": "lobby",
+                "action": "ready_check_complete",
+                "payload": {
+                    "ready_members": ready_set
+                }
+            })
+        } else {
+            let not_ready: HashSet<&String> = member_set.difference(&ready_set).collect();
+            json!({
+                "resource": "lobby",
+                "action": "ready_check_failed",
+                "payload": {
+                    "ready_members": ready_set,
+                    "not_ready_members": not_ready
+                }
+            })
+        };
+        broadcast_to_lobby(&member_set, &data_to_lobby, redis_conn.clone()).await;
+    }
+}
+
+//Returns the lobby player set (members that aren't spectators) and the set of players flagged as ready
+pub async fn get_lobby_ready_state(
+    lobby_id: &String,
+    mut redis_conn: MultiplexedConnection,
+) -> Option<(HashSet<String>, HashSet<String>)> {
+    let key_list = format!("lobby:{}", lobby_id);
+    let mut pipe = redis::pipe();
+    pipe.sdiff(&[
+        format!("{}:members", &key_list),
+        format!("{}:spectators", &key_list),
+    ])
+    .smembers(format!("{}:ready", &key_list));
+    if let Ok((player_set, ready_set)) = pipe
+        .query_async::<(HashSet<String>, HashSet<String>)>(&mut redis_conn)
+        .await
+    {
+        let ready_set = ready_set.intersection(&player_set).cloned().collect();
+        return Some((player_set, ready_set));
+    }
+    return None;
+}
+
+//Members of the lobby that only watch its matches
+pub async fn get_lobby_spectators(
+    lobby_id: &String,
+    mut redis_conn: MultiplexedConnection,
+) -> HashSet<String> {
+    return AsyncCommands::smembers::<_, HashSet<String>>(
+        &mut redis_conn,
+        format!("lobby:{}:spectators", lobby_id),
+    )
+    .await
+    .unwrap_or_default();
+}
+
+//Members by join order with the players before the spectators, the next leader is taken from the front
+async fn get_leader_candidates(
+    lobby_id: &String,
+    redis_conn: MultiplexedConnection,
+) -> Vec<String> {
+    let spectator_set = get_lobby_spectators(lobby_id, redis_conn.clone()).await;
+    let mut members_by_join_order = get_members_by_join_order(lobby_id, redis_conn).await;
+    members_by_join_order.sort_by_key(|member| spectator_set.contains(member));
+    return members_by_join_order;
+}
+
+//A match needs at least one player
+pub fn has_ready_quorum(player_num: usize, ready_num: usize) -> bool {
+    return player_num > 0 && ready_num * 100 >= player_num * *READY_CHECK_QUORUM;
+}
+
+//Sets the status to ARGV[1] only if the current status is one of the other arguments, returns the current status
+static LOBBY_STATUS_TRANSITION_SCRIPT: LazyLock<redis::Script> = LazyLock::new(|| {
+    redis::Script::new(
+        r"
+        local current = redis.call('HGET', KEYS[1], 'status')
+        if not current then
+            return false
+        end
+        for i = 2, #ARGV do
+            if ARGV[i] == current then
+                redis.call('HSET', KEYS[1], 'status', ARGV[1])
+                return current
+            end
+        end
+        return current
+        ",
+    )
+});
+
+pub async fn get_lobby_status(
+    lobby_id: &String,
+    mut redis_conn: MultiplexedConnection,
+) -> Option<LobbyStatus> {
+    let lobby_status = AsyncCommands::hget::<_, _, Option<String>>(
+        &mut redis_conn,
+        format!("lobby:{}", lobby_id),
+        "status",
+    )
+    .await
+    .ok()
+    .flatten()?;
+    return lobby_status.parse::<LobbyStatus>().ok();
+}
+
+//Atomically moves the lobby to next_status if its current status allows it and returns the previous status.
+//Every transition is sent to the lobby members as a lobby/status_change event
+pub async fn transition_lobby_status(
+    lobby_id: &String,
+    next_status: LobbyStatus,
+    mut redis_conn: MultiplexedConnection,
+) -> Result<LobbyStatus, (StatusCode, &'static str)> {
+    let key_list = format!("lobby:{}", lobby_id);
+    let mut script_invocation = LOBBY_STATUS_TRANSITION_SCRIPT.prepare_invoke();
+    script_invocation.key(&key_list).arg(next_status.as_str());
+    for previous_status in next_status.allowed_previous() {
+        script_invocation.arg(previous_status.as_str());
+    }
+    let current_status = match script_invocation
+        .invoke_async::<Option<String>>(&mut redis_conn)
+        .await
+    {
+        Ok(Some(current_status)) => current_status,
+        Ok(None) => return Err((StatusCode::NOT_FOUND, "Lobby not found !")),
+        Err(_) => {
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Error finishing the request, please try again !",
+            ));
+        }
+    };
+    let Ok(current_status) = current_status.parse::<LobbyStatus>() else {
+        return Err((StatusCode::INTERNAL_SERVER_ERROR, "Invalid lobby status !"));
+    };
+    if !current_status.can_transition_to(next_status) {
+        return Err((StatusCode::BAD_REQUEST, current_status.transition_error()));
+    }
+    if let Ok(member_set) = AsyncCommands::smembers::<_, HashSet<String>>(
+        &mut redis_conn,
+        format!("{}:members", &key_list),
+    )
+    .await
+    {
+        let data_to_lobby = json!({
+            "resource": "lobby",
+            "action": "status_change",
+            "payload": {
+                "lobby_id": lobby_id,
+                "from": current_status,
+                "to": next_status
+            }
+        });
+        broadcast_to_lobby(&member_set, &data_to_lobby, redis_conn.clone()).await;
+    }
+    return Ok(current_status);
+}
+
+//Sends the same web socket event to every given lobby member
+pub async fn broadcast_to_lobby(
+    member_set: &HashSet<String>,
+    data_to_lobby: &serde_json::Value,
+    mut redis_conn: MultiplexedConnection,
+) {
+    for member in member_set.iter() {
+        let pub_sub_data_json = json!({
+            "username": member,
+            "data": data_to_lobby
+        });
+        let _ = AsyncCommands::publish::<_, _, ()>(
+            &mut redis_conn,
+            "web_socket_events",
+            pub_sub_data_json.to_string(),
+        )
+        .await;
+    }
+}
+
+//Renames the per-lobby data keys when a lobby gets a new id
+pub async fn move_lobby_data(
+    old_lobby_id: &String,
+    new_lobby_id: &String,
+    mut redis_conn: MultiplexedConnection,
+) {
+    if old_lobby_id == new_lobby_id {
+        return;
+    }
+    //A lobby in a match keeps its game server under the new id
+    if let Ok(Some(game_server_info_str)) = AsyncCommands::get::<_, Option<String>>(
+        &mut redis_conn,
+        format!("game_server:{}", old_lobby_id),
+    )
+    .await
+    {
+        if let Ok(game_server_info) = serde_json::from_str::<GameServer>(&game_server_info_str) {
+            let match_lobbies_key = format!("match:{}:lobbies", game_server_info.server_id);
+            let mut pipe = redis::pipe();
+            pipe.atomic()
+                .rename(
+                    format!("game_server:{}", old_lobby_id),
+                    format!("game_server:{}", new_lobby_id),
+                )
+                .srem(&match_lobbies_key, old_lobby_id)
+                .sadd(&match_lobbies_key, new_lobby_id);
+            let _ = pipe.query_async::<()>(&mut redis_conn).await;
+        }
+    }
+    for suffix in LOBBY_DATA_SUFFIXES {
+        let old_key = format!("lobby:{}:{}", old_lobby_id, suffix);
+        if let Ok(true) = AsyncCommands::exists::<_, bool>(&mut redis_conn, &old_key).await {
+            let _ = AsyncCommands::rename::<_, _, ()>(
+                &mut redis_conn,
+                &old_key,
+                format!("lobby:{}:{}", new_lobby_id, suffix),
+            )
+            .await;
+        }
+    }
+}
+
+//Checks that the target lobby can be joined, then moves the user out of their current lobby and into it.
+//Spectators take a spectator slot instead of a player slot.
+//Returns the joined lobby info and its member set (including the user)
+pub async fn join_lobby_proccess(
+    username: &String,
+    target_lobby_id: &String,
+    as_spectator: bool,
+    mut redis_conn: MultiplexedConnection,
+) -> Result<(LobbyInfo, HashSet<String>), (StatusCode, &'static str)> {
+    //lobby:lobby_haha - {name: "", leader: ""}
+    //user:haha:lobby - lobby_haha
+    //active_lobbies - [lobby_haha]
+    //lobby:lobby_haha:members - [haha]
+    let key_list = format!("lobby:{}", target_lobby_id);
+    let Ok(lobby_info) =
+        AsyncCommands::hgetall::<_, HashMap<String, redis::Value>>(&mut redis_conn, &key_list)
+            .await
+    else {
+        return Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Error finishing the request, please try again !",
+        ));
+    };
+    if lobby_info.is_empty() {
+        return Err((StatusCode::NOT_FOUND, "Lobby not found !"));
+    }
+    if let Some(lobby_status_redis) = lobby_info.get("status") {
+        if let Ok(lobby_status) = String::from_redis_value_ref(lobby_status_redis) {
+            if lobby_status.parse::<LobbyStatus>() != Ok(LobbyStatus::Ready) {
+                return Err((StatusCode::BAD_REQUEST, "Lobby busy !"));
+            }
+        }
+    }
+    let lobby_info_response = LobbyInfo::new(
+        &String::from_redis_value(lobby_info.get("lobby_name").unwrap().clone()).unwrap(),
+        &String::from_redis_value(lobby_info.get("leader").unwrap().clone()).unwrap(),
+        usize::from_redis_value(lobby_info.get("limit_num").unwrap().clone()).unwrap(),
+        //Only Ready lobbies can be joined
+        LobbyStatus::Ready,
+    );
+    //Get lobby members set
+    let mut pipe = redis::pipe();
+    pipe.smembers(format!("{}:members", &key_list)).sinter(&[
+        format!("{}:members", &key_list),
+        format!("{}:spectators", &key_list),
+    ]);
+    let Ok((mut member_set, spectator_set)) = pipe
+        .query_async::<(HashSet<String>, HashSet<String>)>(&mut redis_conn)
+        .await
+    else {
+        return Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Error finishing the request, please try again !",
+        ));
+    };
+    if as_spectator {
+        if spectator_set.len() >= *LOBBY_SPECTATOR_LIMIT {
+            return Err((StatusCode::BAD_REQUEST, "Spectator slots full !"));
+        }
+    } else if member_set.len() - spectator_set.len() >= lobby_info_response.limit_num {
+        return Err((StatusCode::BAD_REQUEST, "Lobby full !"));
+    }
+    if is_banned_from_lobby(username, target_lobby_id, redis_conn.clone()).await {
+        return Err((StatusCode::FORBIDDEN, "Banned from lobby !"));
+    }
+    //User leave current lobby first then join the new lobby
+    leave_lobby_proccess(username, redis_conn.clone()).await;
+    let mut pipe = redis::pipe();
+    pipe.atomic()
+        .set(format!("user:{}:lobby", username), target_lobby_id)
+        .sadd(format!("{}:members", &key_list), username)
+        .zadd(
+            format!("{}:joined", &key_list),
+            username,
+            get_join_timestamp(),
+        );
+    if as_spectator {
+        pipe.sadd(format!("{}:spectators", &key_list), username);
+    }
+    if let Err(_) = pipe.query_async::<()>(&mut redis_conn).await {
+        return Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Error finishing the request, please try again !",
+        ));
+    }
+    member_set.insert(username.clone());
+    lobby_chat_controller::send_chat_history(username, redis_conn.clone()).await;
+    return Ok((lobby_info_response, member_set));
+}
+
 pub async fn leave_lobby_proccess(username: &String, mut redis_conn: MultiplexedConnection) {
     let mut pipe = redis::pipe();
     //lobby:lobby_haha - {name: "", leader: ""}
@@ -706,9 +2935,20 @@ pub async fn leave_lobby_proccess(username: &String, mut redis_conn: Multiplexed
         AsyncCommands::get::<_, String>(&mut redis_conn, format!("user:{}:lobby", username)).await
     {
         let current_key_list = format!("lobby:{}", current_lobby_id);
+        //The party changes, so a queued lobby has to queue again
+        matchmaking_controller::cancel_matchmaking(
+            &current_lobby_id,
+            &format!("{} left the lobby", username),
+            redis_conn.clone(),
+        )
+        .await;
         pipe.atomic()
             //.set(format!("user:{}:lobby", username), "")
-            .srem(format!("{}:members", &current_key_list), username);
+            .srem(format!("{}:members", &current_key_list), username)
+            .srem(format!("{}:ready", &current_key_list), username)
+            .srem(format!("{}:spectators", &current_key_list), username)
+            .hdel(format!("{}:loadouts", &current_key_list), username)
+            .zrem(format!("{}:joined", &current_key_list), username);
         if let Ok(_) = pipe.query_async::<()>(&mut redis_conn).await {
             //Get lobby members set
             if let Ok(mut member_set) = AsyncCommands::smembers::<_, HashSet<String>>(
@@ -728,6 +2968,9 @@ pub async fn leave_lobby_proccess(username: &String, mut redis_conn: Multiplexed
                                 .del(&current_key_list)
                                 .del(format!("user:{}:lobby", username))
                                 .del(format!("{}:members", &current_key_list));
+                            for suffix in LOBBY_DATA_SUFFIXES {
+                                pipe.del(format!("{}:{}", &current_key_list, suffix));
+                            }
                             let _ = pipe.query_async::<()>(&mut redis_conn).await;
                         }
                     }
@@ -737,10 +2980,12 @@ pub async fn leave_lobby_proccess(username: &String, mut redis_conn: Multiplexed
                 if let Ok(lobby_leader) =
                     AsyncCommands::hget(&mut redis_conn, &current_key_list, "leader").await
                 {
-                    //If lobby's leader is left user, grant leader lobby to the first member of the lobby set
+                    //If lobby's leader is left user, grant leader lobby to the longest-standing player of the lobby
+                    let members_by_join_order =
+                        get_leader_candidates(&current_lobby_id, redis_conn.clone()).await;
                     let mut new_leader = &lobby_leader;
                     if &lobby_leader == username {
-                        for member in member_set.iter() {
+                        for member in members_by_join_order.iter() {
                             if member != username {
                                 new_leader = member;
                                 break;
@@ -749,8 +2994,16 @@ pub async fn leave_lobby_proccess(username: &String, mut redis_conn: Multiplexed
                     }
                     let new_lobby_id = format!("lobby_{}", new_leader);
                     let new_key_list = format!("lobby:{}", new_lobby_id);
-                    let lobby_info_for_member =
-                        LobbyInfo::new(&format!("{}'s lobby", new_leader), &new_leader, 5, "Ready");
+                    //Renaming keeps the status, a lobby in a match stays in it
+                    let lobby_status = get_lobby_status(&current_lobby_id, redis_conn.clone())
+                        .await
+                        .unwrap_or(LobbyStatus::Ready);
+                    let lobby_info_for_member = LobbyInfo::new(
+                        &format!("{}'s lobby", new_leader),
+                        &new_leader,
+                        5,
+                        lobby_status,
+                    );
                     if new_leader != &lobby_leader {
                         pipe.atomic()
                             .del(&current_key_list)
@@ -774,6 +3027,13 @@ pub async fn leave_lobby_proccess(username: &String, mut redis_conn: Multiplexed
                             }
                         }
                         let _ = pipe.query_async::<()>(&mut redis_conn).await;
+                        move_lobby_data(&current_lobby_id, &new_lobby_id, redis_conn.clone()).await;
+                        let _ = AsyncCommands::srem::<_, _, ()>(
+                            &mut redis_conn,
+                            format!("{}:spectators", &new_key_list),
+                            new_leader,
+                        )
+                        .await;
                     }
                     for member in member_set.iter() {
                         if new_leader != &lobby_leader {
@@ -814,3 +3074,24 @@ pub async fn leave_lobby_proccess(username: &String, mut redis_conn: Multiplexed
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ready_quorum_needs_players() {
+        assert!(!has_ready_quorum(0, 0));
+        assert!(has_ready_quorum(1, 1));
+        assert!(!has_ready_quorum(4, 0));
+    }
+
+    #[test]
+    fn ready_quorum_follows_the_configured_percentage() {
+        //READY_CHECK_QUORUM percent of 4 players, rounded up
+        let needed_num = (4 * *READY_CHECK_QUORUM).div_ceil(100);
+        assert!(has_ready_quorum(4, needed_num));
+        assert!(!has_ready_quorum(4, needed_num - 1));
+        assert!(has_ready_quorum(4, 4));
+    }
+}
@@ -0,0 +1,601 @@
+use rand::seq::IndexedRandom;
+use redis::{AsyncCommands, aio::MultiplexedConnection};
+use serde_json::json;
+use std::{
+    collections::{HashMap, HashSet},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use axum::{Json, extract::State, http::StatusCode, response::IntoResponse};
+
+use crate::{
+    app_state::AppState,
+    auth::{AuthUser, generate_random_code},
+    controllers::{allocation_queue_controller, lobby_controller, rating_controller},
+    global_vars::{
+        GAME_CATALOG, MATCHMAKING_FILL_WAIT_SECS, MATCHMAKING_MAX_PLAYERS, MATCHMAKING_MIN_PLAYERS,
+        RATING_BAND_BASE, RATING_BAND_MAX, RATING_BAND_WIDEN_PER_SEC, USERNAME_REGEX,
+    },
+    models::{catalog::MatchSettings, game_server::AllocationRequest, lobby::LobbyStatus},
+};
+
+const MATCHMAKING_INTERVAL_SECS: u64 = 2;
+
+struct QueuedLobby {
+    lobby_id: String,
+    game_mode: String,
+    party_size: usize,
+    rating: f64,
+    queued_at: u64,
+}
+
+//Players a match of the game mode needs, the game mode's limits narrowed by the matchmaking limits
+fn get_match_player_limits(game_mode: &str) -> (usize, usize) {
+    match GAME_CATALOG.find_game_mode(game_mode) {
+        Some(game_mode_entry) => (
+            game_mode_entry.min_players.max(*MATCHMAKING_MIN_PLAYERS),
+            game_mode_entry.max_players.min(*MATCHMAKING_MAX_PLAYERS),
+        ),
+        None => (*MATCHMAKING_MIN_PLAYERS, *MATCHMAKING_MAX_PLAYERS),
+    }
+}
+
+impl QueuedLobby {
+    //Acceptable rating difference, widened the longer the lobby waits in queue
+    fn rating_band(&self, now: u64) -> f64 {
+        let waited_secs = now.saturating_sub(self.queued_at) as f64;
+        return (*RATING_BAND_BASE + *RATING_BAND_WIDEN_PER_SEC * waited_secs)
+            .min(*RATING_BAND_MAX);
+    }
+}
+
+//Queues the lobby for the game mode of its match settings, see lobby_controller::set_match_settings
+pub async fn enqueue_lobby(
+    State(app_state_): State<AppState>,
+    claims: AuthUser,
+) -> impl IntoResponse {
+    let username = &claims.username;
+
+    if !USERNAME_REGEX.is_match(username) {
+        return (StatusCode::BAD_REQUEST, "Invalid username format !").into_response();
+    }
+
+    //matchmaking_queue - [lobby_haha: queued_at]
+    //matchmaking:lobby_haha - {game_mode: "", rating: ""}
+    let mut redis_conn = app_state_.redis_conn.clone();
+    if let Ok(lobby_id) =
+        AsyncCommands::get::<_, String>(&mut redis_conn, format!("user:{}:lobby", username)).await
+    {
+        let key_list = format!("lobby:{}", &lobby_id);
+        if let Ok(lobby_info) =
+            AsyncCommands::hgetall::<_, HashMap<String, String>>(&mut redis_conn, &key_list).await
+        {
+            if lobby_info.get("leader") != Some(username) {
+                return (
+                    StatusCode::UNAUTHORIZED,
+                    "No permission to perform the request !",
+                )
+                    .into_response();
+            }
+            let game_mode =
+                lobby_controller::get_lobby_match_settings(&lobby_id, redis_conn.clone())
+                    .await
+                    .game_mode;
+            let (_, max_players) = get_match_player_limits(&game_mode);
+            if let Some((member_set, mut ready_set)) =
+                lobby_controller::get_lobby_ready_state(&lobby_id, redis_conn.clone()).await
+            {
+                ready_set.insert(username.clone());
+                if !lobby_controller::has_ready_quorum(member_set.len(), ready_set.len()) {
+                    return (StatusCode::BAD_REQUEST, "Not enough members are ready !")
+                        .into_response();
+                }
+                if member_set.len() > max_players {
+                    return (StatusCode::BAD_REQUEST, "Party too large for a match !")
+                        .into_response();
+                }
+                let members: Vec<String> = member_set.iter().cloned().collect();
+                let Some(party_rating) =
+                    rating_controller::get_party_rating(&app_state_.connection_pool, &members)
+                        .await
+                else {
+                    return (
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        "Error finishing the request, please try again !",
+                    )
+                        .into_response();
+                };
+                let queued_at = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap()
+                    .as_secs();
+                if let Err((status_code, message)) = lobby_controller::transition_lobby_status(
+                    &lobby_id,
+                    LobbyStatus::Queued,
+                    redis_conn.clone(),
+                )
+                .await
+                {
+                    return (status_code, message).into_response();
+                }
+                let mut pipe = redis::pipe();
+                pipe.atomic()
+                    .hset_multiple(
+                        format!("matchmaking:{}", &lobby_id),
+                        &[
+                            ("game_mode", game_mode.clone()),
+                            ("rating", party_rating.to_string()),
+                        ],
+                    )
+                    .zadd("matchmaking_queue", &lobby_id, queued_at);
+                if let Ok(()) = pipe.query_async(&mut redis_conn).await {
+                    let data_to_lobby = json!({
+                        "resource": "matchmaking",
+                        "action": "queued",
+                        "payload": {
+                            "game_mode": game_mode,
+                            "party_size": member_set.len(),
+                            "rating": party_rating,
+                            "queued_at": queued_at
+                        }
+                    });
+                    //Spectators follow the queue too, they just don't count towards the party
+                    if let Ok(lobby_member_set) = AsyncCommands::smembers::<_, HashSet<String>>(
+                        &mut redis_conn,
+                        format!("{}:members", &key_list),
+                    )
+                    .await
+                    {
+                        lobby_controller::broadcast_to_lobby(
+                            &lobby_member_set,
+                            &data_to_lobby,
+                            redis_conn.clone(),
+                        )
+                        .await;
+                    }
+                    return (
+                        StatusCode::CREATED,
+                        Json(json!({
+                            "game_mode": game_mode,
+                            "party_size": member_set.len(),
+                            "rating": party_rating,
+                            "queued_at": queued_at
+                        })),
+                    )
+                        .into_response();
+                }
+                let _ = lobby_controller::transition_lobby_status(
+                    &lobby_id,
+                    LobbyStatus::Ready,
+                    redis_conn.clone(),
+                )
+                .await;
+            }
+        }
+    }
+    return (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "Error finishing the request, please try again !",
+    )
+        .into_response();
+}
+
+pub async fn dequeue_lobby(
+    State(app_state_): State<AppState>,
+    claims: AuthUser,
+) -> impl IntoResponse {
+    let username = &claims.username;
+
+    if !USERNAME_REGEX.is_match(username) {
+        return (StatusCode::BAD_REQUEST, "Invalid username format !").into_response();
+    }
+
+    let mut redis_conn = app_state_.redis_conn.clone();
+    if let Ok(lobby_id) =
+        AsyncCommands::get::<_, String>(&mut redis_conn, format!("user:{}:lobby", username)).await
+    {
+        if cancel_matchmaking(
+            &lobby_id,
+            &format!("{} left the queue", username),
+            redis_conn,
+        )
+        .await
+        {
+            return (StatusCode::CREATED, "Left matchmaking queue !").into_response();
+        }
+        return (
+            StatusCode::BAD_REQUEST,
+            "Lobby is not in matchmaking queue !",
+        )
+            .into_response();
+    }
+    return (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "Error finishing the request, please try again !",
+    )
+        .into_response();
+}
+
+//Takes a lobby out of the matchmaking queue and notifies its members. Returns false if it wasn't queued
+pub async fn cancel_matchmaking(
+    lobby_id: &String,
+    reason: &str,
+    mut redis_conn: MultiplexedConnection,
+) -> bool {
+    if let Ok(1) =
+        AsyncCommands::zrem::<_, _, usize>(&mut redis_conn, "matchmaking_queue", lobby_id).await
+    {
+        let key_list = format!("lobby:{}", lobby_id);
+        let _ = lobby_controller::transition_lobby_status(
+            lobby_id,
+            LobbyStatus::Ready,
+            redis_conn.clone(),
+        )
+        .await;
+        let mut pipe = redis::pipe();
+        pipe.atomic()
+            .del(format!("matchmaking:{}", lobby_id))
+            .smembers(format!("{}:members", &key_list));
+        if let Ok((_, member_set)) = pipe
+            .query_async::<((), HashSet<String>)>(&mut redis_conn)
+            .await
+        {
+            let data_to_lobby = json!({
+                "resource": "matchmaking",
+                "action": "cancelled",
+                "payload": {
+                    "reason": reason
+                }
+            });
+            lobby_controller::broadcast_to_lobby(&member_set, &data_to_lobby, redis_conn.clone())
+                .await;
+        }
+        return true;
+    }
+    return false;
+}
+
+//Background task grouping queued lobbies into matches
+pub async fn run_matchmaker(app_state_: AppState) {
+    let mut interval = tokio::time::interval(Duration::from_secs(MATCHMAKING_INTERVAL_SECS));
+    loop {
+        interval.tick().await;
+        find_matches(&app_state_).await;
+    }
+}
+
+async fn find_matches(app_state_: &AppState) {
+    let mut redis_conn = app_state_.redis_conn.clone();
+    let Ok(queue) = AsyncCommands::zrange_withscores::<_, Vec<(String, u64)>>(
+        &mut redis_conn,
+        "matchmaking_queue",
+        0,
+        -1,
+    )
+    .await
+    else {
+        return;
+    };
+    //Queue is sorted oldest first, lobbies are matched per game mode in that order
+    let mut lobbies_by_mode: HashMap<String, Vec<QueuedLobby>> = HashMap::new();
+    for (lobby_id, queued_at) in queue {
+        //Members can join or leave while the lobby waits, so the party size is read on every pass.
+        //Spectators don't take a player slot
+        let mut pipe = redis::pipe();
+        pipe.hgetall(format!("matchmaking:{}", &lobby_id)).sdiff(&[
+            format!("lobby:{}:members", &lobby_id),
+            format!("lobby:{}:spectators", &lobby_id),
+        ]);
+        if let Ok((queue_entry, player_set)) = pipe
+            .query_async::<(HashMap<String, String>, HashSet<String>)>(&mut redis_conn)
+            .await
+        {
+            let party_size = player_set.len();
+            let (Some(game_mode), Some(rating)) = (
+                queue_entry.get("game_mode"),
+                queue_entry
+                    .get("rating")
+                    .and_then(|value| value.parse::<f64>().ok()),
+            ) else {
+                continue;
+            };
+            //Waits in queue until members leave if it grew too large for a match
+            if party_size == 0 || party_size > get_match_player_limits(game_mode).1 {
+                continue;
+            }
+            lobbies_by_mode
+                .entry(game_mode.clone())
+                .or_default()
+                .push(QueuedLobby {
+                    lobby_id,
+                    game_mode: game_mode.clone(),
+                    party_size,
+                    rating,
+                    queued_at,
+                });
+        }
+    }
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    for (game_mode, queued_lobbies) in lobbies_by_mode {
+        let (min_players, max_players) = get_match_player_limits(&game_mode);
+        for group in group_queued_lobbies(queued_lobbies, max_players, now) {
+            if should_start_match(&group, min_players, max_players, now) {
+                start_match(app_state_, group).await;
+            }
+        }
+    }
+}
+
+//First fit: each lobby joins the oldest group it fits in. A group fits when there is room left and
+//the rating gap to the group's average is inside the band of the lobby and of every lobby in the group
+fn group_queued_lobbies(
+    queued_lobbies: Vec<QueuedLobby>,
+    max_players: usize,
+    now: u64,
+) -> Vec<Vec<QueuedLobby>> {
+    let mut groups: Vec<Vec<QueuedLobby>> = Vec::new();
+    for queued_lobby in queued_lobbies {
+        let fitting_group = groups.iter().position(|group| {
+            let player_num: usize = group.iter().map(|lobby| lobby.party_size).sum();
+            let group_rating = group
+                .iter()
+                .map(|lobby| lobby.rating * lobby.party_size as f64)
+                .sum::<f64>()
+                / player_num as f64;
+            let rating_gap = (queued_lobby.rating - group_rating).abs();
+            player_num + queued_lobby.party_size <= max_players
+                && rating_gap <= queued_lobby.rating_band(now)
+                && group
+                    .iter()
+                    .all(|lobby| rating_gap <= lobby.rating_band(now))
+        });
+        match fitting_group {
+            Some(group_index) => groups[group_index].push(queued_lobby),
+            None => groups.push(vec![queued_lobby]),
+        }
+    }
+    return groups;
+}
+
+//A full group starts right away, one with at least min_players waits up to MATCHMAKING_FILL_WAIT_SECS
+//for more lobbies, counted from its oldest lobby joining the queue
+fn should_start_match(
+    group: &Vec<QueuedLobby>,
+    min_players: usize,
+    max_players: usize,
+    now: u64,
+) -> bool {
+    let player_num: usize = group.iter().map(|lobby| lobby.party_size).sum();
+    if player_num >= max_players {
+        return true;
+    }
+    let oldest_queued_at = group
+        .iter()
+        .map(|lobby| lobby.queued_at)
+        .min()
+        .unwrap_or(now);
+    return player_num >= min_players
+        && now.saturating_sub(oldest_queued_at) >= *MATCHMAKING_FILL_WAIT_SECS;
+}
+
+async fn start_match(app_state_: &AppState, group: Vec<QueuedLobby>) {
+    let mut redis_conn = app_state_.redis_conn.clone();
+    //Claim the lobbies, one that left the queue meanwhile is left out of the match
+    let mut claimed_lobbies: Vec<QueuedLobby> = Vec::new();
+    for queued_lobby in group {
+        if let Ok(1) = AsyncCommands::zrem::<_, _, usize>(
+            &mut redis_conn,
+            "matchmaking_queue",
+            &queued_lobby.lobby_id,
+        )
+        .await
+        {
+            claimed_lobbies.push(queued_lobby);
+        }
+    }
+    let player_num: usize = claimed_lobbies.iter().map(|lobby| lobby.party_size).sum();
+    if claimed_lobbies.is_empty() {
+        return;
+    }
+    let (min_players, _) = get_match_player_limits(&claimed_lobbies[0].game_mode);
+    if player_num < min_players {
+        for queued_lobby in claimed_lobbies.iter() {
+            let _ = AsyncCommands::zadd::<_, _, _, ()>(
+                &mut redis_conn,
+                "matchmaking_queue",
+                &queued_lobby.lobby_id,
+                queued_lobby.queued_at,
+            )
+            .await;
+        }
+        return;
+    }
+    //A lobby that can't move on from the queue (e.g. sent back to Ready meanwhile) is left out too
+    let mut allocating_lobbies: Vec<QueuedLobby> = Vec::new();
+    for queued_lobby in claimed_lobbies {
+        if let Ok(_) = lobby_controller::transition_lobby_status(
+            &queued_lobby.lobby_id,
+            LobbyStatus::Allocating,
+            redis_conn.clone(),
+        )
+        .await
+        {
+            allocating_lobbies.push(queued_lobby);
+        }
+    }
+    let claimed_lobbies = allocating_lobbies;
+    if claimed_lobbies.is_empty() {
+        return;
+    }
+    let server_id = format!("match_{}", generate_random_code(8).to_lowercase());
+    let game_mode = claimed_lobbies[0].game_mode.clone();
+    //Queued matches are played on a random map of the game mode
+    let map = GAME_CATALOG
+        .find_game_mode(&game_mode)
+        .and_then(|game_mode_entry| game_mode_entry.maps.choose(&mut rand::rng()).cloned())
+        .unwrap_or_default();
+    let match_settings = MatchSettings {
+        game_mode: game_mode.clone(),
+        map,
+    };
+    let lobby_ids: Vec<String> = claimed_lobbies
+        .iter()
+        .map(|lobby| lobby.lobby_id.clone())
+        .collect();
+    let mut member_sets: Vec<HashSet<String>> = Vec::new();
+    for lobby_id in lobby_ids.iter() {
+        let _ =
+            AsyncCommands::del::<_, ()>(&mut redis_conn, format!("matchmaking:{}", lobby_id)).await;
+        if let Ok(member_set) = AsyncCommands::smembers::<_, HashSet<String>>(
+            &mut redis_conn,
+            format!("lobby:{}:members", lobby_id),
+        )
+        .await
+        {
+            let data_to_lobby = json!({
+                "resource": "matchmaking",
+                "action": "match_found",
+                "payload": {
+                    "server_id": server_id,
+                    "game_mode": game_mode,
+                    "map": match_settings.map,
+                    "lobbies": lobby_ids
+                }
+            });
+            lobby_controller::broadcast_to_lobby(&member_set, &data_to_lobby, redis_conn.clone())
+                .await;
+            member_sets.push(member_set);
+        }
+    }
+    //Members get the server address through the game_server/create event
+    //Without a free server the match waits in the allocation queue, its lobbies get their position
+    let allocation_request = AllocationRequest {
+        server_id: server_id.clone(),
+        lobby_ids: lobby_ids.clone(),
+        host: "".to_string(),
+        match_settings,
+        queued_at: lobby_controller::get_timestamp_secs(),
+    };
+    if let Err((_, message)) =
+        allocation_queue_controller::allocate_or_enqueue(app_state_, &allocation_request).await
+    {
+        for lobby_id in lobby_ids.iter() {
+            let _ = lobby_controller::transition_lobby_status(
+                lobby_id,
+                LobbyStatus::Ready,
+                redis_conn.clone(),
+            )
+            .await;
+        }
+        let data_to_lobby = json!({
+            "resource": "matchmaking",
+            "action": "cancelled",
+            "payload": {
+                "reason": message
+            }
+        });
+        for member_set in member_sets.iter() {
+            lobby_controller::broadcast_to_lobby(member_set, &data_to_lobby, redis_conn.clone())
+                .await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn queued_lobby(lobby_id: &str, party_size: usize, rating: f64, queued_at: u64) -> QueuedLobby {
+        QueuedLobby {
+            lobby_id: lobby_id.to_string(),
+            game_mode: "deathmatch".to_string(),
+            party_size,
+            rating,
+            queued_at,
+        }
+    }
+
+    fn group_ids(groups: &Vec<Vec<QueuedLobby>>) -> Vec<Vec<&str>> {
+        return groups
+            .iter()
+            .map(|group| group.iter().map(|lobby| lobby.lobby_id.as_str()).collect())
+            .collect();
+    }
+
+    #[test]
+    fn groups_lobbies_with_close_ratings() {
+        let groups = group_queued_lobbies(
+            vec![
+                queued_lobby("a", 2, 1500.0, 100),
+                queued_lobby("b", 2, 1550.0, 100),
+                queued_lobby("c", 1, 1480.0, 100),
+            ],
+            10,
+            100,
+        );
+        assert_eq!(group_ids(&groups), vec![vec!["a", "b", "c"]]);
+    }
+
+    #[test]
+    fn keeps_distant_ratings_apart_until_the_band_widens() {
+        let lobbies = || {
+            vec![
+                queued_lobby("a", 2, 1500.0, 100),
+                queued_lobby("b", 2, 1800.0, 100),
+            ]
+        };
+        assert_eq!(
+            group_ids(&group_queued_lobbies(lobbies(), 10, 100)),
+            vec![vec!["a"], vec!["b"]]
+        );
+        //The band reaches 300 after 20 seconds in queue
+        assert_eq!(
+            group_ids(&group_queued_lobbies(lobbies(), 10, 120)),
+            vec![vec!["a", "b"]]
+        );
+    }
+
+    #[test]
+    fn starts_full_groups_right_away() {
+        let group = vec![
+            queued_lobby("a", 3, 1500.0, 100),
+            queued_lobby("b", 1, 1500.0, 100),
+        ];
+        assert!(should_start_match(&group, 2, 4, 100));
+    }
+
+    #[test]
+    fn waits_to_fill_a_group_before_starting_at_min_players() {
+        let group = vec![
+            queued_lobby("a", 1, 1500.0, 100),
+            queued_lobby("b", 1, 1500.0, 105),
+        ];
+        assert!(!should_start_match(&group, 2, 4, 105));
+        assert!(should_start_match(
+            &group,
+            2,
+            4,
+            100 + *MATCHMAKING_FILL_WAIT_SECS
+        ));
+        //Never below min_players
+        let group = vec![queued_lobby("a", 1, 1500.0, 100)];
+        assert!(!should_start_match(&group, 2, 4, 100_000));
+    }
+
+    #[test]
+    fn never_overfills_a_group() {
+        let groups = group_queued_lobbies(
+            vec![
+                queued_lobby("a", 3, 1500.0, 100),
+                queued_lobby("b", 2, 1500.0, 100),
+                queued_lobby("c", 1, 1500.0, 100),
+            ],
+            4,
+            100,
+        );
+        assert_eq!(group_ids(&groups), vec![vec!["a", "c"], vec!["b"]]);
+    }
+}
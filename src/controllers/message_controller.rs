@@ -0,0 +1,276 @@
+use redis::AsyncCommands;
+use serde_json::json;
+use std::{
+    collections::HashMap,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use axum::{
+    Json,
+    extract::{Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+};
+use sqlx::PgPool;
+
+use crate::{
+    app_state::AppState, auth::AuthUser, global_vars::USERNAME_REGEX,
+    models::message::DirectMessage,
+};
+
+const MAX_MESSAGE_LEN: usize = 500;
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 100;
+
+pub async fn send_message(
+    State(app_state_): State<AppState>,
+    claims: AuthUser,
+    Json(payload): Json<HashMap<String, String>>,
+) -> impl IntoResponse {
+    let sender = &claims.username;
+
+    if !USERNAME_REGEX.is_match(sender) {
+        return (StatusCode::BAD_REQUEST, "Invalid sender username format !").into_response();
+    }
+
+    let Some(receiver) = payload.get("receiver") else {
+        return (StatusCode::BAD_REQUEST, "Missing receiver !").into_response();
+    };
+    if !USERNAME_REGEX.is_match(receiver) {
+        return (
+            StatusCode::BAD_REQUEST,
+            "Invalid receiver username format !",
+        )
+            .into_response();
+    }
+    if sender == receiver {
+        return (StatusCode::BAD_REQUEST, "Can't send message to self !").into_response();
+    }
+
+    let Some(content) = payload.get("content").map(|content| content.trim()) else {
+        return (StatusCode::BAD_REQUEST, "Missing content !").into_response();
+    };
+    if content.is_empty() {
+        return (StatusCode::BAD_REQUEST, "Message empty !").into_response();
+    }
+    if content.chars().count() > MAX_MESSAGE_LEN {
+        return (StatusCode::BAD_REQUEST, "Message too long !").into_response();
+    }
+
+    if let Err((status_code, message)) =
+        check_can_message(&app_state_.connection_pool, sender, receiver).await
+    {
+        return (status_code, message).into_response();
+    }
+
+    let sent_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as i64;
+    match sqlx::query_as::<_, DirectMessage>(
+        "Insert into messages (sender, receiver, content, sent_at) values ($1, $2, $3, $4) returning id, sender, receiver, content, sent_at, is_read",
+    )
+    .bind(sender)
+    .bind(receiver)
+    .bind(content)
+    .bind(sent_at)
+    .fetch_one(&app_state_.connection_pool)
+    .await
+    {
+        Ok(direct_message) => {
+            //Delivered right away if the receiver is online, otherwise it shows up in the unread count
+            let mut redis_conn = app_state_.redis_conn.clone();
+            let data_to_receiver = json!({
+                "resource": "message",
+                "action": "receive",
+                "payload": {
+                    "message": direct_message
+                }
+            });
+            let pub_sub_data_json = json!({
+                "username": receiver,
+                "data": data_to_receiver
+            });
+            let _ = AsyncCommands::publish::<_, _, ()>(
+                &mut redis_conn,
+                "web_socket_events",
+                pub_sub_data_json.to_string(),
+            )
+            .await;
+            return (StatusCode::CREATED, Json(direct_message)).into_response();
+        }
+        Err(_) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Error finishing the request, please try again !",
+            )
+                .into_response();
+        }
+    }
+}
+
+pub async fn get_conversation(
+    State(app_state_): State<AppState>,
+    claims: AuthUser,
+    query_params: Query<HashMap<String, String>>,
+) -> impl IntoResponse {
+    if query_params.is_empty() {
+        return (StatusCode::BAD_REQUEST, "Params empty !").into_response();
+    }
+
+    let username = &claims.username;
+
+    if !USERNAME_REGEX.is_match(username) {
+        return (StatusCode::BAD_REQUEST, "Invalid username format !").into_response();
+    }
+
+    let Some(other_user) = query_params.get("with") else {
+        return (StatusCode::BAD_REQUEST, "Missing conversation user !").into_response();
+    };
+    if !USERNAME_REGEX.is_match(other_user) {
+        return (StatusCode::BAD_REQUEST, "Invalid username format !").into_response();
+    }
+
+    let mut page_size = DEFAULT_PAGE_SIZE;
+    if let Some(limit_str) = query_params.get("limit") {
+        match limit_str.parse::<i64>() {
+            Ok(value) if value > 0 && value <= MAX_PAGE_SIZE => page_size = value,
+            _ => return (StatusCode::BAD_REQUEST, "Invalid limit !").into_response(),
+        }
+    }
+    //Older pages are requested with the id of the oldest message already loaded
+    let mut before_id = i32::MAX;
+    if let Some(before_id_str) = query_params.get("before_id") {
+        match before_id_str.parse::<i32>() {
+            Ok(value) => before_id = value,
+            _ => return (StatusCode::BAD_REQUEST, "Invalid message id !").into_response(),
+        }
+    }
+
+    if let Ok(mut conversation) = sqlx::query_as::<_, DirectMessage>(
+        "Select id, sender, receiver, content, sent_at, is_read from messages where (sender = $1 and receiver = $2 or sender = $2 and receiver = $1) and id < $3 order by id desc limit $4",
+    )
+    .bind(username)
+    .bind(other_user)
+    .bind(before_id)
+    .bind(page_size)
+    .fetch_all(&app_state_.connection_pool)
+    .await
+    {
+        conversation.reverse();
+        return (StatusCode::OK, Json(conversation)).into_response();
+    }
+    return (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "Error finishing the request, please try again !",
+    )
+        .into_response();
+}
+
+pub async fn mark_messages_read(
+    State(app_state_): State<AppState>,
+    claims: AuthUser,
+    query_params: Query<HashMap<String, String>>,
+) -> impl IntoResponse {
+    if query_params.is_empty() {
+        return (StatusCode::BAD_REQUEST, "Params empty !").into_response();
+    }
+
+    let reader = &claims.username;
+
+    if !USERNAME_REGEX.is_match(reader) {
+        return (StatusCode::BAD_REQUEST, "Invalid username format !").into_response();
+    }
+
+    let Some(sender) = query_params.get("sender") else {
+        return (StatusCode::BAD_REQUEST, "Missing sender !").into_response();
+    };
+    if !USERNAME_REGEX.is_match(sender) {
+        return (StatusCode::BAD_REQUEST, "Invalid sender username format !").into_response();
+    }
+
+    if let Ok(result) = sqlx::query(
+        "Update messages set is_read = true where sender = $1 and receiver = $2 and is_read = false",
+    )
+    .bind(sender)
+    .bind(reader)
+    .execute(&app_state_.connection_pool)
+    .await
+    {
+        if result.rows_affected() > 0 {
+            let mut redis_conn = app_state_.redis_conn.clone();
+            let data_to_sender = json!({
+                "resource": "message",
+                "action": "read",
+                "payload": {
+                    "reader": reader
+                }
+            });
+            let pub_sub_data_json = json!({
+                "username": sender,
+                "data": data_to_sender
+            });
+            let _ = AsyncCommands::publish::<_, _, ()>(
+                &mut redis_conn,
+                "web_socket_events",
+                pub_sub_data_json.to_string(),
+            )
+            .await;
+        }
+        return (
+            StatusCode::CREATED,
+            Json(json!({
+                "sender": sender,
+                "read_num": result.rows_affected()
+            })),
+        )
+            .into_response();
+    }
+    return (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "Error finishing the request, please try again !",
+    )
+        .into_response();
+}
+
+//Only friends that haven't blocked each other can exchange messages
+async fn check_can_message(
+    connection_pool: &PgPool,
+    sender: &String,
+    receiver: &String,
+) -> Result<(), (StatusCode, &'static str)> {
+    match sqlx::query(
+        "Select blocker from BlockedUsers where blocker = $1 and blocked = $2 or blocker = $2 and blocked = $1",
+    )
+    .bind(sender)
+    .bind(receiver)
+    .fetch_optional(connection_pool)
+    .await
+    {
+        Ok(Some(_)) => return Err((StatusCode::FORBIDDEN, "Can't message this player !")),
+        Ok(None) => {}
+        Err(_) => {
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Error finishing the request, please try again !",
+            ));
+        }
+    }
+    match sqlx::query(
+        "Select player1, player2 from friends where player1 = $1 and player2 = $2 or player1 = $2 and player2 = $1 ",
+    )
+    .bind(sender)
+    .bind(receiver)
+    .fetch_optional(connection_pool)
+    .await
+    {
+        Ok(Some(_)) => return Ok(()),
+        Ok(None) => return Err((StatusCode::FORBIDDEN, "Not friends !")),
+        Err(_) => {
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Error finishing the request, please try again !",
+            ));
+        }
+    }
+}
@@ -1,17 +1,32 @@
+mod admin_controller;
+pub mod allocation_queue_controller;
 mod friend_controller;
-mod game_server_controller;
+pub mod game_server_controller;
 mod in_game_controller;
-mod lobby_controller;
+mod loadout_controller;
+mod lobby_chat_controller;
+pub mod lobby_controller;
+pub mod matchmaking_controller;
+mod message_controller;
+mod rating_controller;
 mod user_controller;
+pub mod warm_pool_controller;
 mod web_socket_controller;
 pub mod controllers_center {
     use axum::Router;
 
     use crate::app_state::AppState;
+    use crate::controllers::admin_controller;
+    use crate::controllers::allocation_queue_controller;
     use crate::controllers::friend_controller;
     use crate::controllers::game_server_controller;
     use crate::controllers::in_game_controller;
+    use crate::controllers::loadout_controller;
+    use crate::controllers::lobby_chat_controller;
     use crate::controllers::lobby_controller;
+    use crate::controllers::matchmaking_controller;
+    use crate::controllers::message_controller;
+    use crate::controllers::rating_controller;
     use crate::controllers::user_controller;
     use crate::controllers::web_socket_controller;
 
@@ -51,6 +66,27 @@ pub mod controllers_center {
                 "/friend/remove",
                 axum::routing::post(friend_controller::remove_friend),
             )
+            .route(
+                "/friend/block",
+                axum::routing::post(friend_controller::block_user),
+            )
+            .route(
+                "/friend/unblock",
+                axum::routing::post(friend_controller::unblock_user),
+            )
+            .route(
+                "/message/send",
+                axum::routing::post(message_controller::send_message),
+            )
+            .route(
+                "/message/conversation/get",
+                axum::routing::get(message_controller::get_conversation),
+            )
+            .route(
+                "/message/mark_read",
+                axum::routing::post(message_controller::mark_messages_read),
+            )
+            .route("/lobby", axum::routing::get(lobby_controller::get_lobby))
             .route(
                 "/lobby/create",
                 axum::routing::post(lobby_controller::create_lobby),
@@ -79,14 +115,122 @@ pub mod controllers_center {
                 "/lobby/kick",
                 axum::routing::post(lobby_controller::kick_member),
             )
+            .route(
+                "/lobby/vote_kick/start",
+                axum::routing::post(lobby_controller::start_vote_kick),
+            )
+            .route(
+                "/lobby/vote_kick/vote",
+                axum::routing::post(lobby_controller::vote_kick),
+            )
+            .route(
+                "/lobby/join_code/create",
+                axum::routing::post(lobby_controller::create_join_code),
+            )
+            .route(
+                "/lobby/join_by_code",
+                axum::routing::post(lobby_controller::join_lobby_by_code),
+            )
+            .route(
+                "/lobby/join_link",
+                axum::routing::get(lobby_controller::open_join_link),
+            )
+            .route(
+                "/lobby/join_request/send",
+                axum::routing::post(lobby_controller::send_join_request),
+            )
+            .route(
+                "/lobby/join_request/approve",
+                axum::routing::post(lobby_controller::approve_join_request),
+            )
+            .route(
+                "/lobby/join_request/reject",
+                axum::routing::post(lobby_controller::reject_join_request),
+            )
+            .route(
+                "/lobby/ready",
+                axum::routing::post(lobby_controller::set_ready),
+            )
+            .route(
+                "/lobby/spectate",
+                axum::routing::post(lobby_controller::set_spectator),
+            )
+            .route(
+                "/lobby/match_settings/set",
+                axum::routing::post(lobby_controller::set_match_settings),
+            )
+            .route(
+                "/lobby/loadout/set",
+                axum::routing::post(loadout_controller::set_loadout),
+            )
+            .route(
+                "/catalog/get",
+                axum::routing::get(loadout_controller::get_catalog),
+            )
+            .route(
+                "/lobby/ready_check/start",
+                axum::routing::post(lobby_controller::start_ready_check),
+            )
+            .route(
+                "/lobby/chat/send",
+                axum::routing::post(lobby_chat_controller::send_chat_message),
+            )
+            .route(
+                "/lobby/chat/get",
+                axum::routing::get(lobby_chat_controller::get_chat_history),
+            )
+            .route(
+                "/matchmaking/enqueue",
+                axum::routing::post(matchmaking_controller::enqueue_lobby),
+            )
+            .route(
+                "/matchmaking/dequeue",
+                axum::routing::post(matchmaking_controller::dequeue_lobby),
+            )
             .route(
                 "/game_server/create",
                 axum::routing::post(game_server_controller::create_game_server),
             )
+            .route(
+                "/game_server/queue/cancel",
+                axum::routing::post(allocation_queue_controller::cancel_queued_allocation),
+            )
             .route(
                 "/game_server/drop",
                 axum::routing::post(game_server_controller::drop_game_server),
             )
+            .route(
+                "/game_server/ready",
+                axum::routing::post(game_server_controller::mark_game_server_ready),
+            )
+            .route(
+                "/game_server/heartbeat",
+                axum::routing::post(game_server_controller::game_server_heartbeat),
+            )
+            .route(
+                "/game_server/roster",
+                axum::routing::get(game_server_controller::get_match_roster),
+            )
+            .route(
+                "/game_server/report_result",
+                axum::routing::post(rating_controller::report_match_result),
+            )
+            .route(
+                "/admin/game_server/logs",
+                axum::routing::get(admin_controller::get_game_server_logs),
+            )
+            .route(
+                "/admin/game_server/logs/follow",
+                axum::routing::post(admin_controller::follow_game_server_logs),
+            )
+            .route(
+                "/admin/game_server/logs/unfollow",
+                axum::routing::post(admin_controller::unfollow_game_server_logs),
+            )
+            .route(
+                "/rating/get",
+                axum::routing::get(rating_controller::get_rating),
+            )
             .route(
                 "/in_game/character_stats/get",
                 axum::routing::get(in_game_controller::get_character_stats),
@@ -0,0 +1,385 @@
+use redis::{AsyncCommands, ExistenceCheck, SetExpiry, SetOptions};
+use serde_json::json;
+use std::collections::{HashMap, HashSet};
+
+use axum::{
+    Json,
+    extract::{Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+};
+use sqlx::PgPool;
+
+use crate::{
+    app_state::AppState,
+    auth::AuthUser,
+    controllers::game_server_controller,
+    global_vars::USERNAME_REGEX,
+    models::rating::{MatchResult, PlayerRating},
+};
+
+const DEFAULT_RATING: f64 = 1500.0;
+//New players move faster until their rating settles
+const PROVISIONAL_GAMES: i32 = 30;
+const PROVISIONAL_K_FACTOR: f64 = 40.0;
+const K_FACTOR: f64 = 20.0;
+
+pub async fn get_rating(
+    State(app_state_): State<AppState>,
+    claims: AuthUser,
+    query_params: Query<HashMap<String, String>>,
+) -> impl IntoResponse {
+    let username = query_params
+        .get("username")
+        .cloned()
+        .unwrap_or(claims.username.clone());
+
+    if !USERNAME_REGEX.is_match(&username) {
+        return (StatusCode::BAD_REQUEST, "Invalid username format !").into_response();
+    }
+
+    match load_ratings(&app_state_.connection_pool, &vec![username.clone()]).await {
+        Some(ratings) => {
+            if let Some(player_rating) = ratings.get(&username) {
+                return (StatusCode::OK, Json(player_rating.clone())).into_response();
+            }
+        }
+        None => {}
+    }
+    return (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "Error finishing the request, please try again !",
+    )
+        .into_response();
+}
+
+pub async fn report_match_result(
+    State(app_state_): State<AppState>,
+    Query(query_payload): Query<HashMap<String, String>>,
+    Json(match_result): Json<MatchResult>,
+) -> impl IntoResponse {
+    let (Some(server_id), Some(token)) =
+        (query_payload.get("server_id"), query_payload.get("token"))
+    else {
+        return (StatusCode::BAD_REQUEST, "Missing server id or token !").into_response();
+    };
+    if let Err(err) = game_server_controller::check_game_server_token(
+        server_id,
+        token,
+        app_state_.redis_conn.clone(),
+    )
+    .await
+    {
+        return err.into_response();
+    }
+
+    if match_result.teams.len() < 2 || match_result.teams.iter().any(|team| team.is_empty()) {
+        return (
+            StatusCode::BAD_REQUEST,
+            "At least two non empty teams are required !",
+        )
+            .into_response();
+    }
+    if let Some(winning_team) = match_result.winning_team {
+        if winning_team >= match_result.teams.len() {
+            return (StatusCode::BAD_REQUEST, "Invalid winning team !").into_response();
+        }
+    }
+
+    //Only players of the match's lobbies can be rated
+    let mut redis_conn = app_state_.redis_conn.clone();
+    let Ok(lobby_ids) = AsyncCommands::smembers::<_, HashSet<String>>(
+        &mut redis_conn,
+        format!("match:{}:lobbies", server_id),
+    )
+    .await
+    else {
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Error finishing the request, please try again !",
+        )
+            .into_response();
+    };
+    if lobby_ids.is_empty() {
+        return (StatusCode::NOT_FOUND, "Match not found !").into_response();
+    }
+    //Spectators watch the match without playing it
+    let mut match_players: HashSet<String> = HashSet::new();
+    let mut match_spectators: HashSet<String> = HashSet::new();
+    for lobby_id in lobby_ids.iter() {
+        let key_list = format!("lobby:{}", lobby_id);
+        let mut pipe = redis::pipe();
+        pipe.sdiff(&[
+            format!("{}:members", &key_list),
+            format!("{}:spectators", &key_list),
+        ])
+        .smembers(format!("{}:spectators", &key_list));
+        if let Ok((player_set, spectator_set)) = pipe
+            .query_async::<(HashSet<String>, HashSet<String>)>(&mut redis_conn)
+            .await
+        {
+            match_players.extend(player_set);
+            match_spectators.extend(spectator_set);
+        }
+    }
+    let mut reported_players: HashSet<&String> = HashSet::new();
+    for player in match_result.teams.iter().flatten() {
+        if match_spectators.contains(player) {
+            return (
+                StatusCode::BAD_REQUEST,
+                "Spectators can't be in a match result !",
+            )
+                .into_response();
+        }
+        if !match_players.contains(player) || !reported_players.insert(player) {
+            return (StatusCode::BAD_REQUEST, "Invalid player in match result !").into_response();
+        }
+    }
+
+    //A match result can only be applied once, the key is released again if saving the ratings fails
+    let result_reported_key = format!("match:{}:result_reported", server_id);
+    match AsyncCommands::set_options::<_, _, bool>(
+        &mut redis_conn,
+        &result_reported_key,
+        "1",
+        SetOptions::default()
+            .conditional_set(ExistenceCheck::NX)
+            .with_expiration(SetExpiry::EX(24 * 3600)),
+    )
+    .await
+    {
+        Ok(true) => {}
+        Ok(false) => {
+            return (StatusCode::CONFLICT, "Match result already reported !").into_response();
+        }
+        Err(_) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Error finishing the request, please try again !",
+            )
+                .into_response();
+        }
+    }
+
+    let players: Vec<String> = reported_players.into_iter().cloned().collect();
+    if let Some(ratings) = load_ratings(&app_state_.connection_pool, &players).await {
+        let new_ratings = calculate_new_ratings(&match_result, &ratings);
+        if save_ratings(&app_state_.connection_pool, &new_ratings).await {
+            return (StatusCode::CREATED, Json(json!({ "ratings": new_ratings }))).into_response();
+        }
+    }
+    let _ = AsyncCommands::del::<_, ()>(&mut redis_conn, &result_reported_key).await;
+    return (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "Error finishing the request, please try again !",
+    )
+        .into_response();
+}
+
+async fn save_ratings(connection_pool: &PgPool, new_ratings: &Vec<PlayerRating>) -> bool {
+    let Ok(mut transaction) = connection_pool.begin().await else {
+        return false;
+    };
+    for player_rating in new_ratings.iter() {
+        if let Err(_) = sqlx::query(
+            "Insert into ratings (username, rating, games_played) values ($1, $2, $3) on conflict (username) do update set rating = $2, games_played = $3",
+        )
+        .bind(&player_rating.username)
+        .bind(player_rating.rating)
+        .bind(player_rating.games_played)
+        .execute(&mut *transaction)
+        .await
+        {
+            return false;
+        }
+    }
+    return transaction.commit().await.is_ok();
+}
+
+//Ratings of the given players, players that never played get the default rating
+pub async fn load_ratings(
+    connection_pool: &PgPool,
+    usernames: &Vec<String>,
+) -> Option<HashMap<String, PlayerRating>> {
+    let Ok(rating_rows) = sqlx::query_as::<_, PlayerRating>(
+        "Select username, rating, games_played from ratings where username = any($1)",
+    )
+    .bind(usernames)
+    .fetch_all(connection_pool)
+    .await
+    else {
+        return None;
+    };
+    let mut ratings: HashMap<String, PlayerRating> = usernames
+        .iter()
+        .map(|username| {
+            (
+                username.clone(),
+                PlayerRating::new(username, DEFAULT_RATING, 0),
+            )
+        })
+        .collect();
+    for player_rating in rating_rows {
+        ratings.insert(player_rating.username.clone(), player_rating);
+    }
+    return Some(ratings);
+}
+
+//Party rating used for matchmaking is the average rating of its members
+pub async fn get_party_rating(connection_pool: &PgPool, members: &Vec<String>) -> Option<f64> {
+    let ratings = load_ratings(connection_pool, members).await?;
+    if ratings.is_empty() {
+        return Some(DEFAULT_RATING);
+    }
+    let rating_sum: f64 = ratings.values().map(|player| player.rating).sum();
+    return Some(rating_sum / ratings.len() as f64);
+}
+
+//Team Elo: every player plays against the average rating of each other team
+fn calculate_new_ratings(
+    match_result: &MatchResult,
+    ratings: &HashMap<String, PlayerRating>,
+) -> Vec<PlayerRating> {
+    let team_ratings: Vec<f64> = match_result
+        .teams
+        .iter()
+        .map(|team| {
+            team.iter()
+                .map(|player| ratings.get(player).map_or(DEFAULT_RATING, |r| r.rating))
+                .sum::<f64>()
+                / team.len() as f64
+        })
+        .collect();
+    let mut new_ratings: Vec<PlayerRating> = Vec::new();
+    for (team_index, team) in match_result.teams.iter().enumerate() {
+        for player in team.iter() {
+            let Some(player_rating) = ratings.get(player) else {
+                continue;
+            };
+            let mut rating_change = 0.0;
+            for (opponent_index, opponent_rating) in team_ratings.iter().enumerate() {
+                if opponent_index == team_index {
+                    continue;
+                }
+                let expected_score =
+                    1.0 / (1.0 + 10_f64.powf((opponent_rating - player_rating.rating) / 400.0));
+                let actual_score = match match_result.winning_team {
+                    Some(winning_team) if winning_team == team_index => 1.0,
+                    Some(winning_team) if winning_team == opponent_index => 0.0,
+                    _ => 0.5,
+                };
+                rating_change += actual_score - expected_score;
+            }
+            let k_factor = if player_rating.games_played < PROVISIONAL_GAMES {
+                PROVISIONAL_K_FACTOR
+            } else {
+                K_FACTOR
+            };
+            let opponent_num = (team_ratings.len() - 1) as f64;
+            new_ratings.push(PlayerRating::new(
+                player,
+                player_rating.rating + k_factor * rating_change / opponent_num,
+                player_rating.games_played + 1,
+            ));
+        }
+    }
+    return new_ratings;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ratings(player_ratings: &[(&str, f64, i32)]) -> HashMap<String, PlayerRating> {
+        return player_ratings
+            .iter()
+            .map(|(username, rating, games_played)| {
+                (
+                    username.to_string(),
+                    PlayerRating::new(username, *rating, *games_played),
+                )
+            })
+            .collect();
+    }
+
+    fn match_result(teams: &[&[&str]], winning_team: Option<usize>) -> MatchResult {
+        MatchResult {
+            teams: teams
+                .iter()
+                .map(|team| team.iter().map(|player| player.to_string()).collect())
+                .collect(),
+            winning_team,
+        }
+    }
+
+    fn new_rating_of(new_ratings: &Vec<PlayerRating>, username: &str) -> f64 {
+        return new_ratings
+            .iter()
+            .find(|player| player.username == username)
+            .unwrap()
+            .rating;
+    }
+
+    #[test]
+    fn even_match_moves_by_half_the_k_factor() {
+        let current_ratings = ratings(&[("alice", 1500.0, 50), ("bob", 1500.0, 50)]);
+        let new_ratings = calculate_new_ratings(
+            &match_result(&[&["alice"], &["bob"]], Some(0)),
+            &current_ratings,
+        );
+        assert_eq!(
+            new_rating_of(&new_ratings, "alice"),
+            1500.0 + K_FACTOR / 2.0
+        );
+        assert_eq!(new_rating_of(&new_ratings, "bob"), 1500.0 - K_FACTOR / 2.0);
+        assert!(new_ratings.iter().all(|player| player.games_played == 51));
+    }
+
+    #[test]
+    fn provisional_players_move_faster() {
+        let current_ratings = ratings(&[("alice", 1500.0, 0), ("bob", 1500.0, 50)]);
+        let new_ratings = calculate_new_ratings(
+            &match_result(&[&["alice"], &["bob"]], Some(0)),
+            &current_ratings,
+        );
+        assert_eq!(
+            new_rating_of(&new_ratings, "alice"),
+            1500.0 + PROVISIONAL_K_FACTOR / 2.0
+        );
+        assert_eq!(new_rating_of(&new_ratings, "bob"), 1500.0 - K_FACTOR / 2.0);
+    }
+
+    #[test]
+    fn draw_pulls_ratings_together() {
+        let current_ratings = ratings(&[("alice", 1700.0, 50), ("bob", 1300.0, 50)]);
+        let new_ratings = calculate_new_ratings(
+            &match_result(&[&["alice"], &["bob"]], None),
+            &current_ratings,
+        );
+        assert!(new_rating_of(&new_ratings, "alice") < 1700.0);
+        assert!(new_rating_of(&new_ratings, "bob") > 1300.0);
+    }
+
+    #[test]
+    fn teams_play_against_the_average_rating() {
+        let current_ratings = ratings(&[
+            ("alice", 1400.0, 50),
+            ("bob", 1600.0, 50),
+            ("carol", 1500.0, 50),
+        ]);
+        let new_ratings = calculate_new_ratings(
+            &match_result(&[&["alice", "bob"], &["carol"]], Some(1)),
+            &current_ratings,
+        );
+        assert_eq!(
+            new_rating_of(&new_ratings, "carol"),
+            1500.0 + K_FACTOR / 2.0
+        );
+        //Players without a loaded rating are left out
+        let new_ratings = calculate_new_ratings(
+            &match_result(&[&["alice", "dave"], &["carol"]], Some(0)),
+            &current_ratings,
+        );
+        assert_eq!(new_ratings.len(), 2);
+    }
+}
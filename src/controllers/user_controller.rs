@@ -1,5 +1,5 @@
 use jsonwebtoken::{EncodingKey, Header, encode};
-use redis::{AsyncCommands, FromRedisValue};
+use redis::{AsyncCommands, FromRedisValue, SortedSetAddOptions};
 use serde_json::{Map, Value, json};
 use std::{
     collections::{HashMap, HashSet},
@@ -20,7 +20,12 @@ use crate::{
     auth::{AuthUser, Claims, get_jwt_secret},
     controllers::lobby_controller,
     global_vars::USERNAME_REGEX,
-    models::{game_server::GameServer, in_game::CharacterInfo, lobby::LobbyInfo, user::User},
+    models::{
+        game_server::GameServer,
+        in_game::CharacterInfo,
+        lobby::{LobbyInfo, LobbyStatus},
+        user::User,
+    },
 };
 
 pub async fn create_user(
@@ -141,6 +146,7 @@ pub async fn login(
 
                     let mut lobby_info_response: serde_json::Value = json!({});
                     let mut game_server_info = GameServer {
+                        server_id: "".to_string(),
                         address: "".to_string(),
                         host: "".to_string(),
                     };
@@ -165,9 +171,16 @@ pub async fn login(
                                     let mut pipe = redis::pipe();
                                     pipe.atomic()
                                         .sadd(&lobby_member_keylist, in_username)
-                                        .hgetall(lobby_info_keylist);
-                                    if let Ok((_, lobby_info_map)) = pipe
-                                        .query_async::<((), HashMap<String, String>)>(
+                                        //Only a first join sets the join time, it decides leader handovers
+                                        .zadd_options(
+                                            format!("{}:joined", lobby_info_keylist),
+                                            in_username,
+                                            lobby_controller::get_join_timestamp(),
+                                            &SortedSetAddOptions::add_only(),
+                                        )
+                                        .hgetall(&lobby_info_keylist);
+                                    if let Ok((_, _, lobby_info_map)) = pipe
+                                        .query_async::<((), (), HashMap<String, String>)>(
                                             &mut redis_conn,
                                         )
                                         .await
@@ -199,10 +212,11 @@ pub async fn login(
                                                         .clone()
                                                         .parse()
                                                         .unwrap_or(5),
+                                                    //A lobby without a valid status is shown as idle rather than in a match
                                                     status: lobby_info_map
                                                         .get("status")
-                                                        .expect("Error getting value from map")
-                                                        .clone(),
+                                                        .and_then(|status| status.parse().ok())
+                                                        .unwrap_or(LobbyStatus::Ready),
                                                 };
                                                 lobby_info_response = json!({
                                                     "lobby_name": lobby_info.lobby_name,
@@ -0,0 +1,298 @@
+use redis::{AsyncCommands, aio::MultiplexedConnection};
+use serde_json::json;
+use std::{collections::HashSet, time::Duration};
+
+use crate::{
+    app_state::AppState,
+    auth::generate_random_code,
+    controllers::{game_server_controller, lobby_controller},
+    game_server_ports,
+    global_vars::{
+        BACKEND_INSTANCE_ID, GAME_CATALOG, GAME_SERVER_HEARTBEAT_TIMEOUT_SECS,
+        GAME_SERVER_LOCAL_CAPACITY, GAME_SERVER_REUSE, GAME_SERVER_WARM_POOL_SIZE,
+    },
+    models::game_server::GameServer,
+};
+
+//Warm servers of one backend instance launched but not ready yet, then ready and waiting for a match:
+//warm_game_servers:{instance_id}:starting and warm_game_servers:{instance_id}:idle.
+//Each instance only claims and prunes its own servers, their processes aren't visible to the others
+const WARM_POOL_KEY_PREFIX: &str = "warm_game_servers";
+const WARM_POOL_INTERVAL_SECS: u64 = 5;
+const WARM_SERVER_ID_PREFIX: &str = "warm_";
+
+fn warm_starting_key(owner: &str) -> String {
+    return format!("{}:{}:starting", WARM_POOL_KEY_PREFIX, owner);
+}
+
+fn warm_idle_key(owner: &str) -> String {
+    return format!("{}:{}:idle", WARM_POOL_KEY_PREFIX, owner);
+}
+
+//Warm servers are local to this backend, host agents only run cold started servers
+pub fn is_warm_server(server_id: &str) -> bool {
+    return server_id.starts_with(WARM_SERVER_ID_PREFIX);
+}
+
+//Keeps GAME_SERVER_WARM_POOL_SIZE servers started or idle, replacing the ones that get a match or die
+pub async fn run_warm_pool(app_state_: AppState) {
+    if *GAME_SERVER_WARM_POOL_SIZE == 0 {
+        return;
+    }
+    let mut interval = tokio::time::interval(Duration::from_secs(WARM_POOL_INTERVAL_SECS));
+    loop {
+        interval.tick().await;
+        let warm_server_num = prune_warm_pool(&app_state_).await;
+        for _ in warm_server_num..*GAME_SERVER_WARM_POOL_SIZE {
+            if !start_warm_server(&app_state_).await {
+                break;
+            }
+        }
+    }
+}
+
+//Drops the pooled servers whose process is gone or whose heartbeat expired, returns how many are left
+async fn prune_warm_pool(app_state_: &AppState) -> usize {
+    let mut redis_conn = app_state_.redis_conn.clone();
+    let mut pipe = redis::pipe();
+    pipe.smembers(warm_starting_key(&BACKEND_INSTANCE_ID))
+        .smembers(warm_idle_key(&BACKEND_INSTANCE_ID));
+    let Ok((starting_set, idle_set)) = pipe
+        .query_async::<(HashSet<String>, HashSet<String>)>(&mut redis_conn)
+        .await
+    else {
+        return *GAME_SERVER_WARM_POOL_SIZE;
+    };
+    let mut warm_server_num = 0;
+    for server_id in starting_set.iter().chain(idle_set.iter()) {
+        let is_running = app_state_
+            .game_server_exe_map
+            .read()
+            .await
+            .contains_key(server_id);
+        let is_alive = !idle_set.contains(server_id)
+            || AsyncCommands::exists::<_, bool>(
+                &mut redis_conn,
+                format!("match:{}:heartbeat", server_id),
+            )
+            .await
+            .unwrap_or(true);
+        if is_running && is_alive {
+            warm_server_num += 1;
+            continue;
+        }
+        println!("Removing warm game server {:?} from the pool", server_id);
+        game_server_controller::stop_game_server_process(app_state_, server_id).await;
+        discard_warm_server(server_id, redis_conn.clone()).await;
+    }
+    return warm_server_num;
+}
+
+async fn start_warm_server(app_state_: &AppState) -> bool {
+    let mut redis_conn = app_state_.redis_conn.clone();
+    //Warm servers load the default map and travel to the match map once assigned
+    let Some(match_settings) = GAME_CATALOG.default_match_settings() else {
+        return false;
+    };
+    let Some(map) = GAME_CATALOG.find_map(&match_settings.map) else {
+        return false;
+    };
+    if app_state_.game_server_exe_map.read().await.len() >= *GAME_SERVER_LOCAL_CAPACITY {
+        return false;
+    }
+    let server_id = format!(
+        "{}{}",
+        WARM_SERVER_ID_PREFIX,
+        generate_random_code(8).to_lowercase()
+    );
+    let Some(port) = app_state_.game_server_ports.lock().await.lease(&server_id) else {
+        return false;
+    };
+    let server_info = GameServer::new(&server_id, &game_server_ports::advertised_address(port), "");
+    let ready_token = generate_random_code(32);
+    //match:warm_haha:startup - {game_server: {}, warm: true, owner: ""}, until the server reports ready
+    let mut pipe = redis::pipe();
+    pipe.atomic()
+        .set(
+            format!("match:{}:startup", server_id),
+            json!({
+                "game_server": server_info,
+                "warm": true,
+                "owner": *BACKEND_INSTANCE_ID
+            })
+            .to_string(),
+        )
+        .set(format!("match:{}:token", server_id), &ready_token)
+        .sadd(warm_starting_key(&BACKEND_INSTANCE_ID), &server_id);
+    if let Err(_) = pipe.query_async::<()>(&mut redis_conn).await {
+        app_state_
+            .game_server_ports
+            .lock()
+            .await
+            .release(&server_id);
+        return false;
+    }
+    let launch_request = game_server_controller::build_launch_request(
+        &server_id,
+        port,
+        &map.level,
+        &match_settings.game_mode,
+        ready_token,
+    );
+    let Ok(mut exec) = app_state_.game_server_launcher.spawn(&launch_request) else {
+        app_state_
+            .game_server_ports
+            .lock()
+            .await
+            .release(&server_id);
+        discard_warm_server(&server_id, redis_conn).await;
+        return false;
+    };
+    app_state_
+        .game_server_logs
+        .capture_output(&server_id, &mut exec);
+    {
+        let mut game_server_exe_map_write = app_state_.game_server_exe_map.write().await;
+        game_server_exe_map_write.insert(server_id.clone(), exec);
+    }
+    let startup_app_state = app_state_.clone();
+    tokio::spawn(async move {
+        game_server_controller::watch_game_server_startup(startup_app_state, server_id).await;
+    });
+    return true;
+}
+
+//A warm server that reported ready, or finished a match and is reused, waits for its next match in the pool
+//of the instance running it. The ready or drop call can reach any instance.
+//match:warm_haha:warm - {server_id: "", address: "", host: "", owner: ""}, kept while the server is in the pool or a match
+pub async fn mark_warm_server_idle(
+    server_id: &String,
+    server_info: &serde_json::Value,
+    owner: &str,
+    mut redis_conn: MultiplexedConnection,
+) -> bool {
+    let mut warm_info = server_info.clone();
+    warm_info["owner"] = json!(owner);
+    let heartbeat_key = format!("match:{}:heartbeat", server_id);
+    let mut pipe = redis::pipe();
+    pipe.atomic()
+        .set(format!("match:{}:warm", server_id), warm_info.to_string())
+        .hset_multiple(
+            &heartbeat_key,
+            &[
+                ("player_count", "0".to_string()),
+                ("phase", "waiting".to_string()),
+                (
+                    "last_seen",
+                    lobby_controller::get_timestamp_secs().to_string(),
+                ),
+            ],
+        )
+        .expire(&heartbeat_key, *GAME_SERVER_HEARTBEAT_TIMEOUT_SECS)
+        .srem(warm_starting_key(owner), server_id)
+        .sadd(warm_idle_key(owner), server_id);
+    return pipe.query_async::<()>(&mut redis_conn).await.is_ok();
+}
+
+//Takes an idle warm server out of the pool for a match
+pub async fn claim_warm_server(app_state_: &AppState) -> Option<GameServer> {
+    if *GAME_SERVER_WARM_POOL_SIZE == 0 {
+        return None;
+    }
+    let mut redis_conn = app_state_.redis_conn.clone();
+    loop {
+        let server_id = AsyncCommands::spop::<_, Option<String>>(
+            &mut redis_conn,
+            warm_idle_key(&BACKEND_INSTANCE_ID),
+        )
+        .await
+        .ok()??;
+        let is_running = app_state_
+            .game_server_exe_map
+            .read()
+            .await
+            .contains_key(&server_id);
+        let server_info_opt = AsyncCommands::get::<_, Option<String>>(
+            &mut redis_conn,
+            format!("match:{}:warm", server_id),
+        )
+        .await
+        .ok()
+        .flatten()
+        .and_then(|server_info_str| serde_json::from_str::<GameServer>(&server_info_str).ok());
+        if let (true, Some(server_info)) = (is_running, server_info_opt) {
+            return Some(server_info);
+        }
+        //Died while idle, the next tick of the pool replaces it
+        game_server_controller::stop_game_server_process(app_state_, &server_id).await;
+        discard_warm_server(&server_id, redis_conn.clone()).await;
+    }
+}
+
+//With GAME_SERVER_REUSE, a warm server whose match ended goes back to the pool instead of being stopped
+pub async fn recycle_warm_server(
+    app_state_: &AppState,
+    server_id: &String,
+    ready_token: &String,
+) -> bool {
+    if !*GAME_SERVER_REUSE || !is_warm_server(server_id) {
+        return false;
+    }
+    let mut redis_conn = app_state_.redis_conn.clone();
+    let Ok(Some(warm_info_str)) = AsyncCommands::get::<_, Option<String>>(
+        &mut redis_conn,
+        format!("match:{}:warm", server_id),
+    )
+    .await
+    else {
+        return false;
+    };
+    let Ok(warm_info) = serde_json::from_str::<serde_json::Value>(&warm_info_str) else {
+        return false;
+    };
+    let Some(owner) = warm_info["owner"].as_str() else {
+        return false;
+    };
+    //Only the owner sees the process, a dead server in another pool is pruned by its owner
+    if owner == BACKEND_INSTANCE_ID.as_str()
+        && !app_state_
+            .game_server_exe_map
+            .read()
+            .await
+            .contains_key(server_id)
+    {
+        return false;
+    }
+    let mut pipe = redis::pipe();
+    pipe.scard(warm_starting_key(owner))
+        .scard(warm_idle_key(owner));
+    let Ok((starting_num, idle_num)) = pipe.query_async::<(usize, usize)>(&mut redis_conn).await
+    else {
+        return false;
+    };
+    if starting_num + idle_num >= *GAME_SERVER_WARM_POOL_SIZE {
+        return false;
+    }
+    if let Err(_) = AsyncCommands::set::<_, _, ()>(
+        &mut redis_conn,
+        format!("match:{}:token", server_id),
+        ready_token,
+    )
+    .await
+    {
+        return false;
+    }
+    return mark_warm_server_idle(server_id, &warm_info, owner, redis_conn).await;
+}
+
+async fn discard_warm_server(server_id: &String, mut redis_conn: MultiplexedConnection) {
+    let mut pipe = redis::pipe();
+    pipe.srem(warm_starting_key(&BACKEND_INSTANCE_ID), server_id)
+        .srem(warm_idle_key(&BACKEND_INSTANCE_ID), server_id)
+        .del(format!("match:{}:startup", server_id))
+        .del(format!("match:{}:token", server_id))
+        .del(format!("match:{}:warm", server_id))
+        .del(format!("match:{}:heartbeat", server_id))
+        .del(format!("match:{}:assignment", server_id));
+    let _ = pipe.query_async::<()>(&mut redis_conn).await;
+}
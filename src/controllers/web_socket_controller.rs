@@ -1,40 +1,35 @@
 use axum::{
     extract::{
-        Query, State,
+        State,
         ws::{Message, WebSocket, WebSocketUpgrade},
     },
     http::StatusCode,
     response::IntoResponse,
 };
 
-use std::collections::HashMap;
-
 use futures_util::{SinkExt, stream::StreamExt};
+use redis::AsyncCommands;
 use serde_json::{Map, Value, json};
 
 use crate::app_state::AppState;
+use crate::auth::AuthUser;
+use crate::controllers::{lobby_chat_controller, lobby_controller};
 use crate::global_vars::USERNAME_REGEX;
 
+//The socket belongs to the user of the bearer token, the username is never taken from the client
 pub async fn handle_web_socket_request(
     web_socket_upgrade: WebSocketUpgrade,
     State(app_state): State<AppState>,
-    query_params: Query<HashMap<String, String>>,
+    auth_user: AuthUser,
 ) -> impl IntoResponse {
-    if query_params.is_empty() {
-        return (StatusCode::BAD_REQUEST, "Params empty !").into_response();
+    if !USERNAME_REGEX.is_match(&auth_user.username) {
+        return (StatusCode::BAD_REQUEST, "Invalid username format !").into_response();
     }
 
-    if let Some(tmp_user_id) = query_params.get("username") {
-        if !USERNAME_REGEX.is_match(tmp_user_id) {
-            return (StatusCode::BAD_REQUEST, "Invalid username format !").into_response();
-        }
-
-        let username = tmp_user_id.clone();
-        return web_socket_upgrade.on_upgrade(|socket| async move {
-            handle_socket(socket, app_state.clone(), &username.clone()).await
-        });
-    }
-    return (StatusCode::BAD_REQUEST, "No username found !").into_response();
+    let username = auth_user.username;
+    return web_socket_upgrade.on_upgrade(|socket| async move {
+        handle_socket(socket, app_state.clone(), &username.clone()).await
+    });
 }
 
 // The client and server will communicate based on the data format below
@@ -64,7 +59,31 @@ pub async fn handle_socket(socket: WebSocket, app_state_: AppState, username: &S
         println!("User {:?} is online now !", username);
     }
 
-    let receive_task = tokio::spawn(async move {
+    let mut redis_conn = app_state_.redis_conn.clone();
+    let _ =
+        AsyncCommands::del::<_, ()>(&mut redis_conn, format!("user:{}:disconnect", username)).await;
+    lobby_controller::refresh_presence(username, redis_conn.clone()).await;
+    //Keeps the user online while the socket stays open
+    let presence_redis_conn = redis_conn.clone();
+    let presence_username = username.clone();
+    let presence_task = tokio::spawn(async move {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(
+            lobby_controller::PRESENCE_REFRESH_SECS,
+        ));
+        loop {
+            interval.tick().await;
+            lobby_controller::refresh_presence(&presence_username, presence_redis_conn.clone())
+                .await;
+        }
+    });
+
+    lobby_chat_controller::send_chat_history(username, redis_conn.clone()).await;
+
+    let socket_app_state = app_state_.clone();
+    let socket_username = username.clone();
+    let reply_sender = passive_channel_sender.clone();
+
+    let mut receive_task = tokio::spawn(async move {
         while let Some(Ok(message)) = receiver.next().await {
             match message {
                 Message::Text(text) => {
@@ -77,18 +96,19 @@ pub async fn handle_socket(socket: WebSocket, app_state_: AppState, username: &S
                                 && let Some(action_str) = action_field.as_str()
                                 && let Some(payload) = payload_field.as_object()
                             {
-                                // if let Some(result) = get_friendlist(
-                                //     &app_state_.connection_pool,
-                                //     resource_str,
-                                //     action_str,
-                                //     payload,
-                                // )
-                                // .await
-                                // {
-                                //     if let Err(e) = passive_channel_sender.send(result) {
-                                //         println!("Error sending message to mpsc chancel: {}", e);
-                                //     }
-                                // }
+                                if let Some(result) = handle_web_socket_message(
+                                    &socket_app_state,
+                                    &socket_username,
+                                    resource_str,
+                                    action_str,
+                                    payload,
+                                )
+                                .await
+                                {
+                                    if let Err(e) = reply_sender.send(result) {
+                                        println!("Error sending message to mpsc chancel: {}", e);
+                                    }
+                                }
                             }
                         }
                     }
@@ -101,7 +121,7 @@ pub async fn handle_socket(socket: WebSocket, app_state_: AppState, username: &S
         }
     });
 
-    let sender_task = tokio::spawn(async move {
+    let mut sender_task = tokio::spawn(async move {
         while let Some(message) = receiver_in_channel.recv().await {
             let temp = message.clone();
             if let Err(e) = sender.send(Message::Text(message.into())).await {
@@ -111,4 +131,70 @@ pub async fn handle_socket(socket: WebSocket, app_state_: AppState, username: &S
             }
         }
     });
+
+    //Whichever side stops first ends the connection
+    tokio::select! {
+        _ = &mut receive_task => sender_task.abort(),
+        _ = &mut sender_task => receive_task.abort(),
+    }
+    presence_task.abort();
+
+    {
+        let mut map = app_state_.clients_map.write().await;
+        //A newer connection of the same user may have replaced this one already
+        if let Some(current_sender) = map.get(username)
+            && !current_sender.same_channel(&sender_in_channel)
+        {
+            return;
+        }
+        map.remove(username);
+        println!("User {:?} is offline now !", username);
+    }
+
+    app_state_.game_server_logs.unfollow_all(username).await;
+    lobby_controller::handle_user_disconnect(username, app_state_.redis_conn.clone()).await;
+}
+
+//Handles a message sent by the client, returns the reply for the client if any
+async fn handle_web_socket_message(
+    app_state_: &AppState,
+    username: &String,
+    resource: &str,
+    action: &str,
+    payload: &Map<String, Value>,
+) -> Option<String> {
+    match (resource, action) {
+        ("lobby_chat", "send") => {
+            let message = payload
+                .get("message")
+                .and_then(|message| message.as_str())
+                .unwrap_or("");
+            if let Err((_, error_message)) = lobby_chat_controller::send_lobby_chat_message(
+                username,
+                message,
+                app_state_.redis_conn.clone(),
+            )
+            .await
+            {
+                return Some(
+                    json!({
+                        "resource": "lobby_chat",
+                        "action": "error",
+                        "payload": {
+                            "message": error_message
+                        }
+                    })
+                    .to_string(),
+                );
+            }
+            return None;
+        }
+        ("lobby_chat", "history") => {
+            lobby_chat_controller::send_chat_history(username, app_state_.redis_conn.clone()).await;
+            return None;
+        }
+        _ => {
+            return None;
+        }
+    }
 }
@@ -0,0 +1,199 @@
+use std::{
+    path::PathBuf,
+    process::{Child, Command, Stdio},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::global_vars::{
+    GAME_SERVER_ARGS, GAME_SERVER_EXECUTABLE, GAME_SERVER_LAUNCHER, GAME_SERVER_WORKING_DIR,
+};
+
+//Everything a launcher needs to start the server of one match.
+//Sent to host agents with port 0, the agent fills in a port of its own range
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct LaunchRequest {
+    pub server_id: String,
+    pub port: u16,
+    pub level: String,
+    pub game_mode: String,
+    pub roster_url: String,
+    //The server calls ready_url with &token={ready_token} once it accepts players,
+    //then heartbeat_url with &token=&player_count=&phase= while it runs
+    pub ready_url: String,
+    pub heartbeat_url: String,
+    pub ready_token: String,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum GameServerStatus {
+    Running,
+    //Exit code, None when the process was killed by a signal
+    Exited(Option<i32>),
+    Unknown,
+}
+
+//Starts, stops and polls game server processes
+pub trait GameServerLauncher: Send + Sync {
+    fn spawn(&self, launch_request: &LaunchRequest) -> std::io::Result<Child>;
+
+    fn stop(&self, game_server_process: &mut Child) -> std::io::Result<()> {
+        game_server_process.kill()?;
+        let _ = game_server_process.wait();
+        return Ok(());
+    }
+
+    fn status(&self, game_server_process: &mut Child) -> GameServerStatus {
+        match game_server_process.try_wait() {
+            Ok(Some(exit_status)) => GameServerStatus::Exited(exit_status.code()),
+            Ok(None) => GameServerStatus::Running,
+            Err(_) => GameServerStatus::Unknown,
+        }
+    }
+}
+
+//Launches a native executable, its arguments are built from a template where
+//{level}, {port}, {game_mode}, {server_id}, {roster_url}, {ready_url}, {heartbeat_url} and {ready_token} get replaced
+pub struct ProcessLauncher {
+    pub executable: PathBuf,
+    pub args_template: String,
+    pub working_dir: Option<PathBuf>,
+}
+
+impl ProcessLauncher {
+    fn build_args(&self, launch_request: &LaunchRequest) -> Vec<String> {
+        return self
+            .args_template
+            .split_whitespace()
+            .map(|arg| {
+                arg.replace("{level}", &launch_request.level)
+                    .replace("{port}", &launch_request.port.to_string())
+                    .replace("{game_mode}", &launch_request.game_mode)
+                    .replace("{server_id}", &launch_request.server_id)
+                    .replace("{roster_url}", &launch_request.roster_url)
+                    .replace("{ready_url}", &launch_request.ready_url)
+                    .replace("{heartbeat_url}", &launch_request.heartbeat_url)
+                    .replace("{ready_token}", &launch_request.ready_token)
+            })
+            .collect();
+    }
+}
+
+impl GameServerLauncher for ProcessLauncher {
+    fn spawn(&self, launch_request: &LaunchRequest) -> std::io::Result<Child> {
+        let mut command = Command::new(&self.executable);
+        command
+            .args(self.build_args(launch_request))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        if let Some(working_dir) = &self.working_dir {
+            command.current_dir(working_dir);
+        }
+        return command.spawn();
+    }
+}
+
+//Launches the fake_game_server binary built next to the backend, for local runs and CI without the Unreal build
+pub struct MockLauncher {
+    pub process_launcher: ProcessLauncher,
+}
+
+impl MockLauncher {
+    pub fn new() -> Self {
+        let fake_server_name = format!("fake_game_server{}", std::env::consts::EXE_SUFFIX);
+        let executable = std::env::current_exe()
+            .ok()
+            .and_then(|backend_exe| backend_exe.parent().map(|dir| dir.join(&fake_server_name)))
+            .unwrap_or(PathBuf::from(fake_server_name));
+        return Self {
+            process_launcher: ProcessLauncher {
+                executable,
+                args_template: GAME_SERVER_ARGS.clone(),
+                working_dir: None,
+            },
+        };
+    }
+}
+
+impl Default for MockLauncher {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
+impl GameServerLauncher for MockLauncher {
+    fn spawn(&self, launch_request: &LaunchRequest) -> std::io::Result<Child> {
+        println!(
+            "Starting fake game server {:?} on port {}",
+            launch_request.server_id, launch_request.port
+        );
+        return self.process_launcher.spawn(launch_request);
+    }
+}
+
+//Launcher picked by GAME_SERVER_LAUNCHER: "native" (default) or "mock"
+pub fn create_launcher() -> Box<dyn GameServerLauncher> {
+    match GAME_SERVER_LAUNCHER.as_str() {
+        "mock" => Box::new(MockLauncher::new()),
+        _ => Box::new(ProcessLauncher {
+            executable: PathBuf::from(GAME_SERVER_EXECUTABLE.as_str()),
+            args_template: GAME_SERVER_ARGS.clone(),
+            working_dir: GAME_SERVER_WORKING_DIR.as_ref().map(PathBuf::from),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn launch_request() -> LaunchRequest {
+        LaunchRequest {
+            server_id: "match_abc".to_string(),
+            port: 7780,
+            level: "Arena".to_string(),
+            game_mode: "deathmatch".to_string(),
+            roster_url: "http://backend/roster".to_string(),
+            ready_url: "http://backend/ready".to_string(),
+            heartbeat_url: "http://backend/heartbeat".to_string(),
+            ready_token: "secret".to_string(),
+        }
+    }
+
+    #[test]
+    fn fills_in_the_args_template() {
+        let process_launcher = ProcessLauncher {
+            executable: PathBuf::from("server"),
+            args_template: "{level}?game={game_mode} -port={port} -id={server_id} \
+                            -roster={roster_url} -ready={ready_url} -heartbeat={heartbeat_url} -token={ready_token}"
+                .to_string(),
+            working_dir: None,
+        };
+        assert_eq!(
+            process_launcher.build_args(&launch_request()),
+            vec![
+                "Arena?game=deathmatch",
+                "-port=7780",
+                "-id=match_abc",
+                "-roster=http://backend/roster",
+                "-ready=http://backend/ready",
+                "-heartbeat=http://backend/heartbeat",
+                "-token=secret",
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_args_without_placeholders() {
+        let process_launcher = ProcessLauncher {
+            executable: PathBuf::from("server"),
+            args_template: "  -log   -nosteam ".to_string(),
+            working_dir: None,
+        };
+        assert_eq!(
+            process_launcher.build_args(&launch_request()),
+            vec!["-log", "-nosteam"]
+        );
+    }
+}
@@ -0,0 +1,373 @@
+use redis::{AsyncCommands, aio::MultiplexedConnection};
+use serde_json::json;
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    fs::{self, File, OpenOptions},
+    io::{BufRead, BufReader, Read, Write},
+    path::PathBuf,
+    process::Child,
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::global_vars::{
+    GAME_SERVER_LOG_DIR, GAME_SERVER_LOG_FILES, GAME_SERVER_LOG_LINES, GAME_SERVER_LOG_MAX_BYTES,
+    GAME_SERVER_LOG_RETENTION_SECS,
+};
+
+const LOG_FLUSH_INTERVAL_MILLIS: u64 = 1000;
+//The Redis tail of a server outlives it by this long after its last line
+const LOG_TAIL_TTL_SECS: i64 = 3600;
+const LOG_PRUNE_INTERVAL_SECS: u64 = 3600;
+
+//Output of the game servers started by this process (backend or host agent): written to
+//{GAME_SERVER_LOG_DIR}/{server_id}-{unix secs}.log on this machine, the last GAME_SERVER_LOG_LINES lines
+//kept in memory while the server runs and copied to match:{id}:log so any backend can serve and stream them
+#[derive(Clone)]
+pub struct GameServerLogs {
+    buffers: Arc<Mutex<HashMap<String, ServerLogBuffer>>>,
+    //Lines not yet copied to Redis, see run_log_flusher
+    pending_lines: Arc<Mutex<HashMap<String, Vec<String>>>>,
+    redis_conn: MultiplexedConnection,
+}
+
+struct ServerLogBuffer {
+    lines: VecDeque<String>,
+    //stdout and stderr, the buffer is dropped once both are closed
+    open_streams: usize,
+}
+
+//Log file of one match, rotated to .1, .2... once it reaches GAME_SERVER_LOG_MAX_BYTES
+struct RotatingLogFile {
+    path: PathBuf,
+    file: File,
+    written_bytes: u64,
+}
+
+impl RotatingLogFile {
+    fn open(path: PathBuf) -> std::io::Result<Self> {
+        if let Some(log_dir) = path.parent() {
+            fs::create_dir_all(log_dir)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let written_bytes = file.metadata().map(|metadata| metadata.len()).unwrap_or(0);
+        return Ok(Self {
+            path,
+            file,
+            written_bytes,
+        });
+    }
+
+    fn write_line(&mut self, line: &str) {
+        if self.written_bytes + line.len() as u64 + 1 > *GAME_SERVER_LOG_MAX_BYTES {
+            self.rotate();
+        }
+        if writeln!(self.file, "{}", line).is_ok() {
+            self.written_bytes += line.len() as u64 + 1;
+        }
+    }
+
+    fn rotate(&mut self) {
+        let rotated_path =
+            |index: usize| PathBuf::from(format!("{}.{}", self.path.display(), index));
+        for index in (1..*GAME_SERVER_LOG_FILES).rev() {
+            let _ = fs::rename(rotated_path(index), rotated_path(index + 1));
+        }
+        let _ = fs::rename(&self.path, rotated_path(1));
+        if let Ok(file) = File::create(&self.path) {
+            self.file = file;
+            self.written_bytes = 0;
+        }
+    }
+}
+
+fn get_log_key(server_id: &String) -> String {
+    return format!("match:{}:log", server_id);
+}
+
+fn get_log_followers_key(server_id: &String) -> String {
+    return format!("match:{}:log_followers", server_id);
+}
+
+fn get_followed_logs_key(username: &String) -> String {
+    return format!("user:{}:log_follows", username);
+}
+
+impl GameServerLogs {
+    pub fn new(in_redis_conn: MultiplexedConnection) -> Self {
+        Self {
+            buffers: Arc::new(Mutex::new(HashMap::new())),
+            pending_lines: Arc::new(Mutex::new(HashMap::new())),
+            redis_conn: in_redis_conn,
+        }
+    }
+
+    //Takes the piped stdout/stderr of a spawned server. They have to be drained anyway or the server blocks on a full pipe
+    pub fn capture_output(&self, server_id: &String, game_server_process: &mut Child) {
+        let mut streams: Vec<(&'static str, Box<dyn Read + Send>)> = Vec::new();
+        if let Some(stdout) = game_server_process.stdout.take() {
+            streams.push(("", Box::new(stdout)));
+        }
+        if let Some(stderr) = game_server_process.stderr.take() {
+            streams.push(("[stderr] ", Box::new(stderr)));
+        }
+        if streams.is_empty() {
+            return;
+        }
+        let started_secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or(0);
+        let log_path = PathBuf::from(GAME_SERVER_LOG_DIR.as_str())
+            .join(format!("{}-{}.log", server_id, started_secs));
+        let log_file = match RotatingLogFile::open(log_path) {
+            Ok(log_file) => Some(Arc::new(Mutex::new(log_file))),
+            Err(err) => {
+                eprintln!(
+                    "Can't open log file of game server {:?}: {}",
+                    server_id, err
+                );
+                None
+            }
+        };
+        {
+            let mut buffers = self.buffers.lock().unwrap();
+            let buffer = buffers
+                .entry(server_id.clone())
+                .or_insert_with(|| ServerLogBuffer {
+                    lines: VecDeque::new(),
+                    open_streams: 0,
+                });
+            buffer.open_streams += streams.len();
+        }
+        for (line_prefix, stream) in streams {
+            let game_server_logs = self.clone();
+            let server_id = server_id.clone();
+            let log_file = log_file.clone();
+            thread::spawn(move || {
+                for line in BufReader::new(stream).lines() {
+                    let Ok(line) = line else {
+                        break;
+                    };
+                    let line = format!("{}{}", line_prefix, line);
+                    if let Some(log_file) = &log_file {
+                        log_file.lock().unwrap().write_line(&line);
+                    }
+                    game_server_logs.push_line(&server_id, line);
+                }
+                game_server_logs.close_stream(&server_id);
+            });
+        }
+    }
+
+    fn push_line(&self, server_id: &String, line: String) {
+        {
+            let mut buffers = self.buffers.lock().unwrap();
+            let Some(buffer) = buffers.get_mut(server_id) else {
+                return;
+            };
+            buffer.lines.push_back(line.clone());
+            while buffer.lines.len() > *GAME_SERVER_LOG_LINES {
+                buffer.lines.pop_front();
+            }
+        }
+        let mut pending_lines = self.pending_lines.lock().unwrap();
+        pending_lines
+            .entry(server_id.clone())
+            .or_default()
+            .push(line);
+    }
+
+    fn close_stream(&self, server_id: &String) {
+        let mut buffers = self.buffers.lock().unwrap();
+        if let Some(buffer) = buffers.get_mut(server_id) {
+            buffer.open_streams = buffer.open_streams.saturating_sub(1);
+            if buffer.open_streams == 0 {
+                buffers.remove(server_id);
+            }
+        }
+    }
+
+    //Copies the captured lines to Redis in batches and sends them to the following admins,
+    //also deletes the log files older than GAME_SERVER_LOG_RETENTION_SECS
+    pub async fn run_log_flusher(self) {
+        let mut flush_interval =
+            tokio::time::interval(Duration::from_millis(LOG_FLUSH_INTERVAL_MILLIS));
+        let mut prune_interval =
+            tokio::time::interval(Duration::from_secs(LOG_PRUNE_INTERVAL_SECS));
+        loop {
+            tokio::select! {
+                _ = flush_interval.tick() => self.flush_pending_lines().await,
+                _ = prune_interval.tick() => {
+                    let _ = tokio::task::spawn_blocking(prune_log_files).await;
+                }
+            }
+        }
+    }
+
+    async fn flush_pending_lines(&self) {
+        let pending_lines = std::mem::take(&mut *self.pending_lines.lock().unwrap());
+        let mut redis_conn = self.redis_conn.clone();
+        for (server_id, lines) in pending_lines {
+            let log_key = get_log_key(&server_id);
+            let mut pipe = redis::pipe();
+            pipe.rpush(&log_key, &lines)
+                .ignore()
+                .ltrim(&log_key, -(*GAME_SERVER_LOG_LINES as isize), -1)
+                .ignore()
+                .expire(&log_key, LOG_TAIL_TTL_SECS)
+                .ignore()
+                .smembers(get_log_followers_key(&server_id));
+            let Ok((followers,)) = pipe
+                .query_async::<(HashSet<String>,)>(&mut redis_conn)
+                .await
+            else {
+                continue;
+            };
+            if followers.is_empty() {
+                continue;
+            }
+            let data_to_admin = json!({
+                "resource": "game_server_log",
+                "action": "lines",
+                "payload": {
+                    "server_id": server_id,
+                    "lines": lines
+                }
+            });
+            for follower in followers.iter() {
+                let pub_sub_data_json = json!({
+                    "username": follower,
+                    "data": data_to_admin
+                });
+                let _ = AsyncCommands::publish::<_, _, ()>(
+                    &mut redis_conn,
+                    "web_socket_events",
+                    pub_sub_data_json.to_string(),
+                )
+                .await;
+            }
+        }
+    }
+
+    //Last lines of a server, from memory while it runs here, then from Redis, otherwise from its latest log file on this machine
+    pub async fn tail(&self, server_id: &String, line_num: usize) -> Option<Vec<String>> {
+        {
+            let buffers = self.buffers.lock().unwrap();
+            if let Some(buffer) = buffers.get(server_id) {
+                let skip_num = buffer.lines.len().saturating_sub(line_num);
+                return Some(buffer.lines.iter().skip(skip_num).cloned().collect());
+            }
+        }
+        let mut redis_conn = self.redis_conn.clone();
+        if line_num > 0
+            && let Ok(lines) = AsyncCommands::lrange::<_, Vec<String>>(
+                &mut redis_conn,
+                get_log_key(server_id),
+                -(line_num.min(isize::MAX as usize) as isize),
+                -1,
+            )
+            .await
+            && !lines.is_empty()
+        {
+            return Some(lines);
+        }
+        let file_prefix = format!("{}-", server_id);
+        let latest_log_path = fs::read_dir(GAME_SERVER_LOG_DIR.as_str())
+            .ok()?
+            .filter_map(|dir_entry| dir_entry.ok())
+            .map(|dir_entry| dir_entry.path())
+            .filter(|log_path| {
+                log_path
+                    .file_name()
+                    .and_then(|file_name| file_name.to_str())
+                    .is_some_and(|file_name| {
+                        file_name.starts_with(&file_prefix) && file_name.ends_with(".log")
+                    })
+            })
+            .max()?;
+        let log_content = fs::read_to_string(latest_log_path).ok()?;
+        let lines: Vec<&str> = log_content.lines().collect();
+        let skip_num = lines.len().saturating_sub(line_num);
+        return Some(
+            lines
+                .iter()
+                .skip(skip_num)
+                .map(|line| line.to_string())
+                .collect(),
+        );
+    }
+
+    //Followers are kept in Redis so lines of servers on host agents or other backends reach them too
+    pub async fn follow(&self, server_id: &String, username: &String) -> bool {
+        let mut redis_conn = self.redis_conn.clone();
+        let mut pipe = redis::pipe();
+        pipe.atomic()
+            .sadd(get_log_followers_key(server_id), username)
+            .ignore()
+            .expire(get_log_followers_key(server_id), LOG_TAIL_TTL_SECS)
+            .ignore()
+            .sadd(get_followed_logs_key(username), server_id)
+            .ignore()
+            .expire(get_followed_logs_key(username), LOG_TAIL_TTL_SECS)
+            .ignore();
+        return pipe.query_async::<()>(&mut redis_conn).await.is_ok();
+    }
+
+    pub async fn unfollow(&self, server_id: &String, username: &String) -> bool {
+        let mut redis_conn = self.redis_conn.clone();
+        let mut pipe = redis::pipe();
+        pipe.atomic()
+            .srem(get_log_followers_key(server_id), username)
+            .ignore()
+            .srem(get_followed_logs_key(username), server_id)
+            .ignore();
+        return pipe.query_async::<()>(&mut redis_conn).await.is_ok();
+    }
+
+    //Called when the admin's WebSocket closes
+    pub async fn unfollow_all(&self, username: &String) {
+        let mut redis_conn = self.redis_conn.clone();
+        let Ok(server_ids) = AsyncCommands::smembers::<_, HashSet<String>>(
+            &mut redis_conn,
+            get_followed_logs_key(username),
+        )
+        .await
+        else {
+            return;
+        };
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        for server_id in server_ids.iter() {
+            pipe.srem(get_log_followers_key(server_id), username)
+                .ignore();
+        }
+        pipe.del(get_followed_logs_key(username)).ignore();
+        let _ = pipe.query_async::<()>(&mut redis_conn).await;
+    }
+}
+
+//Deletes the log files (rotated ones included) not written to for GAME_SERVER_LOG_RETENTION_SECS
+fn prune_log_files() {
+    let Ok(dir_entries) = fs::read_dir(GAME_SERVER_LOG_DIR.as_str()) else {
+        return;
+    };
+    let retention = Duration::from_secs(*GAME_SERVER_LOG_RETENTION_SECS);
+    for dir_entry in dir_entries.filter_map(|dir_entry| dir_entry.ok()) {
+        let log_path = dir_entry.path();
+        let is_log_file = log_path
+            .file_name()
+            .and_then(|file_name| file_name.to_str())
+            .is_some_and(|file_name| file_name.contains(".log"));
+        let is_expired = dir_entry
+            .metadata()
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .and_then(|modified| modified.elapsed().ok())
+            .is_some_and(|age| age > retention);
+        if is_log_file && is_expired {
+            let _ = fs::remove_file(&log_path);
+        }
+    }
+}
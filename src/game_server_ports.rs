@@ -0,0 +1,171 @@
+use std::{
+    collections::HashMap,
+    net::{TcpListener, UdpSocket},
+};
+
+use crate::global_vars::{
+    GAME_SERVER_PORT_RANGE_END, GAME_SERVER_PORT_RANGE_START, GAME_SERVER_PUBLIC_HOST,
+    GAME_SERVER_PUBLIC_PORT_MAP, GAME_SERVER_PUBLIC_PORT_OFFSET,
+};
+
+//Ports of the configured range leased to game servers, a lease lasts until its server exits or gets dropped
+pub struct GameServerPortPool {
+    pub range_start: u16,
+    pub range_end: u16,
+    //port - server id
+    leases: HashMap<u16, String>,
+}
+
+impl GameServerPortPool {
+    pub fn new(in_range_start: u16, in_range_end: u16) -> Self {
+        Self {
+            range_start: in_range_start,
+            range_end: in_range_end,
+            leases: HashMap::new(),
+        }
+    }
+
+    pub fn from_env() -> Self {
+        return Self::new(*GAME_SERVER_PORT_RANGE_START, *GAME_SERVER_PORT_RANGE_END);
+    }
+
+    //Leases the first free port of the range, None when the pool is exhausted
+    pub fn lease(&mut self, server_id: &String) -> Option<u16> {
+        if let Some(port) = self.leased_port(server_id) {
+            return Some(port);
+        }
+        for port in self.range_start..=self.range_end {
+            if self.leases.contains_key(&port) {
+                continue;
+            }
+            //Skip ports taken by something outside the pool, e.g. a server left over from a previous run
+            if !is_port_free(port) {
+                println!("Game server port {} is already in use, skipping", port);
+                continue;
+            }
+            self.leases.insert(port, server_id.clone());
+            return Some(port);
+        }
+        return None;
+    }
+
+    pub fn release(&mut self, server_id: &String) {
+        self.leases
+            .retain(|_, lease_server_id| lease_server_id != server_id);
+    }
+
+    pub fn leased_port(&self, server_id: &String) -> Option<u16> {
+        return self
+            .leases
+            .iter()
+            .find(|(_, lease_server_id)| *lease_server_id == server_id)
+            .map(|(port, _)| *port);
+    }
+}
+
+//Game servers listen on UDP, the port is also checked on TCP for beacons and tooling
+fn is_port_free(port: u16) -> bool {
+    return UdpSocket::bind(("0.0.0.0", port)).is_ok()
+        && TcpListener::bind(("0.0.0.0", port)).is_ok();
+}
+
+//Address sent to players for a server listening on a local port, behind NAT the public port can differ
+pub fn advertised_address(port: u16) -> String {
+    return build_advertised_address(
+        port,
+        GAME_SERVER_PUBLIC_HOST.as_str(),
+        *GAME_SERVER_PUBLIC_PORT_OFFSET,
+        &GAME_SERVER_PUBLIC_PORT_MAP,
+    );
+}
+
+fn build_advertised_address(
+    port: u16,
+    public_host: &str,
+    public_port_offset: i32,
+    public_port_map: &HashMap<u16, u16>,
+) -> String {
+    let public_port = match public_port_map.get(&port) {
+        Some(mapped_port) => *mapped_port,
+        None => u16::try_from(port as i32 + public_port_offset).unwrap_or(port),
+    };
+    //IPv6 literals need brackets to be followed by a port
+    if public_host.contains(':') && !public_host.starts_with('[') {
+        return format!("[{}]:{}", public_host, public_port);
+    }
+    return format!("{}:{}", public_host, public_port);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //Ports that are busy on the test machine get skipped, so only the pool's bookkeeping is checked
+    #[test]
+    fn leases_and_releases_ports() {
+        let mut port_pool = GameServerPortPool::new(47611, 47640);
+        let first_server = "match_first".to_string();
+        let second_server = "match_second".to_string();
+        let first_port = port_pool.lease(&first_server).unwrap();
+        let second_port = port_pool.lease(&second_server).unwrap();
+        assert_ne!(first_port, second_port);
+        assert!((47611..=47640).contains(&first_port));
+        assert_eq!(port_pool.lease(&first_server), Some(first_port));
+        assert_eq!(port_pool.leased_port(&second_server), Some(second_port));
+
+        port_pool.release(&first_server);
+        assert_eq!(port_pool.leased_port(&first_server), None);
+        assert_eq!(port_pool.leased_port(&second_server), Some(second_port));
+    }
+
+    #[test]
+    fn returns_none_when_exhausted() {
+        let mut port_pool = GameServerPortPool::new(47651, 47651);
+        let Some(port) = port_pool.lease(&"match_first".to_string()) else {
+            return;
+        };
+        assert_eq!(port, 47651);
+        assert_eq!(port_pool.lease(&"match_second".to_string()), None);
+        port_pool.release(&"match_first".to_string());
+        assert_eq!(port_pool.lease(&"match_second".to_string()), Some(47651));
+    }
+
+    #[test]
+    fn advertises_the_public_port() {
+        let port_map = HashMap::from([(7777, 27015)]);
+        assert_eq!(
+            build_advertised_address(7777, "1.2.3.4", 0, &HashMap::new()),
+            "1.2.3.4:7777"
+        );
+        assert_eq!(
+            build_advertised_address(7777, "1.2.3.4", 100, &HashMap::new()),
+            "1.2.3.4:7877"
+        );
+        //The explicit map wins over the offset
+        assert_eq!(
+            build_advertised_address(7777, "1.2.3.4", 100, &port_map),
+            "1.2.3.4:27015"
+        );
+        //An offset out of the port range is ignored
+        assert_eq!(
+            build_advertised_address(65535, "1.2.3.4", 1, &HashMap::new()),
+            "1.2.3.4:65535"
+        );
+    }
+
+    #[test]
+    fn brackets_ipv6_hosts() {
+        assert_eq!(
+            build_advertised_address(7777, "2001:db8::1", 0, &HashMap::new()),
+            "[2001:db8::1]:7777"
+        );
+        assert_eq!(
+            build_advertised_address(7777, "[2001:db8::1]", 0, &HashMap::new()),
+            "[2001:db8::1]:7777"
+        );
+        assert_eq!(
+            build_advertised_address(7777, "game.example.com", 0, &HashMap::new()),
+            "game.example.com:7777"
+        );
+    }
+}
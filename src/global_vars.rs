@@ -1,12 +1,356 @@
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     sync::{Arc, LazyLock, OnceLock},
 };
 
 use regex::Regex;
 
+use crate::models::catalog::GameCatalog;
+
 //Global variables
 pub static USERNAME_REGEX: LazyLock<Regex> =
     LazyLock::new(|| Regex::new("^[a-zA-Z0-9@]{1,12}$").expect("Invalid regex !"));
 
 pub static SECRET_KEY: OnceLock<String> = OnceLock::new();
+
+pub static JOIN_CODE_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new("^[A-Z0-9]{8}$").expect("Invalid regex !"));
+
+//Base url used to build shareable links, e.g. lobby invite links
+pub static PUBLIC_API_URL: LazyLock<String> = LazyLock::new(|| {
+    std::env::var("PUBLIC_API_URL").unwrap_or("http://127.0.0.1:3000".to_string())
+});
+
+//Percentage of lobby members that must be ready before a match can start (100 = everybody)
+pub static READY_CHECK_QUORUM: LazyLock<usize> = LazyLock::new(|| {
+    std::env::var("READY_CHECK_QUORUM")
+        .ok()
+        .and_then(|value| value.parse::<usize>().ok())
+        .filter(|value| (1..=100).contains(value))
+        .unwrap_or(100)
+});
+
+//Number of players a matchmade match needs to start, and the most it can hold
+pub static MATCHMAKING_MIN_PLAYERS: LazyLock<usize> = LazyLock::new(|| {
+    std::env::var("MATCHMAKING_MIN_PLAYERS")
+        .ok()
+        .and_then(|value| value.parse::<usize>().ok())
+        .unwrap_or(2)
+});
+
+pub static MATCHMAKING_MAX_PLAYERS: LazyLock<usize> = LazyLock::new(|| {
+    std::env::var("MATCHMAKING_MAX_PLAYERS")
+        .ok()
+        .and_then(|value| value.parse::<usize>().ok())
+        .unwrap_or(10)
+});
+
+//How long a group that has enough players waits for more before its match starts anyway
+pub static MATCHMAKING_FILL_WAIT_SECS: LazyLock<u64> = LazyLock::new(|| {
+    std::env::var("MATCHMAKING_FILL_WAIT_SECS")
+        .ok()
+        .and_then(|value| value.parse::<u64>().ok())
+        .unwrap_or(30)
+});
+
+//Skill based matchmaking: lobbies match when their rating gap fits in the band, which starts at
+//RATING_BAND_BASE and grows by RATING_BAND_WIDEN_PER_SEC every second spent in queue up to RATING_BAND_MAX
+pub static RATING_BAND_BASE: LazyLock<f64> = LazyLock::new(|| {
+    std::env::var("RATING_BAND_BASE")
+        .ok()
+        .and_then(|value| value.parse::<f64>().ok())
+        .unwrap_or(100.0)
+});
+
+pub static RATING_BAND_WIDEN_PER_SEC: LazyLock<f64> = LazyLock::new(|| {
+    std::env::var("RATING_BAND_WIDEN_PER_SEC")
+        .ok()
+        .and_then(|value| value.parse::<f64>().ok())
+        .unwrap_or(10.0)
+});
+
+pub static RATING_BAND_MAX: LazyLock<f64> = LazyLock::new(|| {
+    std::env::var("RATING_BAND_MAX")
+        .ok()
+        .and_then(|value| value.parse::<f64>().ok())
+        .unwrap_or(1000.0)
+});
+
+//How long a disconnected lobby leader has to reconnect before the lobby is handed over
+pub static LEADER_HANDOVER_GRACE_SECS: LazyLock<u64> = LazyLock::new(|| {
+    std::env::var("LEADER_HANDOVER_GRACE_SECS")
+        .ok()
+        .and_then(|value| value.parse::<u64>().ok())
+        .unwrap_or(30)
+});
+
+//Number of lobby chat messages kept for members that join or reconnect
+pub static LOBBY_CHAT_HISTORY_LEN: LazyLock<usize> = LazyLock::new(|| {
+    std::env::var("LOBBY_CHAT_HISTORY_LEN")
+        .ok()
+        .and_then(|value| value.parse::<usize>().ok())
+        .filter(|value| *value > 0)
+        .unwrap_or(50)
+});
+
+//How long a kicked player stays banned from the lobby
+pub static LOBBY_BAN_SECS: LazyLock<u64> = LazyLock::new(|| {
+    std::env::var("LOBBY_BAN_SECS")
+        .ok()
+        .and_then(|value| value.parse::<u64>().ok())
+        .unwrap_or(600)
+});
+
+pub static VOTE_KICK_TIMEOUT_SECS: LazyLock<u64> = LazyLock::new(|| {
+    std::env::var("VOTE_KICK_TIMEOUT_SECS")
+        .ok()
+        .and_then(|value| value.parse::<u64>().ok())
+        .filter(|value| *value > 0)
+        .unwrap_or(30)
+});
+
+//Spectator slots of a lobby, on top of its player slots
+pub static LOBBY_SPECTATOR_LIMIT: LazyLock<usize> = LazyLock::new(|| {
+    std::env::var("LOBBY_SPECTATOR_LIMIT")
+        .ok()
+        .and_then(|value| value.parse::<usize>().ok())
+        .unwrap_or(5)
+});
+
+//Characters, classes, items, maps and game modes players can pick, read once from GAME_CATALOG_PATH
+pub static GAME_CATALOG: LazyLock<GameCatalog> = LazyLock::new(|| {
+    let catalog_path =
+        std::env::var("GAME_CATALOG_PATH").unwrap_or("config/game_catalog.json".to_string());
+    let catalog_str = std::fs::read_to_string(&catalog_path).expect("Error reading game catalog !");
+    let catalog =
+        serde_json::from_str::<GameCatalog>(&catalog_str).expect("Invalid game catalog !");
+    if catalog.default_loadout().is_none() || catalog.default_match_settings().is_none() {
+        panic!("Game catalog needs at least one character, game mode and map !");
+    }
+    catalog
+});
+
+//How long a lobby is kept once none of its members is online, so a party survives a quick client restart
+pub static LOBBY_OFFLINE_GRACE_SECS: LazyLock<i64> = LazyLock::new(|| {
+    std::env::var("LOBBY_OFFLINE_GRACE_SECS")
+        .ok()
+        .and_then(|value| value.parse::<i64>().ok())
+        .filter(|value| *value >= 0)
+        .unwrap_or(300)
+});
+
+//"native" runs GAME_SERVER_EXECUTABLE, "mock" runs the bundled fake_game_server binary
+pub static GAME_SERVER_LAUNCHER: LazyLock<String> =
+    LazyLock::new(|| std::env::var("GAME_SERVER_LAUNCHER").unwrap_or("native".to_string()));
+
+pub static GAME_SERVER_EXECUTABLE: LazyLock<String> = LazyLock::new(|| {
+    std::env::var("GAME_SERVER_EXECUTABLE")
+        .unwrap_or(r"D:\GameBuilds\WindowsServer\BeatHimUpServer.exe".to_string())
+});
+
+//Whitespace separated, {level}, {port}, {game_mode}, {server_id}, {roster_url}, {ready_url}, {heartbeat_url} and {ready_token} are filled in per match
+pub static GAME_SERVER_ARGS: LazyLock<String> = LazyLock::new(|| {
+    std::env::var("GAME_SERVER_ARGS").unwrap_or(
+        "{level}?port={port}?game_mode={game_mode} -nopause -log -server_id={server_id} -roster_url={roster_url} -ready_url={ready_url} -heartbeat_url={heartbeat_url} -ready_token={ready_token}"
+            .to_string(),
+    )
+});
+
+pub static GAME_SERVER_WORKING_DIR: LazyLock<Option<String>> =
+    LazyLock::new(|| std::env::var("GAME_SERVER_WORKING_DIR").ok());
+
+//Inclusive port range handed out to game servers, open these ports in the firewall
+pub static GAME_SERVER_PORT_RANGE_START: LazyLock<u16> = LazyLock::new(|| {
+    let Ok(value) = std::env::var("GAME_SERVER_PORT_RANGE_START") else {
+        return 7777;
+    };
+    return value.parse::<u16>().unwrap_or_else(|_| {
+        eprintln!(
+            "Invalid GAME_SERVER_PORT_RANGE_START {:?}, using 7777",
+            value
+        );
+        7777
+    });
+});
+
+//Defaults to 100 ports, capped at 65535
+pub static GAME_SERVER_PORT_RANGE_END: LazyLock<u16> = LazyLock::new(|| {
+    let default_end = GAME_SERVER_PORT_RANGE_START.saturating_add(99);
+    let Ok(value) = std::env::var("GAME_SERVER_PORT_RANGE_END") else {
+        return default_end;
+    };
+    match value.parse::<u16>() {
+        Ok(range_end) if range_end >= *GAME_SERVER_PORT_RANGE_START => return range_end,
+        _ => {
+            eprintln!(
+                "Invalid GAME_SERVER_PORT_RANGE_END {:?} for range start {}, using {}",
+                value, *GAME_SERVER_PORT_RANGE_START, default_end
+            );
+            return default_end;
+        }
+    }
+});
+
+//Host players connect to, the public IP or DNS name of this machine
+pub static GAME_SERVER_PUBLIC_HOST: LazyLock<String> =
+    LazyLock::new(|| std::env::var("GAME_SERVER_PUBLIC_HOST").unwrap_or("127.0.0.1".to_string()));
+
+//Added to the local port when the router forwards a shifted port range
+pub static GAME_SERVER_PUBLIC_PORT_OFFSET: LazyLock<i32> = LazyLock::new(|| {
+    std::env::var("GAME_SERVER_PUBLIC_PORT_OFFSET")
+        .ok()
+        .and_then(|value| value.parse::<i32>().ok())
+        .unwrap_or(0)
+});
+
+//Explicit local:public port forwards, e.g. "7777:27015,7778:27016", checked before the offset
+pub static GAME_SERVER_PUBLIC_PORT_MAP: LazyLock<HashMap<u16, u16>> = LazyLock::new(|| {
+    std::env::var("GAME_SERVER_PUBLIC_PORT_MAP")
+        .unwrap_or_default()
+        .split(',')
+        .filter_map(|port_pair| {
+            let (local_port, public_port) = port_pair.trim().split_once(':')?;
+            Some((
+                local_port.parse::<u16>().ok()?,
+                public_port.parse::<u16>().ok()?,
+            ))
+        })
+        .collect()
+});
+
+//How long a launched server has to load its map and call /game_server/ready before it gets killed
+pub static GAME_SERVER_STARTUP_TIMEOUT_SECS: LazyLock<u64> = LazyLock::new(|| {
+    std::env::var("GAME_SERVER_STARTUP_TIMEOUT_SECS")
+        .ok()
+        .and_then(|value| value.parse::<u64>().ok())
+        .filter(|value| *value > 0)
+        .unwrap_or(120)
+});
+
+//A match waiting for game server capacity longer than this is given up and its lobbies go back to Ready
+pub static GAME_SERVER_QUEUE_TIMEOUT_SECS: LazyLock<i64> = LazyLock::new(|| {
+    std::env::var("GAME_SERVER_QUEUE_TIMEOUT_SECS")
+        .ok()
+        .and_then(|value| value.parse::<i64>().ok())
+        .filter(|value| *value > 0)
+        .unwrap_or(300)
+});
+
+//A running server has to send a heartbeat within this window or the watchdog kills it
+pub static GAME_SERVER_HEARTBEAT_TIMEOUT_SECS: LazyLock<i64> = LazyLock::new(|| {
+    std::env::var("GAME_SERVER_HEARTBEAT_TIMEOUT_SECS")
+        .ok()
+        .and_then(|value| value.parse::<i64>().ok())
+        .filter(|value| *value > 0)
+        .unwrap_or(30)
+});
+
+//Shared by the backend and its host agents to sign the commands and events they exchange.
+//Host agent machines hold it, so it must never be the SECRET_KEY that signs user tokens.
+//Without it the backend runs every game server itself
+pub static HOST_AGENT_SECRET: LazyLock<Option<String>> = LazyLock::new(|| {
+    let host_agent_secret = std::env::var("HOST_AGENT_SECRET").ok()?;
+    if std::env::var("SECRET_KEY").is_ok_and(|secret_key| secret_key == host_agent_secret) {
+        panic!("HOST_AGENT_SECRET must be different from SECRET_KEY !");
+    }
+    Some(host_agent_secret)
+});
+
+//Name of this backend instance, owner of the game servers it runs itself. Must be unique across instances
+pub static BACKEND_INSTANCE_ID: LazyLock<String> = LazyLock::new(|| {
+    std::env::var("BACKEND_INSTANCE_ID").unwrap_or(format!(
+        "backend_{}",
+        crate::auth::generate_random_code(8).to_lowercase()
+    ))
+});
+
+//Name a host agent registers under, must be unique in the fleet
+pub static HOST_AGENT_ID: LazyLock<String> = LazyLock::new(|| {
+    std::env::var("HOST_AGENT_ID").unwrap_or(format!(
+        "host_{}",
+        crate::auth::generate_random_code(8).to_lowercase()
+    ))
+});
+
+//Game servers a host agent runs at most at once
+pub static HOST_AGENT_CAPACITY: LazyLock<usize> = LazyLock::new(|| {
+    std::env::var("HOST_AGENT_CAPACITY")
+        .ok()
+        .and_then(|value| value.parse::<usize>().ok())
+        .filter(|value| *value > 0)
+        .unwrap_or(4)
+});
+
+//Idle game servers kept started so a match doesn't wait for a cold start, 0 disables the pool
+pub static GAME_SERVER_WARM_POOL_SIZE: LazyLock<usize> = LazyLock::new(|| {
+    std::env::var("GAME_SERVER_WARM_POOL_SIZE")
+        .ok()
+        .and_then(|value| value.parse::<usize>().ok())
+        .unwrap_or(0)
+});
+
+//Whether a warm server goes back to the pool after its match instead of being stopped
+pub static GAME_SERVER_REUSE: LazyLock<bool> = LazyLock::new(|| {
+    std::env::var("GAME_SERVER_REUSE")
+        .map(|value| value == "true")
+        .unwrap_or(false)
+});
+
+//Game servers this backend runs itself at once, warm ones included. Host agents have HOST_AGENT_CAPACITY
+pub static GAME_SERVER_LOCAL_CAPACITY: LazyLock<usize> = LazyLock::new(|| {
+    std::env::var("GAME_SERVER_LOCAL_CAPACITY")
+        .ok()
+        .and_then(|value| value.parse::<usize>().ok())
+        .filter(|value| *value > 0)
+        .unwrap_or(4)
+});
+
+//Usernames allowed to use the /admin routes, comma separated
+pub static ADMIN_USERS: LazyLock<HashSet<String>> = LazyLock::new(|| {
+    std::env::var("ADMIN_USERS")
+        .unwrap_or_default()
+        .split(',')
+        .map(|username| username.trim().to_string())
+        .filter(|username| !username.is_empty())
+        .collect()
+});
+
+//Directory of the game server log files, one per server start
+pub static GAME_SERVER_LOG_DIR: LazyLock<String> = LazyLock::new(|| {
+    std::env::var("GAME_SERVER_LOG_DIR").unwrap_or("logs/game_servers".to_string())
+});
+
+//Last lines of each running game server kept in memory for the admin endpoint
+pub static GAME_SERVER_LOG_LINES: LazyLock<usize> = LazyLock::new(|| {
+    std::env::var("GAME_SERVER_LOG_LINES")
+        .ok()
+        .and_then(|value| value.parse::<usize>().ok())
+        .unwrap_or(500)
+});
+
+//Size at which a game server log file is rotated
+pub static GAME_SERVER_LOG_MAX_BYTES: LazyLock<u64> = LazyLock::new(|| {
+    std::env::var("GAME_SERVER_LOG_MAX_BYTES")
+        .ok()
+        .and_then(|value| value.parse::<u64>().ok())
+        .filter(|value| *value > 0)
+        .unwrap_or(10 * 1024 * 1024)
+});
+
+//Rotated files kept per log file: {file}.1 up to {file}.{GAME_SERVER_LOG_FILES}
+pub static GAME_SERVER_LOG_FILES: LazyLock<usize> = LazyLock::new(|| {
+    std::env::var("GAME_SERVER_LOG_FILES")
+        .ok()
+        .and_then(|value| value.parse::<usize>().ok())
+        .filter(|value| *value > 0)
+        .unwrap_or(3)
+});
+
+//Game server log files older than this are deleted
+pub static GAME_SERVER_LOG_RETENTION_SECS: LazyLock<u64> = LazyLock::new(|| {
+    std::env::var("GAME_SERVER_LOG_RETENTION_SECS")
+        .ok()
+        .and_then(|value| value.parse::<u64>().ok())
+        .filter(|value| *value > 0)
+        .unwrap_or(7 * 24 * 3600)
+});
@@ -0,0 +1,256 @@
+//Host agent: `dotg_rust host-agent` registers a machine's capacity in Redis, receives signed launch/stop
+//commands from the backend and runs the game servers with the configured launcher.
+//Several agents can run on one machine for local testing, each with its own HOST_AGENT_ID and
+//GAME_SERVER_PORT_RANGE_START/END, e.g. with GAME_SERVER_LAUNCHER=mock
+use futures_util::StreamExt;
+use redis::{AsyncCommands, aio::MultiplexedConnection};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, process::Child, time::Duration};
+
+use crate::{
+    auth::{sign_host_agent_message, verify_host_agent_message},
+    game_server_launcher::{self, GameServerLauncher, GameServerStatus, LaunchRequest},
+    game_server_logs::GameServerLogs,
+    game_server_ports::{self, GameServerPortPool},
+    global_vars::{HOST_AGENT_CAPACITY, HOST_AGENT_ID, HOST_AGENT_SECRET},
+};
+
+//Channel the backend publishes commands for one host on: host_agent:{host_id}
+pub const HOST_AGENT_COMMAND_CHANNEL_PREFIX: &str = "host_agent";
+//Channel every host agent publishes its events on
+pub const HOST_AGENT_EVENT_CHANNEL: &str = "host_agent_events";
+//Set of the registered host ids, each with a host:{host_id} hash that expires when the agent stops reporting
+pub const GAME_SERVER_HOSTS_KEY: &str = "game_server_hosts";
+pub const HOST_AGENT_TTL_SECS: i64 = 15;
+const HOST_AGENT_REPORT_INTERVAL_SECS: u64 = 5;
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum HostAgentCommand {
+    Launch { launch_request: LaunchRequest },
+    Stop { server_id: String },
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum HostAgentEvent {
+    Launched {
+        server_id: String,
+        address: String,
+    },
+    LaunchFailed {
+        server_id: String,
+    },
+    Exited {
+        server_id: String,
+        exit_code: Option<i32>,
+    },
+}
+
+//Game servers run by this agent, only touched from the agent loop
+struct HostAgent {
+    host_id: String,
+    launcher: Box<dyn GameServerLauncher>,
+    //Output of the servers is written to this machine's GAME_SERVER_LOG_DIR and copied to Redis for the backends
+    logs: GameServerLogs,
+    ports: GameServerPortPool,
+    processes: HashMap<String, Child>,
+    redis_conn: MultiplexedConnection,
+}
+
+//Entry point of `dotg_rust host-agent`: registers this machine with the backend and runs the servers it is told to
+pub async fn run_host_agent() {
+    if HOST_AGENT_SECRET.is_none() {
+        panic!("HOST_AGENT_SECRET not found !");
+    }
+    let redis_url = std::env::var("REDIS_URL").expect("REDIS_URL not found !");
+    let client_redis = redis::Client::open(redis_url).expect("Can't connect to Redis");
+    let redis_conn = client_redis
+        .get_multiplexed_async_connection()
+        .await
+        .expect("Error getting Redis connection");
+    let mut pub_sub = client_redis
+        .get_async_pubsub()
+        .await
+        .expect("Error getting Redis pub/sub connection");
+    let command_channel = format!("{}:{}", HOST_AGENT_COMMAND_CHANNEL_PREFIX, *HOST_AGENT_ID);
+    pub_sub
+        .subscribe(&command_channel)
+        .await
+        .expect("Can't subscribe to host agent channel");
+
+    let mut host_agent = HostAgent {
+        host_id: HOST_AGENT_ID.clone(),
+        launcher: game_server_launcher::create_launcher(),
+        logs: GameServerLogs::new(redis_conn.clone()),
+        ports: GameServerPortPool::from_env(),
+        processes: HashMap::new(),
+        redis_conn,
+    };
+    println!(
+        "Host agent {:?} running up to {} servers on ports {}-{}",
+        host_agent.host_id,
+        *HOST_AGENT_CAPACITY,
+        host_agent.ports.range_start,
+        host_agent.ports.range_end
+    );
+
+    tokio::spawn(host_agent.logs.clone().run_log_flusher());
+
+    let mut command_stream = pub_sub.into_on_message();
+    let mut report_interval =
+        tokio::time::interval(Duration::from_secs(HOST_AGENT_REPORT_INTERVAL_SECS));
+    loop {
+        tokio::select! {
+            redis_message = command_stream.next() => {
+                let Some(redis_message) = redis_message else {
+                    eprintln!("Host agent lost its Redis subscription");
+                    break;
+                };
+                if let Ok(token) = redis_message.get_payload::<String>() {
+                    host_agent.handle_command(&token).await;
+                }
+            }
+            _ = report_interval.tick() => {
+                host_agent.reap_exited_servers().await;
+                host_agent.register().await;
+            }
+        }
+    }
+    host_agent.stop_all();
+}
+
+impl HostAgent {
+    async fn handle_command(&mut self, token: &str) {
+        let Some(claims) = verify_host_agent_message::<HostAgentCommand>(token) else {
+            eprintln!("Rejected host agent command with an invalid signature");
+            return;
+        };
+        if claims.host_id != self.host_id {
+            return;
+        }
+        match claims.message {
+            HostAgentCommand::Launch { launch_request } => self.launch(launch_request).await,
+            HostAgentCommand::Stop { server_id } => {
+                //Reported as exited on the next reap
+                if let Some(game_server_process) = self.processes.get_mut(&server_id) {
+                    let _ = self.launcher.stop(game_server_process);
+                }
+            }
+        }
+    }
+
+    async fn launch(&mut self, mut launch_request: LaunchRequest) {
+        let server_id = launch_request.server_id.clone();
+        let port = if self.processes.len() < *HOST_AGENT_CAPACITY {
+            self.ports.lease(&server_id)
+        } else {
+            None
+        };
+        let Some(port) = port else {
+            self.publish_event(HostAgentEvent::LaunchFailed { server_id })
+                .await;
+            return;
+        };
+        launch_request.port = port;
+        //The server passes it on with its ready call
+        let address = game_server_ports::advertised_address(port);
+        launch_request.ready_url = format!(
+            "{}&address={}",
+            launch_request.ready_url,
+            encode_query_value(&address)
+        );
+        match self.launcher.spawn(&launch_request) {
+            Ok(mut game_server_process) => {
+                self.logs
+                    .capture_output(&server_id, &mut game_server_process);
+                self.processes
+                    .insert(server_id.clone(), game_server_process);
+                self.publish_event(HostAgentEvent::Launched { server_id, address })
+                    .await;
+            }
+            Err(err) => {
+                eprintln!("Failed to start game server {:?}: {}", server_id, err);
+                self.ports.release(&server_id);
+                self.publish_event(HostAgentEvent::LaunchFailed { server_id })
+                    .await;
+            }
+        }
+        self.register().await;
+    }
+
+    async fn reap_exited_servers(&mut self) {
+        let mut exited_servers = Vec::new();
+        let launcher = &self.launcher;
+        self.processes.retain(|server_id, game_server_process| {
+            match launcher.status(game_server_process) {
+                GameServerStatus::Exited(exit_code) => {
+                    exited_servers.push((server_id.clone(), exit_code));
+                    return false;
+                }
+                _ => return true,
+            }
+        });
+        for (server_id, exit_code) in exited_servers {
+            self.ports.release(&server_id);
+            self.publish_event(HostAgentEvent::Exited {
+                server_id,
+                exit_code,
+            })
+            .await;
+        }
+    }
+
+    //host:host_a - {capacity: "", running: "", last_seen: ""}, refreshed every report
+    async fn register(&mut self) {
+        let host_key = format!("host:{}", self.host_id);
+        let mut pipe = redis::pipe();
+        pipe.atomic()
+            .hset_multiple(
+                &host_key,
+                &[
+                    ("capacity", HOST_AGENT_CAPACITY.to_string()),
+                    ("running", self.processes.len().to_string()),
+                    (
+                        "last_seen",
+                        std::time::SystemTime::now()
+                            .duration_since(std::time::UNIX_EPOCH)
+                            .unwrap()
+                            .as_secs()
+                            .to_string(),
+                    ),
+                ],
+            )
+            .expire(&host_key, HOST_AGENT_TTL_SECS)
+            .sadd(GAME_SERVER_HOSTS_KEY, &self.host_id);
+        if let Err(err) = pipe.query_async::<()>(&mut self.redis_conn).await {
+            eprintln!("Failed to register host agent: {}", err);
+        }
+    }
+
+    async fn publish_event(&mut self, event: HostAgentEvent) {
+        let Some(token) = sign_host_agent_message(&self.host_id, event) else {
+            return;
+        };
+        let _ = AsyncCommands::publish::<_, _, ()>(
+            &mut self.redis_conn,
+            HOST_AGENT_EVENT_CHANNEL,
+            token,
+        )
+        .await;
+    }
+
+    fn stop_all(&mut self) {
+        for (_, game_server_process) in self.processes.iter_mut() {
+            let _ = self.launcher.stop(game_server_process);
+        }
+    }
+}
+
+//Addresses only hold host name characters, ':' and the brackets of IPv6 hosts
+fn encode_query_value(value: &str) -> String {
+    return value
+        .replace(':', "%3A")
+        .replace('[', "%5B")
+        .replace(']', "%5D");
+}
@@ -1,7 +1,12 @@
 use redis::{
     AsyncConnectionConfig, ConnectionAddr, FromRedisValue, PushInfo, PushKind, RedisConnectionInfo,
 };
-use std::{collections::HashMap, io::Write, process::Child, sync::Arc};
+use std::{
+    collections::HashMap,
+    io::Write,
+    process::Child,
+    sync::{Arc, LazyLock},
+};
 use tokio::sync::{
     RwLock,
     mpsc::{self, UnboundedReceiver},
@@ -10,12 +15,20 @@ use tokio::sync::{
 mod app_state;
 mod auth;
 mod controllers;
+mod game_server_launcher;
+mod game_server_logs;
+mod game_server_ports;
 mod global_vars;
+mod host_agent;
 mod models;
 
 use app_state::{AppState, ClientSender, ClientsMap};
-use controllers::controllers_center;
+use controllers::{
+    allocation_queue_controller, controllers_center, game_server_controller, lobby_controller,
+    matchmaking_controller, warm_pool_controller,
+};
 use dotenvy::dotenv;
+use global_vars::{GAME_CATALOG, HOST_AGENT_SECRET};
 use sqlx::PgPool;
 
 use crate::app_state::GameServerExeMap;
@@ -23,6 +36,16 @@ use crate::app_state::GameServerExeMap;
 #[tokio::main]
 async fn main() -> Result<(), sqlx::Error> {
     dotenv().expect("Error loading .env file");
+    //`dotg_rust host-agent` runs game servers for a backend instead of serving the API
+    if std::env::args().nth(1).as_deref() == Some("host-agent") {
+        host_agent::run_host_agent().await;
+        return Ok(());
+    }
+    //Fail on startup rather than on the first request if the catalog or the host agent secret is invalid
+    LazyLock::force(&GAME_CATALOG);
+    if HOST_AGENT_SECRET.is_none() {
+        println!("HOST_AGENT_SECRET not set, game servers only run on this backend");
+    }
 
     let connection_str = std::env::var("DATABASE_URL").expect("DATABASE_URL not found !");
     let redis_url = std::env::var("REDIS_URL").expect("REDIS_URL not found !");
@@ -43,10 +66,21 @@ async fn main() -> Result<(), sqlx::Error> {
     let game_server_exe_map: GameServerExeMap =
         Arc::new(RwLock::new(HashMap::<String, Child>::new()));
 
+    let game_server_launcher = Arc::from(game_server_launcher::create_launcher());
+
+    let game_server_ports = Arc::new(tokio::sync::Mutex::new(
+        game_server_ports::GameServerPortPool::from_env(),
+    ));
+
+    let game_server_logs = game_server_logs::GameServerLogs::new(redis_conn.clone());
+
     let app_state_ = AppState {
         connection_pool,
         clients_map,
         game_server_exe_map,
+        game_server_launcher,
+        game_server_logs,
+        game_server_ports,
         redis_conn,
     };
 
@@ -56,6 +90,42 @@ async fn main() -> Result<(), sqlx::Error> {
         subcribe_to_channel(redis_app_state, rx).await;
     });
 
+    let game_server_logs_flusher = app_state_.game_server_logs.clone();
+
+    tokio::spawn(async {
+        game_server_logs_flusher.run_log_flusher().await;
+    });
+
+    let matchmaker_app_state = app_state_.clone();
+
+    tokio::spawn(async {
+        matchmaking_controller::run_matchmaker(matchmaker_app_state).await;
+    });
+
+    let lobby_sweeper_app_state = app_state_.clone();
+
+    tokio::spawn(async {
+        lobby_controller::run_lobby_sweeper(lobby_sweeper_app_state).await;
+    });
+
+    let game_server_supervisor_app_state = app_state_.clone();
+
+    tokio::spawn(async {
+        game_server_controller::run_game_server_supervisor(game_server_supervisor_app_state).await;
+    });
+
+    let warm_pool_app_state = app_state_.clone();
+
+    tokio::spawn(async {
+        warm_pool_controller::run_warm_pool(warm_pool_app_state).await;
+    });
+
+    let allocation_queue_app_state = app_state_.clone();
+
+    tokio::spawn(async {
+        allocation_queue_controller::run_allocation_queue(allocation_queue_app_state).await;
+    });
+
     let app_routers = controllers_center::create_app_router().with_state(app_state_);
     // run our app with hyper, listening globally on port 3000
     let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
@@ -66,7 +136,11 @@ async fn main() -> Result<(), sqlx::Error> {
 async fn subcribe_to_channel(app_state_: AppState, mut rx: UnboundedReceiver<PushInfo>) {
     let mut conn = app_state_.redis_conn.clone();
     if let Ok(()) = conn
-        .subscribe(&["web_socket_events", "drop_game_server_event"])
+        .subscribe(&[
+            "web_socket_events",
+            "drop_game_server_event",
+            host_agent::HOST_AGENT_EVENT_CHANNEL,
+        ])
         .await
     {
         loop {
@@ -102,17 +176,22 @@ async fn subcribe_to_channel(app_state_: AppState, mut rx: UnboundedReceiver<Pus
                                 if let Ok(game_server_id) =
                                     String::from_redis_value(redis_message.data[1].clone())
                                 {
-                                    {
-                                        // let mut game_server_exe_map =
-                                        //     app_state_.game_server_exe_map.write().await;
-                                        // if let Some(game_server_proccess) =
-                                        //     game_server_exe_map.get_mut(&game_server_id)
-                                        // {
-                                        //     if let Ok(_) = game_server_proccess.kill() {
-                                        //         game_server_exe_map.remove(&game_server_id);
-                                        //     }
-                                        // }
-                                    }
+                                    game_server_controller::stop_game_server_process(
+                                        &app_state_,
+                                        &game_server_id,
+                                    )
+                                    .await;
+                                }
+                            }
+                            host_agent::HOST_AGENT_EVENT_CHANNEL => {
+                                if let Ok(token) =
+                                    String::from_redis_value(redis_message.data[1].clone())
+                                {
+                                    game_server_controller::handle_host_agent_event(
+                                        &app_state_,
+                                        &token,
+                                    )
+                                    .await;
                                 }
                             }
                             _ => {}
@@ -0,0 +1,117 @@
+use serde::{Deserialize, Serialize};
+
+//Server-side catalog of what players can pick, loaded from the game catalog config file
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct GameCatalog {
+    pub characters: Vec<CharacterEntry>,
+    pub items: Vec<String>,
+    pub max_items: usize,
+    pub maps: Vec<MapEntry>,
+    //The first game mode is the default one
+    pub game_modes: Vec<GameModeEntry>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct CharacterEntry {
+    pub id: String,
+    pub classes: Vec<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct MapEntry {
+    pub id: String,
+    //Level the game server is launched with
+    pub level: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct GameModeEntry {
+    pub id: String,
+    pub min_players: usize,
+    pub max_players: usize,
+    //Ids of the maps the mode can be played on
+    pub maps: Vec<String>,
+}
+
+//Game mode and map picked for a lobby's next match
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct MatchSettings {
+    pub game_mode: String,
+    pub map: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Loadout {
+    pub character: String,
+    pub class: String,
+    #[serde(default)]
+    pub items: Vec<String>,
+}
+
+impl GameCatalog {
+    pub fn validate_loadout(&self, loadout: &Loadout) -> Result<(), &'static str> {
+        let Some(character) = self
+            .characters
+            .iter()
+            .find(|character| character.id == loadout.character)
+        else {
+            return Err("Unknown character !");
+        };
+        if !character.classes.contains(&loadout.class) {
+            return Err("Invalid class for character !");
+        }
+        if loadout.items.len() > self.max_items {
+            return Err("Too many items !");
+        }
+        if loadout.items.iter().any(|item| !self.items.contains(item)) {
+            return Err("Unknown item !");
+        }
+        return Ok(());
+    }
+
+    //Loadout of members that didn't pick one before the match
+    pub fn default_loadout(&self) -> Option<Loadout> {
+        let character = self.characters.first()?;
+        return Some(Loadout {
+            character: character.id.clone(),
+            class: character.classes.first()?.clone(),
+            items: Vec::new(),
+        });
+    }
+
+    pub fn find_game_mode(&self, game_mode_id: &str) -> Option<&GameModeEntry> {
+        return self
+            .game_modes
+            .iter()
+            .find(|game_mode| game_mode.id == game_mode_id);
+    }
+
+    pub fn find_map(&self, map_id: &str) -> Option<&MapEntry> {
+        return self.maps.iter().find(|map| map.id == map_id);
+    }
+
+    pub fn validate_match_settings(
+        &self,
+        match_settings: &MatchSettings,
+    ) -> Result<&GameModeEntry, &'static str> {
+        let Some(game_mode) = self.find_game_mode(&match_settings.game_mode) else {
+            return Err("Unknown game mode !");
+        };
+        if self.find_map(&match_settings.map).is_none() {
+            return Err("Unknown map !");
+        }
+        if !game_mode.maps.contains(&match_settings.map) {
+            return Err("Map not available in game mode !");
+        }
+        return Ok(game_mode);
+    }
+
+    //Settings of lobbies whose leader didn't pick any: default game mode on its first map
+    pub fn default_match_settings(&self) -> Option<MatchSettings> {
+        let game_mode = self.game_modes.first()?;
+        return Some(MatchSettings {
+            game_mode: game_mode.id.clone(),
+            map: game_mode.maps.first()?.clone(),
+        });
+    }
+}
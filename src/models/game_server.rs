@@ -2,16 +2,33 @@ use redis_macros::{FromRedisValue, ToRedisArgs};
 use serde::{Deserialize, Serialize};
 use sqlx::prelude::FromRow;
 
+use crate::models::catalog::MatchSettings;
+
 #[derive(Clone, Debug, Deserialize, Serialize, FromRow, FromRedisValue, ToRedisArgs)]
 pub struct GameServer {
+    //Id passed to the server process, the lobby id for leader started servers
+    #[serde(default)]
+    pub server_id: String,
     pub address: String,
     pub host: String,
 }
 impl GameServer {
-    pub fn new(in_address: &str, in_host: &str) -> Self {
+    pub fn new(in_server_id: &str, in_address: &str, in_host: &str) -> Self {
         Self {
+            server_id: in_server_id.to_string(),
             address: in_address.to_string(),
             host: in_host.to_string(),
         }
     }
 }
+
+//A match waiting in the allocation queue for game server capacity
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct AllocationRequest {
+    pub server_id: String,
+    pub lobby_ids: Vec<String>,
+    //Leader that asked for the server, empty for matchmaking
+    pub host: String,
+    pub match_settings: MatchSettings,
+    pub queued_at: i64,
+}
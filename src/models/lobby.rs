@@ -1,24 +1,154 @@
 use redis_macros::{FromRedisValue, ToRedisArgs};
 use serde::{Deserialize, Serialize};
 use sqlx::prelude::FromRow;
+use std::{fmt, str::FromStr};
+
+//Lifecycle of a lobby: Ready -> In_Queue -> Allocating -> In_Match -> Ready.
+//A leader starting a server skips the queue, and a lobby goes back to Ready when leaving the queue or failing to get a server
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum LobbyStatus {
+    Ready,
+    #[serde(rename = "In_Queue")]
+    Queued,
+    Allocating,
+    #[serde(rename = "In_Match")]
+    InMatch,
+}
+
+impl LobbyStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LobbyStatus::Ready => "Ready",
+            LobbyStatus::Queued => "In_Queue",
+            LobbyStatus::Allocating => "Allocating",
+            LobbyStatus::InMatch => "In_Match",
+        }
+    }
+
+    //Statuses a lobby can move to this status from
+    pub fn allowed_previous(&self) -> &'static [LobbyStatus] {
+        match self {
+            LobbyStatus::Ready => &[
+                LobbyStatus::Queued,
+                LobbyStatus::Allocating,
+                LobbyStatus::InMatch,
+            ],
+            LobbyStatus::Queued => &[LobbyStatus::Ready],
+            LobbyStatus::Allocating => &[LobbyStatus::Ready, LobbyStatus::Queued],
+            LobbyStatus::InMatch => &[LobbyStatus::Allocating],
+        }
+    }
+
+    pub fn can_transition_to(&self, next_status: LobbyStatus) -> bool {
+        return next_status.allowed_previous().contains(self);
+    }
+
+    //Error returned when a lobby in this status is asked for a transition it doesn't allow
+    pub fn transition_error(&self) -> &'static str {
+        match self {
+            LobbyStatus::Ready => "Lobby is not in a queue or match !",
+            LobbyStatus::Queued => "Lobby is in matchmaking queue !",
+            LobbyStatus::Allocating => "Lobby is waiting for a game server !",
+            LobbyStatus::InMatch => "Lobby is in a match !",
+        }
+    }
+}
+
+impl fmt::Display for LobbyStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for LobbyStatus {
+    type Err = ();
+
+    fn from_str(status_str: &str) -> Result<Self, Self::Err> {
+        match status_str {
+            "Ready" => Ok(LobbyStatus::Ready),
+            "In_Queue" => Ok(LobbyStatus::Queued),
+            "Allocating" => Ok(LobbyStatus::Allocating),
+            "In_Match" => Ok(LobbyStatus::InMatch),
+            _ => Err(()),
+        }
+    }
+}
 
 #[derive(Clone, Debug, Deserialize, Serialize, FromRow, FromRedisValue, ToRedisArgs)]
 pub struct LobbyInfo {
     pub lobby_name: String,
     pub leader: String,
     pub limit_num: usize,
-    //Status - Ready | In_Queue | In_Match
-    pub status: String,
+    #[sqlx(try_from = "String")]
+    pub status: LobbyStatus,
+}
+
+impl TryFrom<String> for LobbyStatus {
+    type Error = String;
+
+    fn try_from(status_str: String) -> Result<Self, Self::Error> {
+        return status_str
+            .parse::<LobbyStatus>()
+            .map_err(|_| format!("Invalid lobby status {}", status_str));
+    }
 }
 
 impl LobbyInfo {
-    pub fn new(in_name: &str, in_leader: &str, in_limit_num: usize, in_status: &str) -> Self {
+    pub fn new(
+        in_name: &str,
+        in_leader: &str,
+        in_limit_num: usize,
+        in_status: LobbyStatus,
+    ) -> Self {
         Self {
             lobby_name: in_name.to_string(),
             leader: in_leader.to_string(),
             limit_num: in_limit_num,
-            //Status - Ready | In_Queue | In_Match
-            status: in_status.to_string(),
+            status: in_status,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn follows_the_lobby_lifecycle() {
+        assert!(LobbyStatus::Ready.can_transition_to(LobbyStatus::Queued));
+        assert!(LobbyStatus::Queued.can_transition_to(LobbyStatus::Allocating));
+        assert!(LobbyStatus::Allocating.can_transition_to(LobbyStatus::InMatch));
+        assert!(LobbyStatus::InMatch.can_transition_to(LobbyStatus::Ready));
+        //A leader starting a server skips the queue
+        assert!(LobbyStatus::Ready.can_transition_to(LobbyStatus::Allocating));
+        //Leaving the queue or failing to get a server
+        assert!(LobbyStatus::Queued.can_transition_to(LobbyStatus::Ready));
+        assert!(LobbyStatus::Allocating.can_transition_to(LobbyStatus::Ready));
+    }
+
+    #[test]
+    fn rejects_skipped_steps() {
+        assert!(!LobbyStatus::Ready.can_transition_to(LobbyStatus::InMatch));
+        assert!(!LobbyStatus::Queued.can_transition_to(LobbyStatus::InMatch));
+        assert!(!LobbyStatus::InMatch.can_transition_to(LobbyStatus::Queued));
+        assert!(!LobbyStatus::InMatch.can_transition_to(LobbyStatus::Allocating));
+        assert!(!LobbyStatus::Ready.can_transition_to(LobbyStatus::Ready));
+    }
+
+    #[test]
+    fn parses_its_own_names() {
+        for status in [
+            LobbyStatus::Ready,
+            LobbyStatus::Queued,
+            LobbyStatus::Allocating,
+            LobbyStatus::InMatch,
+        ] {
+            assert_eq!(status.as_str().parse::<LobbyStatus>(), Ok(status));
+            assert_eq!(
+                serde_json::to_string(&status).unwrap(),
+                format!("\"{}\"", status)
+            );
         }
+        assert!("Queued".parse::<LobbyStatus>().is_err());
     }
 }
@@ -0,0 +1,13 @@
+use serde::{Deserialize, Serialize};
+use sqlx::prelude::FromRow;
+
+#[derive(Clone, Debug, Deserialize, Serialize, FromRow)]
+pub struct DirectMessage {
+    pub id: i32,
+    pub sender: String,
+    pub receiver: String,
+    pub content: String,
+    //Unix time in milliseconds
+    pub sent_at: i64,
+    pub is_read: bool,
+}
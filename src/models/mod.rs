@@ -1,5 +1,8 @@
+pub mod catalog;
 pub mod friend;
 pub mod game_server;
 pub mod in_game;
 pub mod lobby;
+pub mod message;
+pub mod rating;
 pub mod user;
@@ -0,0 +1,26 @@
+use serde::{Deserialize, Serialize};
+use sqlx::prelude::FromRow;
+
+#[derive(Clone, Debug, Deserialize, Serialize, FromRow)]
+pub struct PlayerRating {
+    pub username: String,
+    pub rating: f64,
+    pub games_played: i32,
+}
+
+impl PlayerRating {
+    pub fn new(in_username: &str, in_rating: f64, in_games_played: i32) -> Self {
+        Self {
+            username: in_username.to_string(),
+            rating: in_rating,
+            games_played: in_games_played,
+        }
+    }
+}
+
+//Result reported by a game server. Teams are lists of usernames, winning_team indexes teams (None for a draw)
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct MatchResult {
+    pub teams: Vec<Vec<String>>,
+    pub winning_team: Option<usize>,
+}